@@ -0,0 +1,156 @@
+#version 450 core
+
+// Samples along the view ray, and towards each light from every one of those
+#define VIEW_SAMPLES 16
+#define LIGHT_SAMPLES 8
+
+out vec4 FragColor;
+
+layout(location=0) in vec3 frag_pos;
+layout(location=6) in float log_z;
+
+// 1 / log2(far + 1), used when built with LOG_DEPTH
+uniform layout(location=41) float log_depth;
+
+// A copy of the depth drawn so far, and the way from a point on screen, x and
+// y from -1 to 1 and z as read from it, back to the camera relative world
+layout(binding=6) uniform sampler2D scene_depth;
+uniform layout(location=68) mat4 window_to_world;
+
+// The planet and its air, relative to the camera and in world units, see
+// `draw_atmospheres` in main.rs
+uniform layout(location=60) vec3 planet_center;
+uniform layout(location=61) float planet_radius;
+uniform layout(location=62) float atmosphere_radius;
+uniform layout(location=63) vec3 rayleigh;
+uniform layout(location=64) float rayleigh_scale_height;
+uniform layout(location=65) float mie;
+uniform layout(location=66) float mie_scale_height;
+uniform layout(location=67) float mie_anisotropy;
+
+// Brightness of a light of colour 1 before scattering
+const float LIGHT_INTENSITY = 20.0;
+
+// Distances along the ray to where it enters and leaves the sphere, the first
+// larger than the second when it misses
+vec2 ray_sphere(vec3 origin, vec3 direction, float radius)
+{
+    vec3 offset = origin - planet_center;
+    float b = dot(offset, direction);
+    float c = dot(offset, offset) - radius * radius;
+    float discriminant = b * b - c;
+    if (discriminant < 0.0)
+    {
+        return vec2(1.0, -1.0);
+    }
+    float root = sqrt(discriminant);
+    return vec2(-b - root, -b + root);
+}
+
+// Air and haze density at `position`, relative to the surface
+vec2 density(vec3 position)
+{
+    float altitude = length(position - planet_center) - planet_radius;
+    return exp(-altitude / vec2(rayleigh_scale_height, mie_scale_height));
+}
+
+// How far along `direction` the solid scene under this fragment is, the far
+// plane where nothing was drawn
+float scene_distance(vec3 direction)
+{
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(scene_depth, texel, 0).r;
+#ifdef LOG_DEPTH
+    // Undo log2(1 + w) * log_depth for the distance straight ahead, then
+    // stretch it out along this fragment's ray
+    vec4 ahead = window_to_world * vec4(0.0, 0.0, 0.5, 1.0);
+    vec3 forward = normalize(ahead.xyz / ahead.w);
+    return (exp2(depth / log_depth) - 1.0) / dot(direction, forward);
+#else
+    vec2 ndc = (vec2(texel) + 0.5) / vec2(textureSize(scene_depth, 0)) * 2.0 - 1.0;
+    vec4 position = window_to_world * vec4(ndc, depth, 1.0);
+    return length(position.xyz / position.w);
+#endif
+}
+
+vec3 extinction(vec2 optical_depth)
+{
+    // Haze absorbs a little on top of what it scatters
+    return exp(-(rayleigh * optical_depth.x + mie * 1.1 * optical_depth.y));
+}
+
+void main()
+{
+    // The camera sits at the origin
+    vec3 direction = normalize(frag_pos);
+    vec2 shell = ray_sphere(vec3(0.0), direction, atmosphere_radius);
+    float start = max(shell.x, 0.0);
+    // Stopping at the terrain, or whatever else is in the way, hazes it over
+    // by how much air is in front of it
+    float end = min(shell.y, scene_distance(direction));
+    if (end <= start)
+    {
+        discard;
+    }
+
+    float step_length = (end - start) / float(VIEW_SAMPLES);
+    vec2 view_depth = vec2(0.0);
+    vec3 rayleigh_sum = vec3(0.0);
+    vec3 mie_sum = vec3(0.0);
+    for (int i = 0; i < VIEW_SAMPLES; i++)
+    {
+        vec3 position = direction * (start + (float(i) + 0.5) * step_length);
+        vec2 step_density = density(position) * step_length;
+        view_depth += step_density;
+
+        for (int l = 0; l < light_count; l++)
+        {
+            Light light = lights[l];
+            vec3 light_direction = light.kind == DIRECTIONAL_LIGHT
+                ? -light.direction
+                : normalize(light.position - position);
+
+            // Night side, the planet is in the way
+            vec2 blocker = ray_sphere(position, light_direction, planet_radius);
+            if (blocker.x < blocker.y && blocker.x > 0.0)
+            {
+                continue;
+            }
+
+            float light_step = ray_sphere(position, light_direction, atmosphere_radius).y
+                / float(LIGHT_SAMPLES);
+            vec2 light_depth = vec2(0.0);
+            for (int j = 0; j < LIGHT_SAMPLES; j++)
+            {
+                light_depth += density(position + light_direction * (float(j) + 0.5) * light_step)
+                    * light_step;
+            }
+
+            vec3 reaching = extinction(view_depth + light_depth) * light.diffuse;
+
+            // Rayleigh and Cornette-Shanks phase functions
+            float mu = dot(direction, light_direction);
+            float g = mie_anisotropy;
+            float rayleigh_phase = 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+            float mie_phase = 3.0 / (8.0 * PI) * ((1.0 - g * g) * (1.0 + mu * mu))
+                / ((2.0 + g * g) * pow(1.0 + g * g - 2.0 * g * mu, 1.5));
+
+            rayleigh_sum += reaching * step_density.x * rayleigh_phase;
+            mie_sum += reaching * step_density.y * mie_phase;
+        }
+    }
+
+    vec3 color = LIGHT_INTENSITY * (rayleigh_sum * rayleigh + mie_sum * mie);
+    // Soft clip so the glow at the limb does not saturate
+    color = 1.0 - exp(-color);
+
+    // What lies behind shows through dimmed by the air in front of it
+    vec3 transmittance = extinction(view_depth);
+    float opacity = 1.0 - (transmittance.r + transmittance.g + transmittance.b) / 3.0;
+    FragColor = vec4(color, opacity);
+    // Only written when needed, as writing depth at all turns off early depth
+    // testing
+#ifdef LOG_DEPTH
+    gl_FragDepth = log2(log_z) * log_depth;
+#endif
+}
@@ -0,0 +1,54 @@
+#version 450 core
+
+out vec4 FragColor;
+
+layout(location=0) in vec2 uv;
+
+// The level above, or the scene itself on the first pass
+layout(binding=4) uniform sampler2D source;
+
+// 1 / the size of `source`
+uniform layout(location=110) vec2 texel_size;
+// Brightness above which light spills into the bloom
+uniform layout(location=111) float threshold;
+// Whether `source` is the scene, which is cut down to its bright parts
+uniform layout(location=112) bool first_pass;
+
+// Keeps only the light above the threshold, eased in over a knee of half the
+// threshold so nothing pops in
+vec3 bright_part(vec3 color)
+{
+    float brightness = max(color.r, max(color.g, color.b));
+    float knee = 0.5 * threshold;
+    float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 1e-5);
+    float contribution = max(soft, brightness - threshold) / max(brightness, 1e-5);
+    return color * contribution;
+}
+
+void main()
+{
+    // Thirteen taps in overlapping boxes, which keeps small bright spots from
+    // flickering as they move between texels
+    vec2 t = texel_size;
+    vec3 a = texture(source, uv + t * vec2(-2.0, 2.0)).rgb;
+    vec3 b = texture(source, uv + t * vec2(0.0, 2.0)).rgb;
+    vec3 c = texture(source, uv + t * vec2(2.0, 2.0)).rgb;
+    vec3 d = texture(source, uv + t * vec2(-2.0, 0.0)).rgb;
+    vec3 e = texture(source, uv).rgb;
+    vec3 f = texture(source, uv + t * vec2(2.0, 0.0)).rgb;
+    vec3 g = texture(source, uv + t * vec2(-2.0, -2.0)).rgb;
+    vec3 h = texture(source, uv + t * vec2(0.0, -2.0)).rgb;
+    vec3 i = texture(source, uv + t * vec2(2.0, -2.0)).rgb;
+    vec3 j = texture(source, uv + t * vec2(-1.0, 1.0)).rgb;
+    vec3 k = texture(source, uv + t * vec2(1.0, 1.0)).rgb;
+    vec3 l = texture(source, uv + t * vec2(-1.0, -1.0)).rgb;
+    vec3 m = texture(source, uv + t * vec2(1.0, -1.0)).rgb;
+
+    vec3 color = e * 0.125
+        + (a + c + g + i) * 0.03125
+        + (b + d + f + h) * 0.0625
+        + (j + k + l + m) * 0.125;
+
+    FragColor = vec4(first_pass ? bright_part(color) : color, 1.0);
+}
@@ -0,0 +1,28 @@
+#version 450 core
+
+out vec4 FragColor;
+
+layout(location=0) in vec2 uv;
+
+// The smaller level, added onto the one being drawn
+layout(binding=4) uniform sampler2D source;
+
+// 1 / the size of `source`
+uniform layout(location=110) vec2 texel_size;
+
+void main()
+{
+    // A 3x3 tent filter, spreading the light out as it grows back
+    vec2 t = texel_size;
+    vec3 color = texture(source, uv).rgb * 4.0;
+    color += (texture(source, uv + vec2(-t.x, 0.0)).rgb
+        + texture(source, uv + vec2(t.x, 0.0)).rgb
+        + texture(source, uv + vec2(0.0, -t.y)).rgb
+        + texture(source, uv + vec2(0.0, t.y)).rgb) * 2.0;
+    color += texture(source, uv + vec2(-t.x, -t.y)).rgb
+        + texture(source, uv + vec2(t.x, -t.y)).rgb
+        + texture(source, uv + vec2(-t.x, t.y)).rgb
+        + texture(source, uv + vec2(t.x, t.y)).rgb;
+
+    FragColor = vec4(color / 16.0, 1.0);
+}
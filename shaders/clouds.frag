@@ -0,0 +1,64 @@
+#version 450 core
+
+// Defined in noise.glsl
+float cloud_cover(Noise noise, vec3 direction, float drift, float coverage, float density);
+
+out vec4 FragColor;
+
+layout(location=0) in vec3 frag_pos;
+// The shell is a unit sphere, so this is also the direction from the
+// planet's centre in the planet's own frame
+layout(location=5) in vec3 normalVector;
+layout(location=6) in float log_z;
+
+uniform layout(location=11) mat4 model_matrix;
+
+// 1 / log2(far + 1), used when built with LOG_DEPTH
+uniform layout(location=41) float log_depth;
+
+// The cloud layer, see `draw_clouds` in main.rs
+uniform layout(location=71) Noise cloud_noise;
+uniform layout(location=78) float cloud_coverage;
+uniform layout(location=79) float cloud_density;
+uniform layout(location=80) float cloud_drift;
+uniform layout(location=82) vec3 cloud_color;
+
+void main()
+{
+    float cover = cloud_cover(cloud_noise, normalize(normalVector), cloud_drift, cloud_coverage, cloud_density);
+    if (cover <= 0.0)
+    {
+        discard;
+    }
+
+    vec3 normal = normalize(normalVector * mat3(model_matrix));
+    vec3 light_sum = vec3(0.0);
+    for (int i = 0; i < light_count; i++)
+    {
+        Light light = lights[i];
+        vec3 light_direction;
+        float attenuation = 1.0;
+        if (light.kind == DIRECTIONAL_LIGHT)
+        {
+            light_direction = -light.direction;
+        }
+        else
+        {
+            vec3 to_light = light.position - frag_pos;
+            float d = length(to_light);
+            light_direction = to_light / d;
+            attenuation = 1.0 / (light.constant + light.linear * d + light.quadratic * d * d);
+        }
+
+        // Light scattered through the cloud reaches a little past the terminator
+        float diffuse = max((dot(normal, light_direction) + 0.2) / 1.2, 0.0);
+        light_sum += attenuation * (light.ambient + diffuse * light.diffuse);
+    }
+
+    FragColor = vec4(cloud_color * light_sum, cover);
+    // Only written when needed, as writing depth at all turns off early depth
+    // testing
+#ifdef LOG_DEPTH
+    gl_FragDepth = log2(log_z) * log_depth;
+#endif
+}
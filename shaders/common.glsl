@@ -0,0 +1,44 @@
+// Declarations shared by several shaders, put in front of each of their stages
+// by `ShaderBuilder::include`. Has no #version line of its own.
+
+// Must match MAX_LIGHTS in light.rs
+#define MAX_LIGHTS 16
+
+#define POINT_LIGHT 0
+#define DIRECTIONAL_LIGHT 1
+#define SPOT_LIGHT 2
+
+#define PI 3.14159265
+
+// Matches `Noise` in noise.rs
+struct Noise {
+    float strength;
+    float base_roughness;
+    float roughness;
+    float persistence;
+    vec3 center;
+    int num_layers;
+    float min_value;
+};
+
+// Laid out to match `GpuLight` in light.rs
+struct Light {
+    vec3 position;
+    int kind;
+    vec3 direction;
+    float cos_inner;
+    vec3 ambient;
+    float cos_outer;
+    vec3 diffuse;
+    float constant;
+    vec3 specular;
+    float linear;
+    float quadratic;
+    // Layer of the light's cube in `shadow_maps`, -1 for none
+    int shadow;
+};
+
+layout(std140, binding=0) uniform Lights {
+    int light_count;
+    Light lights[MAX_LIGHTS];
+};
@@ -2,13 +2,24 @@
 
 out vec4 FragColor;
 
-layout(location=1) in vec3 ambient_material;
+layout(location=6) in float log_z;
+
+// The star's blackbody colour, replacing the vertex colours
+uniform layout(location=42) vec3 emission;
+
+// 1 / log2(far + 1), used when built with LOG_DEPTH
+uniform layout(location=41) float log_depth;
 
 
 void main()
 {
-    vec3 color = ambient_material;
+    vec3 color = emission;
     FragColor = vec4(color, 1.0);
+    // Only written when needed, as writing depth at all turns off early depth
+    // testing
+#ifdef LOG_DEPTH
+    gl_FragDepth = log2(log_z) * log_depth;
+#endif
 }
 
 
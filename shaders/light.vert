@@ -18,6 +18,19 @@ layout(location=4) out float shininess_out;
 layout(location=5) in vec3 normalVector;
 layout(location=5) out vec3 normal_vector_out;
 
+// One more than the distance in front of the camera, for logarithmic depth
+layout(location=6) out float log_z_out;
+
+// Physically based material, one slot up to make room for `log_z_out`
+layout(location=6) in float roughness;
+layout(location=7) out float roughness_out;
+
+layout(location=7) in float metallic;
+layout(location=8) out float metallic_out;
+
+layout(location=8) in float shading;
+layout(location=9) flat out float shading_out;
+
 
 uniform layout(location=10) mat4 transform_matrix;
 uniform layout(location=11) mat4 model_matrix;
@@ -27,6 +40,7 @@ void main()
 {
     const vec4 transformed_pos = vec4(position, 1) * transform_matrix;
     gl_Position = transformed_pos;
+    log_z_out = 1.0 + transformed_pos.w;
     
     frag_pos_out = vec3(vec4(position, 1) * model_matrix);
     normal_vector_out = normalVector;
@@ -34,4 +48,7 @@ void main()
     diffuse_material_out = diffuse_material;
     specular_material_out = specular_material;
     shininess_out = shininess;
+    metallic_out = metallic;
+    shading_out = shading;
+    roughness_out = roughness;
 }
\ No newline at end of file
@@ -0,0 +1,75 @@
+#version 450 core
+
+out vec4 FragColor;
+
+layout(location=0) in vec3 frag_pos;
+layout(location=6) in float log_z;
+
+uniform layout(location=11) mat4 model_matrix;
+
+// 1 / log2(far + 1), used when built with LOG_DEPTH
+uniform layout(location=41) float log_depth;
+
+// Colour and density across the ring, from the inner edge out, see rings.rs
+layout(binding=2) uniform sampler1D ring_profile;
+
+// In world units, see `draw_rings` in main.rs
+uniform layout(location=90) float planet_radius;
+uniform layout(location=91) float inner_radius;
+uniform layout(location=92) float outer_radius;
+
+void main()
+{
+    vec3 center = vec3(vec4(0.0, 0.0, 0.0, 1.0) * model_matrix);
+    vec3 offset = frag_pos - center;
+    float across = (length(offset) - inner_radius) / (outer_radius - inner_radius);
+    vec4 profile = texture(ring_profile, across);
+    if (profile.a <= 0.0)
+    {
+        discard;
+    }
+
+    vec3 normal = normalize(vec3(0.0, 1.0, 0.0) * mat3(model_matrix));
+    // The camera sits at the origin
+    float camera_side = dot(normal, -frag_pos);
+
+    vec3 light_sum = vec3(0.0);
+    for (int i = 0; i < light_count; i++)
+    {
+        Light light = lights[i];
+        vec3 light_direction;
+        float attenuation = 1.0;
+        if (light.kind == DIRECTIONAL_LIGHT)
+        {
+            light_direction = -light.direction;
+        }
+        else
+        {
+            vec3 to_light = light.position - frag_pos;
+            float d = length(to_light);
+            light_direction = to_light / d;
+            attenuation = 1.0 / (light.constant + light.linear * d + light.quadratic * d * d);
+        }
+
+        // The planet is in the way when the ray towards the light heads into it
+        float b = dot(offset, light_direction);
+        float c = dot(offset, offset) - planet_radius * planet_radius;
+        bool shadowed = b < 0.0 && b * b - c > 0.0;
+
+        // Both faces catch the light, but from the far side only what gets
+        // through the ring reaches the camera
+        float lit = abs(dot(normal, light_direction));
+        if (dot(normal, light_direction) * camera_side < 0.0)
+        {
+            lit *= 1.0 - profile.a;
+        }
+        light_sum += attenuation * (light.ambient + (shadowed ? 0.0 : lit) * light.diffuse);
+    }
+
+    FragColor = vec4(profile.rgb * light_sum, profile.a);
+    // Only written when needed, as writing depth at all turns off early depth
+    // testing
+#ifdef LOG_DEPTH
+    gl_FragDepth = log2(log_z) * log_depth;
+#endif
+}
@@ -0,0 +1,15 @@
+#version 450 core
+
+layout(location=0) in vec3 frag_pos;
+
+// The light whose shadow cube is being drawn, and how far its shadows reach
+uniform layout(location=46) vec3 light_position;
+uniform layout(location=43) float shadow_range;
+
+
+void main()
+{
+    // Distance rather than window depth, so every face of the cube and the
+    // lookup in shape.frag agree on one measure
+    gl_FragDepth = length(frag_pos - light_position) / shadow_range;
+}
@@ -1,11 +1,9 @@
 #version 450 core
 
-struct Light {
-    vec3 position;
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-};
+#define MAX_SHADOW_LIGHTS 4
+
+// Defined in noise.glsl
+float cloud_cover(Noise noise, vec3 direction, float drift, float coverage, float density);
 
 out vec4 FragColor;
 
@@ -15,38 +13,194 @@ layout(location=2) in vec3 diffuse_material;
 layout(location=3) in vec3 specular_material;
 layout(location=4) in float shininess_material;
 layout(location=5) in vec3 normalVector;
+layout(location=6) in float log_z;
+layout(location=7) in float roughness_material;
+layout(location=8) in float metallic_material;
+// 0 for Phong, 1 for physically based, see `Shading` in material.rs
+layout(location=9) flat in float shading_material;
 
 
 uniform layout(location=11) mat4 model_matrix;
 uniform layout(location=12) vec3 camera_position;
-uniform layout(location=13) Light light;
 
-void main()
+// 1 / log2(far + 1), used when built with LOG_DEPTH
+uniform layout(location=41) float log_depth;
+
+// Distance to the nearest occluder around each shadow casting light, as a
+// fraction of `shadow_range`, see shadow.rs
+layout(binding=1) uniform samplerCubeArrayShadow shadow_maps;
+uniform layout(location=43) float shadow_range;
+uniform layout(location=44) float shadow_bias;
+uniform layout(location=45) float shadow_softness;
+
+// The cloud layer around this node, if it casts shadows, see `draw_scene` in
+// main.rs. It shares the node's centre and rotation.
+uniform layout(location=70) bool cloud_shadows;
+uniform layout(location=71) Noise cloud_noise;
+uniform layout(location=78) float cloud_coverage;
+uniform layout(location=79) float cloud_density;
+uniform layout(location=80) float cloud_drift;
+uniform layout(location=81) float cloud_radius;
+
+// Spread of the filter's samples, one per edge of a cube around the direction
+const int SHADOW_SAMPLES = 20;
+const vec3 shadow_offsets[SHADOW_SAMPLES] = vec3[](
+    vec3( 1,  1,  1), vec3( 1, -1,  1), vec3(-1, -1,  1), vec3(-1,  1,  1),
+    vec3( 1,  1, -1), vec3( 1, -1, -1), vec3(-1, -1, -1), vec3(-1,  1, -1),
+    vec3( 1,  1,  0), vec3( 1, -1,  0), vec3(-1, -1,  0), vec3(-1,  1,  0),
+    vec3( 1,  0,  1), vec3(-1,  0,  1), vec3( 1,  0, -1), vec3(-1,  0, -1),
+    vec3( 0,  1,  1), vec3( 0, -1,  1), vec3( 0, -1, -1), vec3( 0,  1, -1)
+);
+
+// How much of `light` reaches this fragment, from 0 in full shadow to 1
+float shadow_factor(Light light, vec3 normal, vec3 light_direction)
 {
-    mat3 scale_rotate_matrix = mat3(model_matrix);
+    vec3 from_light = frag_pos - light.position;
+    float depth = length(from_light) / shadow_range;
+    if (light.shadow < 0 || depth >= 1.0)
+    {
+        return 1.0;
+    }
+
+    // Surfaces at a grazing angle to the light need more bias
+    float slope = 1.0 - max(dot(normal, light_direction), 0.0);
+    float reference = depth - shadow_bias * (1.0 + 4.0 * slope);
+
+    float radius = shadow_softness * length(from_light);
+    float lit = 0.0;
+    for (int i = 0; i < SHADOW_SAMPLES; i++)
+    {
+        vec3 direction = from_light + shadow_offsets[i] * radius;
+        lit += texture(shadow_maps, vec4(direction, float(light.shadow)), reference);
+    }
+    return lit / float(SHADOW_SAMPLES);
+}
 
-    vec3 actual_normal = normalize(normalVector * scale_rotate_matrix);
-    vec3 light_direction = normalize(light.position - frag_pos);
+// How much of the light from `light_direction` gets through the clouds above
+// this fragment, from 0 under the thickest cloud to 1
+float cloud_shadow(vec3 light_direction)
+{
+    if (!cloud_shadows)
+    {
+        return 1.0;
+    }
+
+    // Where the ray towards the light leaves the cloud layer
+    vec3 center = vec3(vec4(0.0, 0.0, 0.0, 1.0) * model_matrix);
+    vec3 offset = frag_pos - center;
+    float b = dot(offset, light_direction);
+    float c = dot(offset, offset) - cloud_radius * cloud_radius;
+    if (c > 0.0)
+    {
+        // Peaks above the clouds
+        return 1.0;
+    }
+    vec3 exit = offset + light_direction * (-b + sqrt(b * b - c));
+
+    // Back into the planet's frame, where the cloud pattern is laid out
+    vec3 direction = normalize(mat3(model_matrix) * exit);
+    return 1.0 - cloud_cover(cloud_noise, direction, cloud_drift, cloud_coverage, cloud_density);
+}
+
+// Lambert diffuse plus a GGX microfacet specular lobe with Smith shadowing and
+// Schlick's Fresnel, with the diffuse colour as albedo. Lights are scaled by π
+// so a matte white surface comes out as bright as under Phong.
+vec3 cook_torrance(Light light, vec3 normal, vec3 camera_direction, vec3 light_direction)
+{
+    float n_dot_l = max(dot(normal, light_direction), 0.0);
+    float n_dot_v = max(dot(normal, camera_direction), 0.0);
+    if (n_dot_l <= 0.0)
+    {
+        return vec3(0.0);
+    }
+    vec3 halfway = normalize(light_direction + camera_direction);
+    float n_dot_h = max(dot(normal, halfway), 0.0);
+    float h_dot_v = max(dot(halfway, camera_direction), 0.0);
+
+    // Perfectly smooth surfaces would make the highlight vanishingly small
+    float roughness = clamp(roughness_material, 0.04, 1.0);
+    float alpha = roughness * roughness;
+    float alpha2 = alpha * alpha;
+
+    float denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    float distribution = alpha2 / (PI * denominator * denominator);
+
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float geometry = n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+
+    // Dielectrics reflect about 4% head on, metals tint the reflection
+    vec3 f0 = mix(vec3(0.04), diffuse_material, metallic_material);
+    vec3 fresnel = f0 + (1.0 - f0) * pow(1.0 - h_dot_v, 5.0);
+
+    vec3 specular = distribution * geometry * fresnel / max(4.0 * n_dot_v * n_dot_l, 1e-4);
+    // Metals have no diffuse, and what is reflected does not also scatter
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic_material) * diffuse_material / PI;
+
+    return (diffuse * light.diffuse + specular * light.specular) * PI * n_dot_l;
+}
+
+vec3 shade(Light light, vec3 normal, vec3 camera_direction)
+{
+    vec3 light_direction;
+    float attenuation = 1.0;
+    float spot = 1.0;
+    if (light.kind == DIRECTIONAL_LIGHT)
+    {
+        light_direction = -light.direction;
+    }
+    else
+    {
+        vec3 to_light = light.position - frag_pos;
+        float d = length(to_light);
+        light_direction = to_light / d;
+        attenuation = 1.0 / (light.constant + light.linear * d + light.quadratic * d * d);
+
+        if (light.kind == SPOT_LIGHT)
+        {
+            float cos_angle = dot(-light_direction, light.direction);
+            spot = clamp((cos_angle - light.cos_outer) / max(light.cos_inner - light.cos_outer, 1e-4), 0.0, 1.0);
+        }
+        spot *= shadow_factor(light, normal, light_direction);
+    }
+    spot *= cloud_shadow(light_direction);
+
+    // `spot` also carries the shadow, which leaves the ambient light alone
+    if (shading_material > 0.5)
+    {
+        vec3 ambient = diffuse_material * light.ambient;
+        return attenuation * (ambient + spot * cook_torrance(light, normal, camera_direction, light_direction));
+    }
 
     //Ambient component
     vec3 ambient = ambient_material * light.ambient;
 
     //Diffuse component
-    vec3 diffuse = (max(0, dot(actual_normal, light_direction)) * diffuse_material) * light.diffuse;
+    vec3 diffuse = (max(0, dot(normal, light_direction)) * diffuse_material) * light.diffuse;
 
     //Specular component
-    vec3 camera_direction = normalize(camera_position - frag_pos);
-    vec3 reflection_direction = reflect(-light_direction, actual_normal);
+    vec3 reflection_direction = reflect(-light_direction, normal);
     float spec = pow(max(dot(camera_direction, reflection_direction), 0.0), shininess_material);
     vec3 specular = (specular_material * spec) * light.specular;
 
-
-    vec3 color =  (ambient + diffuse + specular);
-    FragColor = vec4(color, 1.0);
+    return attenuation * (ambient + spot * (diffuse + specular));
 }
 
+void main()
+{
+    mat3 scale_rotate_matrix = mat3(model_matrix);
 
+    vec3 actual_normal = normalize(normalVector * scale_rotate_matrix);
+    vec3 camera_direction = normalize(camera_position - frag_pos);
 
-
-
-
+    vec3 color = vec3(0.0);
+    for (int i = 0; i < light_count; i++)
+    {
+        color += shade(lights[i], actual_normal, camera_direction);
+    }
+    FragColor = vec4(color, 1.0);
+    // Only written when needed, as writing depth at all turns off early depth
+    // testing
+#ifdef LOG_DEPTH
+    gl_FragDepth = log2(log_z) * log_depth;
+#endif
+}
@@ -1,15 +1,5 @@
 #version 450 core
 
-struct Noise {
-    float strength;
-    float base_roughness;
-    float roughness;
-    float persistence;
-    vec3 center;
-    int num_layers;
-    float min_value;
-};
-
 layout(location=0) in vec3 position;
 layout(location=0) out vec3 frag_pos_out;
 
@@ -28,6 +18,19 @@ layout(location=4) out float shininess_out;
 layout(location=5) in vec3 normalVector;
 layout(location=5) out vec3 normal_vector_out;
 
+// One more than the distance in front of the camera, for logarithmic depth
+layout(location=6) out float log_z_out;
+
+// Physically based material, one slot up to make room for `log_z_out`
+layout(location=6) in float roughness;
+layout(location=7) out float roughness_out;
+
+layout(location=7) in float metallic;
+layout(location=8) out float metallic_out;
+
+layout(location=8) in float shading;
+layout(location=9) flat out float shading_out;
+
 
 uniform layout(location=10) mat4 transform_matrix;
 uniform layout(location=11) mat4 model_matrix;
@@ -35,80 +38,32 @@ uniform layout(location=11) mat4 model_matrix;
 
 uniform layout(location=17) Noise noise;
 
+// Elevation to colour gradient, see biome.rs. No stops keeps the vertex material.
+const int MAX_BIOME_STOPS = 8;
+uniform layout(location=24) int biome_stop_count;
+uniform layout(location=25) float biome_heights[MAX_BIOME_STOPS];
+uniform layout(location=33) vec3 biome_colors[MAX_BIOME_STOPS];
+uniform layout(location=50) float biome_roughness[MAX_BIOME_STOPS];
+
 
-//	Classic Perlin 3D Noise 
-//	by Stefan Gustavson
-//
-vec4 permute(vec4 x){return mod(((x*34.0)+1.0)*x, 289.0);}
-vec4 taylorInvSqrt(vec4 r){return 1.79284291400159 - 0.85373472095314 * r;}
-vec3 fade(vec3 t) {return t*t*t*(t*(t*6.0-15.0)+10.0);}
-
-float cnoise(vec3 P){
-  vec3 Pi0 = floor(P); // Integer part for indexing
-  vec3 Pi1 = Pi0 + vec3(1.0); // Integer part + 1
-  Pi0 = mod(Pi0, 289.0);
-  Pi1 = mod(Pi1, 289.0);
-  vec3 Pf0 = fract(P); // Fractional part for interpolation
-  vec3 Pf1 = Pf0 - vec3(1.0); // Fractional part - 1.0
-  vec4 ix = vec4(Pi0.x, Pi1.x, Pi0.x, Pi1.x);
-  vec4 iy = vec4(Pi0.yy, Pi1.yy);
-  vec4 iz0 = Pi0.zzzz;
-  vec4 iz1 = Pi1.zzzz;
-
-  vec4 ixy = permute(permute(ix) + iy);
-  vec4 ixy0 = permute(ixy + iz0);
-  vec4 ixy1 = permute(ixy + iz1);
-
-  vec4 gx0 = ixy0 / 7.0;
-  vec4 gy0 = fract(floor(gx0) / 7.0) - 0.5;
-  gx0 = fract(gx0);
-  vec4 gz0 = vec4(0.5) - abs(gx0) - abs(gy0);
-  vec4 sz0 = step(gz0, vec4(0.0));
-  gx0 -= sz0 * (step(0.0, gx0) - 0.5);
-  gy0 -= sz0 * (step(0.0, gy0) - 0.5);
-
-  vec4 gx1 = ixy1 / 7.0;
-  vec4 gy1 = fract(floor(gx1) / 7.0) - 0.5;
-  gx1 = fract(gx1);
-  vec4 gz1 = vec4(0.5) - abs(gx1) - abs(gy1);
-  vec4 sz1 = step(gz1, vec4(0.0));
-  gx1 -= sz1 * (step(0.0, gx1) - 0.5);
-  gy1 -= sz1 * (step(0.0, gy1) - 0.5);
-
-  vec3 g000 = vec3(gx0.x,gy0.x,gz0.x);
-  vec3 g100 = vec3(gx0.y,gy0.y,gz0.y);
-  vec3 g010 = vec3(gx0.z,gy0.z,gz0.z);
-  vec3 g110 = vec3(gx0.w,gy0.w,gz0.w);
-  vec3 g001 = vec3(gx1.x,gy1.x,gz1.x);
-  vec3 g101 = vec3(gx1.y,gy1.y,gz1.y);
-  vec3 g011 = vec3(gx1.z,gy1.z,gz1.z);
-  vec3 g111 = vec3(gx1.w,gy1.w,gz1.w);
-
-  vec4 norm0 = taylorInvSqrt(vec4(dot(g000, g000), dot(g010, g010), dot(g100, g100), dot(g110, g110)));
-  g000 *= norm0.x;
-  g010 *= norm0.y;
-  g100 *= norm0.z;
-  g110 *= norm0.w;
-  vec4 norm1 = taylorInvSqrt(vec4(dot(g001, g001), dot(g011, g011), dot(g101, g101), dot(g111, g111)));
-  g001 *= norm1.x;
-  g011 *= norm1.y;
-  g101 *= norm1.z;
-  g111 *= norm1.w;
-
-  float n000 = dot(g000, Pf0);
-  float n100 = dot(g100, vec3(Pf1.x, Pf0.yz));
-  float n010 = dot(g010, vec3(Pf0.x, Pf1.y, Pf0.z));
-  float n110 = dot(g110, vec3(Pf1.xy, Pf0.z));
-  float n001 = dot(g001, vec3(Pf0.xy, Pf1.z));
-  float n101 = dot(g101, vec3(Pf1.x, Pf0.y, Pf1.z));
-  float n011 = dot(g011, vec3(Pf0.x, Pf1.yz));
-  float n111 = dot(g111, Pf1);
-
-  vec3 fade_xyz = fade(Pf0);
-  vec4 n_z = mix(vec4(n000, n100, n010, n110), vec4(n001, n101, n011, n111), fade_xyz.z);
-  vec2 n_yz = mix(n_z.xy, n_z.zw, fade_xyz.y);
-  float n_xyz = mix(n_yz.x, n_yz.y, fade_xyz.x); 
-  return 2.2 * n_xyz;
+// Defined in noise.glsl
+float cnoise(vec3 P);
+
+// Colour and roughness blended between the stops around `height`
+void biome_material(float height, out vec3 color, out float roughness)
+{
+    color = biome_colors[0];
+    roughness = biome_roughness[0];
+    for (int i = 1; i < biome_stop_count; i++)
+    {
+        float span = max(biome_heights[i] - biome_heights[i - 1], 1e-6);
+        float t = clamp((height - biome_heights[i - 1]) / span, 0.0, 1.0);
+        if (height >= biome_heights[i - 1])
+        {
+            color = mix(biome_colors[i - 1], biome_colors[i], t);
+            roughness = mix(biome_roughness[i - 1], biome_roughness[i], t);
+        }
+    }
 }
 
 void main()
@@ -116,22 +71,26 @@ void main()
     float noise_value = 0.0;
     float frequency = noise.base_roughness;
     float amplitude = 1.0; 
+    float max_value = 0.0;
 
     for (int i = 0; i < noise.num_layers; i++)
     {
         float v = cnoise(position * frequency + noise.center); 
         noise_value += (v + 1.0) * 0.5 * amplitude;
+        max_value += amplitude;
         frequency *= noise.roughness;
         amplitude *= noise.persistence;
     }
 
     noise_value = noise_value * noise.strength;
     noise_value = max(0.0, noise_value - noise.min_value);
+    max_value = max_value * noise.strength - noise.min_value;
 
     vec3 new_position = position * (noise_value + 1.0);
 
     const vec4 transformed_pos = vec4(new_position, 1) * transform_matrix;
     gl_Position = transformed_pos;
+    log_z_out = 1.0 + transformed_pos.w;
     
     frag_pos_out = vec3(vec4(new_position, 1) * model_matrix);
 
@@ -139,6 +98,15 @@ void main()
     normal_vector_out = normalize(normalVector / (noise_value + 1.0));
     ambient_material_out = ambient_material;
     diffuse_material_out = diffuse_material;
+    roughness_out = roughness;
+    if (biome_stop_count > 0)
+    {
+        float height = max_value > 0.0 ? min(noise_value / max_value, 1.0) : 0.0;
+        biome_material(height, ambient_material_out, roughness_out);
+        diffuse_material_out = ambient_material_out;
+    }
     specular_material_out = specular_material;
     shininess_out = shininess;
+    metallic_out = metallic;
+    shading_out = shading;
 }
\ No newline at end of file
@@ -0,0 +1,48 @@
+#version 450 core
+
+// Defined in noise.glsl
+float fractal_noise(Noise noise, vec3 position);
+
+out vec4 FragColor;
+
+layout(location=0) in vec3 view_direction;
+
+// Six images around the camera, used in place of the procedural sky
+layout(binding=3) uniform samplerCube skybox;
+uniform layout(location=100) bool use_skybox;
+
+// See `Starfield` in background.rs
+uniform layout(location=101) vec3 sky_color;
+uniform layout(location=102) bool milky_way;
+uniform layout(location=103) float milky_way_brightness;
+uniform layout(location=104) vec3 galactic_pole;
+uniform layout(location=105) vec3 noise_offset;
+
+void main()
+{
+    vec3 direction = normalize(view_direction);
+    if (use_skybox)
+    {
+        FragColor = vec4(texture(skybox, direction).rgb, 1.0);
+        return;
+    }
+
+    vec3 color = sky_color;
+    if (milky_way)
+    {
+        float latitude = dot(direction, galactic_pole);
+
+        // Clumps of unresolved stars spread over a soft band
+        Noise glow_noise = Noise(1.0, 2.5, 2.2, 0.55, noise_offset, 6, 0.0);
+        float band = exp(-latitude * latitude / (2.0 * 0.15 * 0.15));
+        float glow = band * (0.3 + 0.7 * fractal_noise(glow_noise, direction));
+
+        // Dust along the middle of the band blots part of it out
+        Noise dust_noise = Noise(1.0, 5.0, 2.0, 0.5, noise_offset + 17.0, 5, 0.0);
+        float lane = exp(-latitude * latitude / (2.0 * 0.04 * 0.04));
+        float dust = lane * smoothstep(0.4, 0.7, fractal_noise(dust_noise, direction));
+
+        color += milky_way_brightness * glow * (1.0 - 0.8 * dust) * vec3(0.55, 0.58, 0.7);
+    }
+    FragColor = vec4(color, 1.0);
+}
@@ -0,0 +1,18 @@
+#version 450 core
+
+// A triangle covering the screen, made up from the vertex index alone
+layout(location=0) out vec3 view_direction;
+
+// Back from clip space to the direction around the camera, see
+// `draw_background` in main.rs
+uniform layout(location=10) mat4 inverse_view_projection;
+
+void main()
+{
+    vec2 corner = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+    gl_Position = vec4(corner, 0.0, 1.0);
+
+    // Any point along the pixel's ray will do, the camera sits at the origin
+    vec4 point = vec4(corner, 0.5, 1.0) * inverse_view_projection;
+    view_direction = point.xyz / point.w;
+}
@@ -0,0 +1,16 @@
+#version 450 core
+
+out vec4 FragColor;
+
+layout(location=0) in vec3 color;
+
+void main()
+{
+    // Round points, fading towards the edge
+    float distance = length(gl_PointCoord - 0.5) * 2.0;
+    if (distance > 1.0)
+    {
+        discard;
+    }
+    FragColor = vec4(color, 1.0 - distance * distance);
+}
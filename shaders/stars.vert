@@ -0,0 +1,22 @@
+#version 450 core
+
+// See `Starfield::mesh` in background.rs
+layout(location=0) in vec3 direction;
+layout(location=2) in vec3 color;
+layout(location=0) out vec3 color_out;
+
+uniform layout(location=10) mat4 view_projection_matrix;
+
+void main()
+{
+    // Infinitely far away. z = w puts them on the far plane for standard and
+    // logarithmic depth, but on the near one under reversed-Z, which is fine
+    // only because the background is drawn with the depth test off.
+    vec4 position = vec4(direction, 0.0) * view_projection_matrix;
+    gl_Position = position.xyww;
+
+    // Bright stars spread over a few pixels instead of clipping
+    float brightest = max(color.r, max(color.g, color.b));
+    gl_PointSize = clamp(sqrt(brightest) * 1.5, 1.0, 4.0);
+    color_out = color / max(gl_PointSize * 0.5, 1.0);
+}
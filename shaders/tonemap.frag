@@ -0,0 +1,54 @@
+#version 450 core
+
+// Numbered as `ToneMapper` in post.rs
+#define REINHARD 0
+#define ACES 1
+#define EXPOSURE 2
+
+out vec4 FragColor;
+
+layout(location=0) in vec2 uv;
+
+// The scene in floating point, unbounded
+layout(binding=4) uniform sampler2D scene;
+// Light blurred out of the scene's bright parts, see post.rs
+layout(binding=5) uniform sampler2D bloom;
+
+uniform layout(location=113) bool bloom_enabled;
+uniform layout(location=114) float bloom_intensity;
+uniform layout(location=115) float exposure;
+uniform layout(location=116) int tone_mapper;
+
+// The curves are copied into the tests in post.rs
+
+// Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 x)
+{
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main()
+{
+    vec3 color = texture(scene, uv).rgb;
+    if (bloom_enabled)
+    {
+        color += texture(bloom, uv).rgb * bloom_intensity;
+    }
+    color *= exposure;
+
+    if (tone_mapper == REINHARD)
+    {
+        color = color / (1.0 + color);
+    }
+    else if (tone_mapper == ACES)
+    {
+        color = aces(color);
+    }
+    else
+    {
+        color = 1.0 - exp(-color);
+    }
+
+    // The scene's colours are already picked for the screen, so no gamma here
+    FragColor = vec4(color, 1.0);
+}
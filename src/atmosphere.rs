@@ -0,0 +1,71 @@
+// A shell of air around a planet, drawn by atmosphere.frag with single
+// Rayleigh and Mie scattering. Lengths are in planet radii so the same
+// atmosphere looks alike on planets of any size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Atmosphere {
+    // Thickness of the shell above the surface
+    pub height: f32,
+    // Scattering by air molecules at the surface, per unit length and colour
+    // channel. Blue scattering most gives the sky its colour.
+    pub rayleigh: glm::Vec3,
+    // Altitude over which the air thins out by a factor of e
+    pub rayleigh_scale_height: f32,
+    // Scattering by haze and dust at the surface, the same for every colour
+    pub mie: f32,
+    pub mie_scale_height: f32,
+    // How strongly haze scatters forwards, from 0 for evenly to just under 1,
+    // which makes the glow around a setting sun
+    pub mie_anisotropy: f32,
+    // Scales both kinds of scattering, 1 for an Earth-like atmosphere
+    pub density: f32,
+}
+
+impl Atmosphere {
+    // Earth's coefficients, stretched over a shell thick enough to see
+    pub fn new() -> Atmosphere {
+        Atmosphere {
+            height: 0.15,
+            rayleigh: glm::vec3(1.76, 4.16, 7.17),
+            rayleigh_scale_height: 0.025,
+            mie: 6.72,
+            mie_scale_height: 0.00375,
+            mie_anisotropy: 0.76,
+            density: 1.0,
+        }
+    }
+
+    // Share of each colour that makes it straight up from the surface to space
+    pub fn zenith_transmittance(&self) -> glm::Vec3 {
+        // Integral of the exponential density over the shell
+        let column = |scale_height: f32| scale_height * (1.0 - (-self.height / scale_height).exp());
+        let rayleigh = self.rayleigh * column(self.rayleigh_scale_height);
+        // Haze absorbs a little on top of what it scatters
+        let mie = self.mie * 1.1 * column(self.mie_scale_height);
+        (rayleigh.add_scalar(mie) * -self.density).map(f32::exp)
+    }
+}
+
+impl Default for Atmosphere {
+    fn default() -> Atmosphere {
+        Atmosphere::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blue_is_scattered_out_most() {
+        let atmosphere = Atmosphere::new();
+        let transmittance = atmosphere.zenith_transmittance();
+        assert!(transmittance.x > transmittance.y && transmittance.y > transmittance.z);
+        assert!(transmittance.z > 0.5);
+
+        let thin = Atmosphere {
+            density: 0.0,
+            ..atmosphere
+        };
+        assert_eq!(thin.zenith_transmittance(), glm::vec3(1.0, 1.0, 1.0));
+    }
+}
@@ -0,0 +1,350 @@
+use crate::gpu::{Texture, VertexArray};
+use crate::mesh::{Mesh, VertexUpdates};
+use crate::star::blackbody_color;
+use crate::vertex_format::STAR_VERTEX_FORMAT;
+
+// Faintest stars drawn, about what the naked eye sees on a dark night
+pub const FAINTEST_MAGNITUDE: f32 = 6.5;
+// Sirius, the brightest star in the night sky
+pub const BRIGHTEST_MAGNITUDE: f32 = -1.5;
+
+// Faces of a skybox, in the order GL expects cube map layers in
+pub const SKYBOX_FACES: [&str; 6] = ["right", "left", "top", "bottom", "front", "back"];
+
+// Stars behind everything else, generated from a seed so the same sky comes
+// back every time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Starfield {
+    pub seed: u64,
+    pub count: u32,
+    // Scales the light of every star
+    pub brightness: f32,
+    // Colour of the empty sky between the stars
+    pub sky_color: glm::Vec3,
+    // A glowing band of unresolved stars with dark dust lanes, around the sky
+    // square to `galactic_pole`, with more stars gathered towards it
+    pub milky_way: bool,
+    pub milky_way_brightness: f32,
+    pub galactic_pole: glm::Vec3,
+}
+
+// One star of the field, as seen from anywhere in the scene
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackgroundStar {
+    pub direction: glm::Vec3,
+    // Apparent magnitude, lower is brighter
+    pub magnitude: f32,
+    // Surface temperature in kelvin, for its colour
+    pub temperature: f32,
+}
+
+impl Starfield {
+    pub fn new() -> Starfield {
+        Starfield {
+            seed: 1,
+            count: 6000,
+            brightness: 1.0,
+            sky_color: glm::vec3(0.035, 0.046, 0.078),
+            milky_way: true,
+            milky_way_brightness: 0.5,
+            galactic_pole: glm::vec3(0.35, 0.85, 0.4),
+        }
+    }
+
+    pub fn stars(&self) -> Vec<BackgroundStar> {
+        let mut random = SplitMix64(self.seed);
+        let to_sky = glm::quat_rotation(
+            &glm::vec3(0.0, 1.0, 0.0),
+            &glm::normalize(&self.galactic_pole),
+        );
+
+        (0..self.count)
+            .map(|_| {
+                // Evenly over the sphere, with galactic latitude along y
+                let y = 2.0 * random.next_f32() - 1.0;
+                let angle = random.next_f32() * std::f32::consts::TAU;
+                let ring = (1.0 - y * y).sqrt();
+                let mut direction = glm::vec3(ring * angle.cos(), y, ring * angle.sin());
+                // Flatten some of them into the disc of the galaxy
+                if self.milky_way && random.next_f32() < 0.4 {
+                    direction.y *= 0.15;
+                    direction = glm::normalize(&direction);
+                }
+
+                // Each magnitude fainter holds about three times as many stars
+                let magnitude = (FAINTEST_MAGNITUDE + random.next_f32().max(1e-6).log10() / 0.5)
+                    .max(BRIGHTEST_MAGNITUDE);
+                // Mostly cool stars, with a few hot blue ones
+                let temperature = 3000.0 * (1.0 + 9.0 * random.next_f32().powi(3));
+
+                BackgroundStar {
+                    direction: glm::quat_rotate_vec3(&to_sky, &direction),
+                    magnitude,
+                    temperature,
+                }
+            })
+            .collect()
+    }
+
+    // How bright a star of `magnitude` is drawn. Each magnitude is 2.5 times
+    // the light, squashed to its square root so faint stars still show.
+    pub fn intensity(&self, magnitude: f32) -> f32 {
+        self.brightness * 0.15 * 10.0_f32.powf(0.2 * (FAINTEST_MAGNITUDE - magnitude))
+    }
+
+    // Offset into the noise for the Milky Way, so each seed gets its own
+    pub fn noise_offset(&self) -> glm::Vec3 {
+        let mut random = SplitMix64(!self.seed);
+        glm::vec3(random.next_f32(), random.next_f32(), random.next_f32()) * 100.0
+    }
+
+    // The stars as points, direction in `vertices` and colour in `diffuse`,
+    // for STAR_VERTEX_FORMAT. Nothing else of the mesh is filled in.
+    pub fn mesh(&self) -> Mesh {
+        let mut mesh = Mesh::empty();
+        for (i, star) in self.stars().iter().enumerate() {
+            let color = blackbody_color(star.temperature) * self.intensity(star.magnitude);
+            mesh.vertices.extend(&star.direction);
+            mesh.material.diffuse.extend(&color);
+            mesh.indices.push(i as u32);
+        }
+        mesh.index_count = mesh.indices.len() as i32;
+        mesh
+    }
+}
+
+impl Default for Starfield {
+    fn default() -> Starfield {
+        Starfield::new()
+    }
+}
+
+// Everything behind the scene: a procedural starfield, or six images around
+// the camera in its place
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Background {
+    pub starfield: Starfield,
+    // Image files for the faces, in the order of SKYBOX_FACES
+    pub skybox: Option<[String; 6]>,
+}
+
+// Pseudo random numbers that only depend on the seed
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1)
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+// Reads the six faces of a skybox as RGBA8, checking they are square and all
+// the same size. Returns the edge length and the texels of each face.
+pub fn load_skybox_faces(paths: &[String; 6]) -> Result<(u32, Vec<Vec<u8>>), String> {
+    let mut size = None;
+    let mut faces = Vec::with_capacity(6);
+    for path in paths {
+        let image = image::open(path)
+            .map_err(|e| format!("{}: {}", path, e))?
+            .into_rgba8();
+        let (width, height) = image.dimensions();
+        if width != height || size.is_some_and(|size| size != width) {
+            return Err(format!(
+                "{}: skybox faces must be square and the same size, this one is {}x{}",
+                path, width, height
+            ));
+        }
+        size = Some(width);
+        faces.push(image.into_raw());
+    }
+    Ok((size.unwrap_or(0), faces))
+}
+
+// The background on the GPU: the stars as points, and the skybox cube map if
+// there is one. Remade from `Background` when that changes.
+pub struct Sky {
+    background: Background,
+    stars: VertexArray,
+    star_count: i32,
+    skybox: Option<Texture>,
+    // Nothing to read, but drawing needs a vertex array bound
+    empty: VertexArray,
+}
+
+impl Sky {
+    pub unsafe fn new(background: &Background) -> Sky {
+        let mesh = background.starfield.mesh();
+        let mut sky = Sky {
+            background: background.clone(),
+            stars: mesh.create_vao_with_format(&STAR_VERTEX_FORMAT, VertexUpdates::Static),
+            star_count: mesh.index_count,
+            skybox: None,
+            empty: VertexArray::new(),
+        };
+        sky.load_skybox();
+        sky
+    }
+
+    // Catches up with edits to `background`, only redoing what changed
+    pub unsafe fn update(&mut self, background: &Background) {
+        if *background == self.background {
+            return;
+        }
+        let restarred = background.starfield.seed != self.background.starfield.seed
+            || background.starfield.count != self.background.starfield.count
+            || background.starfield.brightness != self.background.starfield.brightness
+            || background.starfield.milky_way != self.background.starfield.milky_way
+            || background.starfield.galactic_pole != self.background.starfield.galactic_pole;
+        let reloaded = background.skybox != self.background.skybox;
+        self.background = background.clone();
+
+        if restarred {
+            let mesh = background.starfield.mesh();
+            self.stars = mesh.create_vao_with_format(&STAR_VERTEX_FORMAT, VertexUpdates::Static);
+            self.star_count = mesh.index_count;
+        }
+        if reloaded {
+            self.load_skybox();
+        }
+    }
+
+    pub unsafe fn bind_stars(&self) -> i32 {
+        self.stars.bind();
+        self.star_count
+    }
+
+    pub unsafe fn bind_empty(&self) {
+        self.empty.bind();
+    }
+
+    // Whether there is a skybox, bound to `unit` if so
+    pub unsafe fn bind_skybox(&self, unit: u32) -> bool {
+        match &self.skybox {
+            Some(texture) => {
+                texture.bind(unit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Faces that can not be read leave the starfield showing, rather than
+    // retrying every frame
+    unsafe fn load_skybox(&mut self) {
+        self.skybox = None;
+        let paths = match &self.background.skybox {
+            Some(paths) => paths,
+            None => return,
+        };
+        match load_skybox_faces(paths) {
+            Ok((size, faces)) => {
+                let texture = Texture::new_cube(gl::RGBA8, size);
+                for (face, texels) in faces.iter().enumerate() {
+                    gl::TextureSubImage3D(
+                        texture.id(),
+                        0,
+                        0,
+                        0,
+                        face as i32,
+                        size as i32,
+                        size as i32,
+                        1,
+                        gl::RGBA,
+                        gl::UNSIGNED_BYTE,
+                        texels.as_ptr() as *const _,
+                    );
+                }
+                self.skybox = Some(texture);
+            }
+            Err(e) => println!("Failed to load skybox: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_seed_gives_the_same_sky() {
+        let starfield = Starfield::new();
+        let stars = starfield.stars();
+        assert_eq!(stars.len(), starfield.count as usize);
+        assert_eq!(stars, starfield.stars());
+
+        let other = Starfield {
+            seed: 2,
+            ..starfield
+        };
+        assert_ne!(stars, other.stars());
+
+        for star in &stars {
+            assert!((glm::length(&star.direction) - 1.0).abs() < 1e-4);
+            assert!(star.magnitude >= BRIGHTEST_MAGNITUDE && star.magnitude <= FAINTEST_MAGNITUDE);
+            assert!(star.temperature >= 3000.0 && star.temperature <= 30000.0);
+        }
+        // Faint stars far outnumber bright ones
+        let bright = stars.iter().filter(|star| star.magnitude < 3.0).count();
+        assert!(bright * 5 < stars.len(), "{} bright stars", bright);
+    }
+
+    #[test]
+    fn stars_gather_in_the_milky_way() {
+        let starfield = Starfield {
+            galactic_pole: glm::vec3(0.0, 0.0, 1.0),
+            ..Starfield::new()
+        };
+        let in_band = |starfield: &Starfield| {
+            starfield
+                .stars()
+                .iter()
+                .filter(|star| star.direction.z.abs() < 0.2)
+                .count()
+        };
+        let without = Starfield {
+            milky_way: false,
+            ..starfield
+        };
+        assert!(in_band(&starfield) > in_band(&without) * 3 / 2);
+    }
+
+    #[test]
+    fn skybox_faces_must_match() {
+        let dir = std::env::temp_dir();
+        let path = |name: &str| {
+            dir.join(format!(
+                "studious-system-sky-{}-{}.png",
+                name,
+                std::process::id()
+            ))
+            .to_str()
+            .unwrap()
+            .to_string()
+        };
+        image::RgbaImage::new(4, 4).save(path("square")).unwrap();
+        image::RgbaImage::new(4, 2).save(path("wide")).unwrap();
+
+        let square: [String; 6] = std::array::from_fn(|_| path("square"));
+        let (size, faces) = load_skybox_faces(&square).unwrap();
+        assert_eq!(size, 4);
+        assert_eq!(faces.len(), 6);
+        assert!(faces.iter().all(|face| face.len() == 4 * 4 * 4));
+
+        let mut mixed = square.clone();
+        mixed[3] = path("wide");
+        assert!(load_skybox_faces(&mixed).is_err());
+        mixed[3] = path("missing");
+        assert!(load_skybox_faces(&mixed).is_err());
+
+        std::fs::remove_file(path("square")).unwrap();
+        std::fs::remove_file(path("wide")).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+// Colours the terrain by elevation. Heights run from 0 at the lowest point the
+// noise can reach (sea level, with `min_value` flattening everything below it)
+// to 1 at the highest.
+pub const MAX_BIOME_STOPS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BiomeStop {
+    pub height: f32,
+    pub color: glm::Vec3,
+    // Surface roughness for physically based shading, blended like the colour
+    pub roughness: f32,
+}
+
+// Without stops the planet keeps the colours of its material
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Biome {
+    pub stops: Vec<BiomeStop>,
+}
+
+impl Biome {
+    pub fn new() -> Biome {
+        Biome { stops: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stops.is_empty()
+    }
+
+    // The stops from the lowest up, whatever order they were edited in
+    fn sorted_stops(&self) -> Vec<BiomeStop> {
+        let mut stops = self.stops.clone();
+        stops.sort_by(|a, b| a.height.total_cmp(&b.height));
+        stops
+    }
+
+    // Same blend as shape.vert: flat below the first and above the last stop,
+    // linear in between
+    fn blend_at(&self, height: f32) -> Option<BiomeStop> {
+        let stops = self.sorted_stops();
+        let mut blended = *stops.first()?;
+        for pair in stops.windows(2) {
+            let (low, high) = (pair[0], pair[1]);
+            let span = (high.height - low.height).max(1e-6);
+            let t = ((height - low.height) / span).clamp(0.0, 1.0);
+            if height >= low.height {
+                blended.color = glm::lerp(&low.color, &high.color, t);
+                blended.roughness = low.roughness + (high.roughness - low.roughness) * t;
+            }
+        }
+        Some(blended)
+    }
+
+    pub fn color_at(&self, height: f32) -> Option<glm::Vec3> {
+        self.blend_at(height).map(|stop| stop.color)
+    }
+
+    pub fn roughness_at(&self, height: f32) -> Option<f32> {
+        self.blend_at(height).map(|stop| stop.roughness)
+    }
+
+    // Stop heights, colours and roughness packed for glUniform1fv / glUniform3fv,
+    // in order of height as shape.vert expects
+    pub fn uniform_arrays(&self) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+        let stops = self.sorted_stops();
+        let stops = &stops[..stops.len().min(MAX_BIOME_STOPS)];
+        let heights = stops.iter().map(|stop| stop.height).collect();
+        let colors = stops
+            .iter()
+            .flat_map(|stop| stop.color.iter().copied())
+            .collect();
+        let roughness = stops.iter().map(|stop| stop.roughness).collect();
+        (heights, colors, roughness)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn biome() -> Biome {
+        Biome {
+            stops: vec![
+                BiomeStop {
+                    height: 0.0,
+                    color: glm::vec3(0.0, 0.0, 1.0),
+                    roughness: 0.2,
+                },
+                BiomeStop {
+                    height: 0.5,
+                    color: glm::vec3(0.0, 1.0, 0.0),
+                    roughness: 0.6,
+                },
+                BiomeStop {
+                    height: 1.0,
+                    color: glm::vec3(1.0, 1.0, 1.0),
+                    roughness: 0.6,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn blends_between_stops() {
+        let biome = biome();
+        assert_eq!(biome.color_at(-1.0), Some(glm::vec3(0.0, 0.0, 1.0)));
+        assert_eq!(biome.color_at(0.25), Some(glm::vec3(0.0, 0.5, 0.5)));
+        assert_eq!(biome.color_at(0.75), Some(glm::vec3(0.5, 1.0, 0.5)));
+        assert_eq!(biome.color_at(2.0), Some(glm::vec3(1.0, 1.0, 1.0)));
+        assert_eq!(Biome::new().color_at(0.5), None);
+
+        assert_eq!(biome.roughness_at(-1.0), Some(0.2));
+        assert!((biome.roughness_at(0.25).unwrap() - 0.4).abs() < 1e-6);
+        assert_eq!(biome.roughness_at(0.75), Some(0.6));
+    }
+
+    #[test]
+    fn stops_blend_in_order_of_height() {
+        let sorted = biome();
+        let mut shuffled = sorted.clone();
+        shuffled.stops.swap(0, 2);
+        assert_eq!(shuffled.uniform_arrays(), sorted.uniform_arrays());
+        for height in [-1.0, 0.25, 0.5, 0.75, 2.0] {
+            assert_eq!(shuffled.color_at(height), sorted.color_at(height));
+            assert_eq!(shuffled.roughness_at(height), sorted.roughness_at(height));
+        }
+    }
+}
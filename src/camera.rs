@@ -0,0 +1,333 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum CameraMode {
+    // Circles a focus point, usually a planet, at `distance`
+    Orbit,
+    // Flies with six degrees of freedom, turning with the mouse or keyboard
+    Free,
+}
+
+// How depth is spread over the depth buffer. Both alternatives to the standard
+// mapping keep precision far from the camera, so a nearby surface and a distant
+// star can share a frame without z-fighting.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum DepthMode {
+    Standard,
+    // Near maps to 1 and far to 0 in a floating point depth buffer
+    ReversedZ,
+    // Fragments write a depth logarithmic in their distance
+    Logarithmic,
+}
+
+// A blend from where the orbit focus was to where it is going, so switching
+// targets glides over instead of jumping
+struct Transition {
+    from_focus: glm::DVec3,
+    from_distance: f32,
+    to_distance: f32,
+    elapsed: f32,
+}
+
+pub struct Camera {
+    // Double precision like the planets. Everything is drawn relative to the
+    // camera, which keeps the numbers the GPU sees small near it.
+    pub position: glm::DVec3,
+    // Rotation from camera space, looking down -z with +y up, to world space
+    pub orientation: glm::Quat,
+    // Vertical field of view in radians
+    pub fov: f32,
+    pub mode: CameraMode,
+    pub near: f32,
+    pub far: f32,
+    pub depth_mode: DepthMode,
+
+    // Index of the planet the orbit follows
+    pub target: Option<usize>,
+    pub focus: glm::DVec3,
+    pub distance: f32,
+    // Seconds a switch between targets takes
+    pub transition_time: f32,
+    // Free mode speed as a multiple of the altitude above the nearest surface,
+    // per second, so flying slows down on the way in to land
+    pub speed: f32,
+
+    orbit_yaw: f32,
+    orbit_pitch: f32,
+    transition: Option<Transition>,
+}
+
+impl Camera {
+    pub fn new(position: glm::DVec3) -> Camera {
+        Camera {
+            position,
+            orientation: glm::quat_identity(),
+            fov: glm::half_pi(),
+            mode: CameraMode::Free,
+            near: 0.01,
+            far: 100_000.0,
+            depth_mode: DepthMode::ReversedZ,
+            target: None,
+            focus: glm::DVec3::new(0.0, 0.0, 0.0),
+            distance: 5.0,
+            transition_time: 1.5,
+            speed: 1.0,
+            orbit_yaw: 0.0,
+            orbit_pitch: 0.0,
+            transition: None,
+        }
+    }
+
+    pub fn forward(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &glm::vec3(0.0, 0.0, -1.0))
+    }
+
+    pub fn right(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &glm::vec3(1.0, 0.0, 0.0))
+    }
+
+    pub fn up(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &glm::vec3(0.0, 1.0, 0.0))
+    }
+
+    // Only turns the world, as it is already positioned relative to the camera
+    pub fn view_matrix(&self) -> glm::Mat4 {
+        glm::quat_to_mat4(&glm::quat_conjugate(&self.orientation))
+    }
+
+    pub fn projection_matrix(&self, aspect_ratio: f32) -> glm::Mat4 {
+        match self.depth_mode {
+            // Swapping the planes of a zero-to-one projection reverses it
+            DepthMode::ReversedZ => {
+                glm::perspective_rh_zo(aspect_ratio, self.fov, self.far, self.near)
+            }
+            DepthMode::Standard | DepthMode::Logarithmic => {
+                glm::perspective(aspect_ratio, self.fov, self.near, self.far)
+            }
+        }
+    }
+
+    // Scale for the logarithmic depth the shaders write, 0 to leave depth alone
+    pub fn log_depth_coefficient(&self) -> f32 {
+        match self.depth_mode {
+            DepthMode::Logarithmic => 1.0 / (self.far + 1.0).log2(),
+            DepthMode::Standard | DepthMode::ReversedZ => 0.0,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        if mode == CameraMode::Orbit && self.mode != CameraMode::Orbit {
+            // Keep looking at whatever is straight ahead
+            self.focus = self.position + self.forward().cast() * self.distance as f64;
+            self.match_orbit_angles();
+        }
+        self.mode = mode;
+    }
+
+    // Starts gliding over to `target`, ending up `distance` away from its centre
+    pub fn follow(&mut self, target: Option<usize>, distance: f32) {
+        self.target = target;
+        self.transition = Some(Transition {
+            from_focus: self.focus,
+            from_distance: self.distance,
+            to_distance: distance,
+            elapsed: 0.0,
+        });
+    }
+
+    // Turns the orbit by the given angles in radians, e.g. from a mouse drag
+    pub fn rotate_orbit(&mut self, yaw: f32, pitch: f32) {
+        let limit = glm::half_pi::<f32>() - 0.01;
+        self.orbit_yaw += yaw;
+        self.orbit_pitch = (self.orbit_pitch + pitch).clamp(-limit, limit);
+    }
+
+    // Each step moves a tenth of the way in, negative steps move out
+    pub fn zoom(&mut self, steps: f32) {
+        let factor = 0.9_f32.powf(steps);
+        match &mut self.transition {
+            Some(transition) => transition.to_distance *= factor,
+            None => self.distance *= factor,
+        }
+    }
+
+    // Free mode: `movement` is in camera space and `rotation` holds the pitch,
+    // yaw and roll in radians around the camera's own axes
+    pub fn fly(&mut self, movement: glm::Vec3, rotation: glm::Vec3) {
+        self.position += glm::quat_rotate_vec3(&self.orientation, &movement).cast();
+        let angle = glm::length(&rotation);
+        if angle > 0.0 {
+            self.orientation = glm::quat_normalize(
+                &(self.orientation * glm::quat_angle_axis(angle, &(rotation / angle))),
+            );
+        }
+    }
+
+    // Distance to the closest surface of the given spheres, as (centre, radius)
+    // pairs, or None without any. Zero once inside one.
+    pub fn altitude(&self, bodies: impl IntoIterator<Item = (glm::DVec3, f64)>) -> Option<f64> {
+        bodies
+            .into_iter()
+            .map(|(centre, radius)| (glm::distance(&self.position, &centre) - radius).max(0.0))
+            .reduce(f64::min)
+    }
+
+    // Advances any transition and places the orbit camera. `target_position` is
+    // where the followed planet currently is, if there is one.
+    pub fn update(&mut self, delta_time: f32, target_position: Option<glm::DVec3>) {
+        let goal = target_position.unwrap_or(self.focus);
+
+        match &mut self.transition {
+            Some(transition) => {
+                transition.elapsed += delta_time;
+                let t = (transition.elapsed / self.transition_time.max(1e-6)).min(1.0);
+                // Ease in and out
+                let s = t * t * (3.0 - 2.0 * t);
+                self.focus = glm::lerp(&transition.from_focus, &goal, s as f64);
+                self.distance = transition.from_distance
+                    + (transition.to_distance - transition.from_distance) * s;
+                if t >= 1.0 {
+                    self.transition = None;
+                }
+            }
+            None => self.focus = goal,
+        }
+
+        if self.mode == CameraMode::Orbit {
+            self.orientation = glm::quat_angle_axis(self.orbit_yaw, &glm::vec3(0.0, 1.0, 0.0))
+                * glm::quat_angle_axis(self.orbit_pitch, &glm::vec3(1.0, 0.0, 0.0));
+            self.position = self.focus
+                + glm::quat_rotate_vec3(&self.orientation, &glm::vec3(0.0, 0.0, self.distance))
+                    .cast();
+        }
+    }
+
+    // Picks orbit angles that reproduce the current view direction
+    fn match_orbit_angles(&mut self) {
+        let forward = self.forward();
+        self.orbit_pitch = forward.y.clamp(-1.0, 1.0).asin();
+        self.orbit_yaw = (-forward.x).atan2(-forward.z);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: glm::Vec3, b: glm::Vec3) {
+        assert!(glm::distance(&a, &b) < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    fn assert_near(a: glm::DVec3, b: glm::DVec3) {
+        assert!(glm::distance(&a, &b) < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn orbit_looks_at_the_focus() {
+        let mut camera = Camera::new(glm::DVec3::new(0.0, 0.0, 0.0));
+        camera.set_mode(CameraMode::Orbit);
+        camera.focus = glm::DVec3::new(1.0, 2.0, 3.0);
+        camera.rotate_orbit(0.7, -0.3);
+        camera.update(0.016, None);
+
+        let offset = camera.focus - camera.position;
+        assert!((glm::length(&offset) - camera.distance as f64).abs() < 1e-4);
+        let in_view = camera.view_matrix() * offset.cast::<f32>().push(1.0);
+        assert_close(in_view.xyz(), glm::vec3(0.0, 0.0, -camera.distance));
+    }
+
+    #[test]
+    fn follows_a_moving_target_after_the_transition() {
+        let mut camera = Camera::new(glm::DVec3::new(0.0, 0.0, 5.0));
+        camera.set_mode(CameraMode::Orbit);
+        camera.follow(Some(1), 2.0);
+
+        let halfway = camera.transition_time / 2.0;
+        camera.update(halfway, Some(glm::DVec3::new(10.0, 0.0, 0.0)));
+        assert_near(camera.focus, glm::DVec3::new(5.0, 0.0, 0.0));
+        assert!((camera.distance - 3.5).abs() < 1e-4);
+
+        camera.update(halfway, Some(glm::DVec3::new(10.0, 0.0, 0.0)));
+        camera.update(0.1, Some(glm::DVec3::new(12.0, 1.0, 0.0)));
+        assert_near(camera.focus, glm::DVec3::new(12.0, 1.0, 0.0));
+        assert_eq!(camera.distance, 2.0);
+    }
+
+    #[test]
+    fn switching_to_orbit_keeps_the_view() {
+        let mut camera = Camera::new(glm::DVec3::new(0.0, 1.0, 5.0));
+        camera.fly(glm::vec3(0.0, 0.0, 0.0), glm::vec3(0.0, 0.4, 0.0));
+        camera.fly(glm::vec3(0.0, 0.0, 0.0), glm::vec3(-0.2, 0.0, 0.0));
+        let forward = camera.forward();
+
+        camera.set_mode(CameraMode::Orbit);
+        camera.update(0.016, None);
+        assert_close(camera.forward(), forward);
+        assert_near(camera.position, glm::DVec3::new(0.0, 1.0, 5.0));
+    }
+
+    #[test]
+    fn flies_along_its_own_axes() {
+        let mut camera = Camera::new(glm::DVec3::new(0.0, 0.0, 0.0));
+        // A quarter roll puts the camera's right where up used to be
+        camera.fly(
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(0.0, 0.0, glm::half_pi()),
+        );
+        assert_close(camera.right(), glm::vec3(0.0, 1.0, 0.0));
+
+        // Yawing left now turns towards the camera's left, which is world down
+        camera.fly(
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(0.0, glm::half_pi(), 0.0),
+        );
+        assert_close(camera.forward(), glm::vec3(0.0, -1.0, 0.0));
+
+        camera.fly(glm::vec3(0.0, 0.0, -2.0), glm::vec3(0.0, 0.0, 0.0));
+        assert_near(camera.position, glm::DVec3::new(0.0, -2.0, 0.0));
+    }
+
+    #[test]
+    fn small_steps_register_far_from_the_origin() {
+        let start = glm::DVec3::new(1.5e11, 0.0, 0.0);
+        let mut camera = Camera::new(start);
+        camera.fly(glm::vec3(0.0, 0.0, -0.001), glm::vec3(0.0, 0.0, 0.0));
+        assert_near(camera.position - start, glm::DVec3::new(0.0, 0.0, -0.001));
+    }
+
+    #[test]
+    fn altitude_is_measured_to_the_nearest_surface() {
+        let camera = Camera::new(glm::DVec3::new(0.0, 0.0, 10.0));
+        assert_eq!(camera.altitude([]), None);
+
+        let bodies = [
+            (glm::DVec3::new(0.0, 0.0, 0.0), 4.0),
+            (glm::DVec3::new(0.0, 3.0, 10.0), 1.0),
+        ];
+        assert_eq!(camera.altitude(bodies), Some(2.0));
+        assert_eq!(
+            camera.altitude([(glm::DVec3::new(0.0, 0.0, 9.0), 5.0)]),
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn reversed_z_puts_near_at_one() {
+        let mut camera = Camera::new(glm::DVec3::new(0.0, 0.0, 0.0));
+        camera.near = 0.5;
+        camera.far = 1.0e6;
+        let depth = |camera: &Camera, distance: f32| {
+            let clip = camera.projection_matrix(1.0) * glm::vec4(0.0, 0.0, -distance, 1.0);
+            clip.z / clip.w
+        };
+
+        camera.depth_mode = DepthMode::ReversedZ;
+        assert!((depth(&camera, camera.near) - 1.0).abs() < 1e-5);
+        assert!(depth(&camera, camera.far).abs() < 1e-5);
+        assert!(depth(&camera, 10.0) > depth(&camera, 11.0));
+
+        camera.depth_mode = DepthMode::Standard;
+        assert!((depth(&camera, camera.near) + 1.0).abs() < 1e-5);
+        assert!((depth(&camera, camera.far) - 1.0).abs() < 1e-3);
+    }
+}
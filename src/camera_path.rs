@@ -0,0 +1,202 @@
+use crate::camera::Camera;
+
+// A recorded camera pose, `time` seconds into the path
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    pub time: f32,
+    pub position: glm::DVec3,
+    pub orientation: glm::Quat,
+    pub fov: f32,
+}
+
+impl Keyframe {
+    pub fn capture(time: f32, camera: &Camera) -> Keyframe {
+        Keyframe {
+            time,
+            position: camera.position,
+            orientation: camera.orientation,
+            fov: camera.fov,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.position = self.position;
+        camera.orientation = self.orientation;
+        camera.fov = self.fov;
+    }
+}
+
+// Keyframes sorted by time. Positions and field of view follow a Catmull-Rom
+// spline through them and orientations are slerped, so a flythrough passes
+// every keyframe without kinks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn new() -> CameraPath {
+        CameraPath::default()
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    // Time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    // Adds `keyframe` in time order, replacing one at the same time
+    pub fn insert(&mut self, keyframe: Keyframe) {
+        match self
+            .keyframes
+            .binary_search_by(|k| k.time.total_cmp(&keyframe.time))
+        {
+            Ok(i) => self.keyframes[i] = keyframe,
+            Err(i) => self.keyframes.insert(i, keyframe),
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Keyframe {
+        self.keyframes.remove(index)
+    }
+
+    // Camera pose at `time`, held at the ends. None for an empty path.
+    pub fn sample(&self, time: f32) -> Option<Keyframe> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        if time <= first.time {
+            return Some(*first);
+        }
+        if time >= last.time {
+            return Some(*last);
+        }
+
+        // The segment from keyframe i to i + 1, with the neighbours on either
+        // side shaping the curve. The ends repeat to stand in for missing ones.
+        let i = self.keyframes.partition_point(|k| k.time <= time) - 1;
+        let k0 = &self.keyframes[i.saturating_sub(1)];
+        let k1 = &self.keyframes[i];
+        let k2 = &self.keyframes[i + 1];
+        let k3 = &self.keyframes[(i + 2).min(self.keyframes.len() - 1)];
+        let t = (time - k1.time) / (k2.time - k1.time);
+
+        // Take the short way around
+        let mut to = k2.orientation;
+        if glm::quat_dot(&k1.orientation, &to) < 0.0 {
+            to = -to;
+        }
+
+        Some(Keyframe {
+            time,
+            position: catmull_rom(k0.position, k1.position, k2.position, k3.position, t as f64),
+            orientation: glm::quat_normalize(&glm::quat_slerp(&k1.orientation, &to, t)),
+            fov: catmull_rom(
+                glm::vec1(k0.fov as f64),
+                glm::vec1(k1.fov as f64),
+                glm::vec1(k2.fov as f64),
+                glm::vec1(k3.fov as f64),
+                t as f64,
+            )
+            .x as f32,
+        })
+    }
+}
+
+// Uniform Catmull-Rom between `p1` and `p2`
+fn catmull_rom<const D: usize>(
+    p0: glm::TVec<f64, D>,
+    p1: glm::TVec<f64, D>,
+    p2: glm::TVec<f64, D>,
+    p3: glm::TVec<f64, D>,
+    t: f64,
+) -> glm::TVec<f64, D> {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, position: glm::DVec3, yaw: f32) -> Keyframe {
+        Keyframe {
+            time,
+            position,
+            orientation: glm::quat_angle_axis(yaw, &glm::vec3(0.0, 1.0, 0.0)),
+            fov: 1.0 + time,
+        }
+    }
+
+    fn assert_close(a: glm::DVec3, b: glm::DVec3) {
+        assert!(glm::distance(&a, &b) < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn passes_through_every_keyframe() {
+        let mut path = CameraPath::new();
+        path.insert(keyframe(2.0, glm::DVec3::new(4.0, 1.0, 0.0), 1.0));
+        path.insert(keyframe(0.0, glm::DVec3::new(0.0, 0.0, 0.0), 0.0));
+        path.insert(keyframe(3.0, glm::DVec3::new(5.0, -2.0, 3.0), -0.5));
+        path.insert(keyframe(1.0, glm::DVec3::new(1.0, 2.0, 0.0), 0.5));
+        assert_eq!(path.duration(), 3.0);
+
+        for keyframe in path.keyframes() {
+            let sample = path.sample(keyframe.time).unwrap();
+            assert_close(sample.position, keyframe.position);
+            assert!((sample.fov - keyframe.fov).abs() < 1e-5);
+            assert!(glm::quat_dot(&sample.orientation, &keyframe.orientation).abs() > 0.9999);
+        }
+        assert_eq!(path.sample(-1.0), path.keyframes().first().copied());
+        assert_eq!(path.sample(10.0), path.keyframes().last().copied());
+    }
+
+    #[test]
+    fn straight_evenly_spaced_keyframes_give_steady_motion() {
+        let mut path = CameraPath::new();
+        for i in 0..4 {
+            path.insert(keyframe(
+                i as f32,
+                glm::DVec3::new(i as f64 * 2.0, 0.0, 0.0),
+                0.0,
+            ));
+        }
+        let sample = path.sample(1.25).unwrap();
+        assert_close(sample.position, glm::DVec3::new(2.5, 0.0, 0.0));
+        assert!((sample.fov - 2.25).abs() < 1e-5);
+    }
+
+    #[test]
+    fn turns_the_short_way() {
+        let mut path = CameraPath::new();
+        path.insert(keyframe(0.0, glm::DVec3::new(0.0, 0.0, 0.0), 0.0));
+        let mut end = keyframe(1.0, glm::DVec3::new(0.0, 0.0, 0.0), 0.5);
+        end.orientation = -end.orientation;
+        path.insert(end);
+
+        let halfway = path.sample(0.5).unwrap().orientation;
+        let expected = glm::quat_angle_axis(0.25, &glm::vec3(0.0, 1.0, 0.0));
+        assert!(glm::quat_dot(&halfway, &expected).abs() > 0.9999);
+    }
+
+    #[test]
+    fn inserting_at_the_same_time_replaces() {
+        let mut path = CameraPath::new();
+        path.insert(keyframe(1.0, glm::DVec3::new(0.0, 0.0, 0.0), 0.0));
+        path.insert(keyframe(1.0, glm::DVec3::new(1.0, 0.0, 0.0), 0.0));
+        assert_eq!(path.keyframes().len(), 1);
+        assert_eq!(path.remove(0).position, glm::DVec3::new(1.0, 0.0, 0.0));
+        assert!(path.is_empty());
+    }
+}
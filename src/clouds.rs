@@ -0,0 +1,103 @@
+use crate::noise::Noise;
+
+// Width of the soft edge around each cloud, in noise elevation
+const EDGE: f32 = 0.15;
+
+// A layer of cloud wrapped around a planet just above its surface, drawn by
+// clouds.frag and optionally darkening the terrain under it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clouds {
+    // Height of the layer above the surface, in planet radii
+    pub altitude: f32,
+    // Share of the sky that is clouded over, from 0 for clear to 1 for overcast
+    pub coverage: f32,
+    // Opacity of the thickest clouds
+    pub density: f32,
+    pub color: glm::Vec3,
+    // Radians per second the layer drifts around the spin axis at the equator,
+    // on top of the planet's own spin
+    pub wind_speed: f32,
+    // Pattern of the clouds, scaled to [0, 1] like terrain elevation
+    pub noise: Noise,
+    // Whether the clouds shade the terrain below
+    pub shadows: bool,
+}
+
+impl Clouds {
+    pub fn new() -> Clouds {
+        Clouds {
+            altitude: 0.02,
+            coverage: 0.45,
+            density: 0.9,
+            color: glm::vec3(1.0, 1.0, 1.0),
+            wind_speed: 0.05,
+            noise: Noise {
+                base_roughness: 2.0,
+                num_layers: 5,
+                ..Noise::new()
+            },
+            shadows: true,
+        }
+    }
+
+    // How far the layer has turned at the equator after `time` seconds
+    pub fn drift(&self, time: f32) -> f32 {
+        (self.wind_speed * time).rem_euclid(std::f32::consts::TAU)
+    }
+
+    // Opacity of the layer in `direction` from the planet's centre, in the
+    // planet's own frame. Same as `cloud_cover` in noise.glsl.
+    pub fn cover(&self, direction: &glm::Vec3, time: f32) -> f32 {
+        let direction = direction.normalize();
+        let angle = self.drift(time) * (1.0 - 0.5 * direction.y * direction.y);
+        let turned = glm::rotate_y_vec3(&direction, angle);
+
+        // Spread so no coverage leaves even the highest noise clear, and full
+        // coverage clouds over even the lowest
+        let threshold = (1.0 - self.coverage) * (1.0 + EDGE) - EDGE;
+        let t = ((self.noise.elevation(&turned) - threshold) / EDGE).clamp(0.0, 1.0);
+        self.density * t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Default for Clouds {
+    fn default() -> Clouds {
+        Clouds::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directions() -> impl Iterator<Item = glm::Vec3> {
+        (0..200).map(|i| {
+            let i = i as f32;
+            glm::vec3((i * 0.37).sin(), (i * 0.11).cos(), (i * 0.73).sin())
+        })
+    }
+
+    #[test]
+    fn coverage_runs_from_clear_to_overcast() {
+        let mut clouds = Clouds::new();
+        clouds.coverage = 0.0;
+        assert!(directions().all(|d| clouds.cover(&d, 0.0) == 0.0));
+
+        clouds.coverage = 1.0;
+        assert!(directions().all(|d| clouds.cover(&d, 0.0) == clouds.density));
+
+        clouds.coverage = 0.5;
+        let clouded = directions().filter(|d| clouds.cover(d, 0.0) > 0.5).count();
+        assert!(clouded > 20 && clouded < 180, "{} of 200 clouded", clouded);
+    }
+
+    #[test]
+    fn wind_moves_the_pattern_along() {
+        let clouds = Clouds::new();
+        let direction = glm::vec3(1.0, 0.0, 0.0);
+        // A quarter turn at the equator lines up what was a quarter turn ahead
+        let time = std::f32::consts::FRAC_PI_2 / clouds.wind_speed;
+        let ahead = glm::rotate_y_vec3(&direction, std::f32::consts::FRAC_PI_2);
+        assert!((clouds.cover(&direction, time) - clouds.cover(&ahead, 0.0)).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,385 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::mesh::Mesh;
+
+pub enum MeshFormat {
+    Obj,
+    Ply,
+    Stl,
+    Glb,
+}
+
+impl MeshFormat {
+    fn from_ext(ext: &std::ffi::OsStr) -> Result<MeshFormat, String> {
+        match ext.to_str() {
+            Some("obj") => Ok(MeshFormat::Obj),
+            Some("ply") => Ok(MeshFormat::Ply),
+            Some("stl") => Ok(MeshFormat::Stl),
+            Some("glb") => Ok(MeshFormat::Glb),
+            _ => Err(ext.to_string_lossy().into_owned()),
+        }
+    }
+}
+
+impl Mesh {
+    // Writes the mesh to disk, picking the format from the file extension
+    pub fn export(&self, path: &str) -> io::Result<()> {
+        let format = Path::new(path)
+            .extension()
+            .ok_or_else(|| format!("Missing file extension: {}", path))
+            .and_then(MeshFormat::from_ext)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let mut out = BufWriter::new(File::create(path)?);
+        match format {
+            MeshFormat::Obj => self.write_obj(&mut out)?,
+            MeshFormat::Ply => self.write_ply(&mut out)?,
+            MeshFormat::Stl => self.write_stl(&mut out)?,
+            MeshFormat::Glb => self.write_glb(&mut out)?,
+        }
+        out.flush()
+    }
+
+    pub fn write_obj<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "# studious-system")?;
+        writeln!(out, "o planet")?;
+        for v in self.vertices.chunks(3) {
+            writeln!(out, "v {} {} {}", v[0], v[1], v[2])?;
+        }
+        for n in self.normals.chunks(3) {
+            writeln!(out, "vn {} {} {}", n[0], n[1], n[2])?;
+        }
+        // OBJ indices are 1-based, and every vertex has a normal with the same index
+        for f in self.indices.chunks(3) {
+            writeln!(
+                out,
+                "f {a}//{a} {b}//{b} {c}//{c}",
+                a = f[0] + 1,
+                b = f[1] + 1,
+                c = f[2] + 1
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn write_ply<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "ply")?;
+        writeln!(out, "format binary_little_endian 1.0")?;
+        writeln!(out, "comment studious-system")?;
+        writeln!(out, "element vertex {}", self.vertex_count())?;
+        for property in ["x", "y", "z", "nx", "ny", "nz"] {
+            writeln!(out, "property float {}", property)?;
+        }
+        for property in ["red", "green", "blue"] {
+            writeln!(out, "property uchar {}", property)?;
+        }
+        writeln!(out, "element face {}", self.indices.len() / 3)?;
+        writeln!(out, "property list uchar uint vertex_indices")?;
+        writeln!(out, "end_header")?;
+
+        for i in 0..self.vertex_count() {
+            for value in self.vertices[i * 3..i * 3 + 3]
+                .iter()
+                .chain(&self.normals[i * 3..i * 3 + 3])
+            {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            for channel in &self.material.diffuse[i * 3..i * 3 + 3] {
+                out.write_all(&[(channel.clamp(0.0, 1.0) * 255.0).round() as u8])?;
+            }
+        }
+
+        for f in self.indices.chunks(3) {
+            out.write_all(&[3])?;
+            for index in f {
+                out.write_all(&index.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_stl<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut header = [0u8; 80];
+        let name = b"studious-system";
+        header[..name.len()].copy_from_slice(name);
+        out.write_all(&header)?;
+        out.write_all(&((self.indices.len() / 3) as u32).to_le_bytes())?;
+
+        for f in self.indices.chunks(3) {
+            let [a, b, c] = [f[0], f[1], f[2]].map(|i| self.position(i as usize));
+
+            // STL stores one normal per facet, so use the geometric one
+            let normal = glm::cross(&(b - a), &(c - a));
+            let normal = if normal.norm() > 0.0 {
+                normal.normalize()
+            } else {
+                normal
+            };
+
+            for vector in [normal, a, b, c] {
+                for value in vector.iter() {
+                    out.write_all(&value.to_le_bytes())?;
+                }
+            }
+            out.write_all(&0u16.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn write_glb<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let vertex_count = self.vertex_count();
+
+        // Binary chunk layout: positions, normals, colours, indices
+        let mut bin: Vec<u8> = Vec::new();
+        for value in self
+            .vertices
+            .iter()
+            .chain(&self.normals)
+            .chain(&self.material.diffuse)
+        {
+            bin.extend_from_slice(&value.to_le_bytes());
+        }
+        for index in &self.indices {
+            bin.extend_from_slice(&index.to_le_bytes());
+        }
+        let buffer_length = bin.len();
+        pad_to_four(&mut bin, 0);
+
+        let attribute_length = vertex_count * 3 * 4;
+        let (min, max) = self.bounds();
+
+        let mut json = format!(
+            concat!(
+                r#"{{"asset":{{"version":"2.0","generator":"studious-system"}},"#,
+                r#""scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0,"name":"planet"}}],"#,
+                r#""meshes":[{{"primitives":[{{"attributes":{{"POSITION":0,"NORMAL":1,"COLOR_0":2}},"indices":3,"mode":4}}]}}],"#,
+                r#""buffers":[{{"byteLength":{buffer_length}}}],"#,
+                r#""bufferViews":["#,
+                r#"{{"buffer":0,"byteOffset":0,"byteLength":{attribute_length},"target":34962}},"#,
+                r#"{{"buffer":0,"byteOffset":{normal_offset},"byteLength":{attribute_length},"target":34962}},"#,
+                r#"{{"buffer":0,"byteOffset":{color_offset},"byteLength":{attribute_length},"target":34962}},"#,
+                r#"{{"buffer":0,"byteOffset":{index_offset},"byteLength":{index_length},"target":34963}}],"#,
+                r#""accessors":["#,
+                r#"{{"bufferView":0,"componentType":5126,"count":{vertex_count},"type":"VEC3","min":[{min}],"max":[{max}]}},"#,
+                r#"{{"bufferView":1,"componentType":5126,"count":{vertex_count},"type":"VEC3"}},"#,
+                r#"{{"bufferView":2,"componentType":5126,"count":{vertex_count},"type":"VEC3"}},"#,
+                r#"{{"bufferView":3,"componentType":5125,"count":{index_count},"type":"SCALAR"}}]}}"#,
+            ),
+            buffer_length = buffer_length,
+            attribute_length = attribute_length,
+            normal_offset = attribute_length,
+            color_offset = attribute_length * 2,
+            index_offset = attribute_length * 3,
+            index_length = self.indices.len() * 4,
+            vertex_count = vertex_count,
+            index_count = self.indices.len(),
+            min = format!("{},{},{}", min.x, min.y, min.z),
+            max = format!("{},{},{}", max.x, max.y, max.z),
+        )
+        .into_bytes();
+        pad_to_four(&mut json, b' ');
+
+        let total_length = 12 + 8 + json.len() + 8 + bin.len();
+        out.write_all(b"glTF")?;
+        out.write_all(&2u32.to_le_bytes())?;
+        out.write_all(&(total_length as u32).to_le_bytes())?;
+
+        out.write_all(&(json.len() as u32).to_le_bytes())?;
+        out.write_all(b"JSON")?;
+        out.write_all(&json)?;
+
+        out.write_all(&(bin.len() as u32).to_le_bytes())?;
+        out.write_all(b"BIN\0")?;
+        out.write_all(&bin)?;
+        Ok(())
+    }
+
+    fn vertex_count(&self) -> usize {
+        self.vertices.len() / 3
+    }
+
+    fn position(&self, index: usize) -> glm::Vec3 {
+        glm::vec3(
+            self.vertices[index * 3],
+            self.vertices[index * 3 + 1],
+            self.vertices[index * 3 + 2],
+        )
+    }
+
+    fn bounds(&self) -> (glm::Vec3, glm::Vec3) {
+        let mut min = glm::vec3(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = glm::vec3(f32::MIN, f32::MIN, f32::MIN);
+        for i in 0..self.vertex_count() {
+            let position = self.position(i);
+            min = glm::min2(&min, &position);
+            max = glm::max2(&max, &position);
+        }
+        (min, max)
+    }
+}
+
+fn pad_to_four(bytes: &mut Vec<u8>, padding: u8) {
+    while !bytes.len().is_multiple_of(4) {
+        bytes.push(padding);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Read};
+
+    use crate::biome::Biome;
+    use crate::material::Material;
+    use crate::noise::Noise;
+    use crate::sphere::Sphere;
+
+    use super::*;
+
+    fn test_mesh() -> Mesh {
+        Sphere::new(2, Material::new()).displaced_mesh(&Noise::new(), &Biome::new())
+    }
+
+    fn export_to_temp(mesh: &Mesh, name: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("studious-system-{}-{}", std::process::id(), name));
+        mesh.export(path.to_str().unwrap()).unwrap();
+        path
+    }
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn obj_round_trip() {
+        let mesh = test_mesh();
+        let path = export_to_temp(&mesh, "planet.obj");
+
+        let (mut positions, mut normals, mut indices) = (0, 0, 0);
+        for line in io::BufReader::new(File::open(&path).unwrap()).lines() {
+            let line = line.unwrap();
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("v") => positions += 1,
+                Some("vn") => normals += 1,
+                Some("f") => {
+                    for corner in parts {
+                        let index: usize = corner.split('/').next().unwrap().parse().unwrap();
+                        assert!(index >= 1 && index <= mesh.vertex_count());
+                        indices += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(positions, mesh.vertex_count());
+        assert_eq!(normals, mesh.vertex_count());
+        assert_eq!(indices, mesh.indices.len());
+    }
+
+    #[test]
+    fn ply_round_trip() {
+        let mesh = test_mesh();
+        let path = export_to_temp(&mesh, "planet.ply");
+
+        let mut reader = io::BufReader::new(File::open(&path).unwrap());
+        let (mut vertex_count, mut face_count) = (0, 0);
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                ["element", "vertex", n] => vertex_count = n.parse().unwrap(),
+                ["element", "face", n] => face_count = n.parse().unwrap(),
+                ["end_header"] => break,
+                _ => {}
+            }
+        }
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(vertex_count, mesh.vertex_count());
+        assert_eq!(face_count * 3, mesh.indices.len());
+
+        // 6 floats and 3 colour bytes per vertex, a count byte and 3 indices per face
+        let face_offset = vertex_count * (6 * 4 + 3);
+        assert_eq!(body.len(), face_offset + face_count * (1 + 3 * 4));
+        assert_eq!(body[24..27], [255, 128, 79]);
+        for face in 0..face_count {
+            let offset = face_offset + face * 13;
+            assert_eq!(body[offset], 3);
+            for corner in 0..3 {
+                let index = read_u32(&body, offset + 1 + corner * 4);
+                assert_eq!(index, mesh.indices[face * 3 + corner]);
+            }
+        }
+    }
+
+    #[test]
+    fn stl_round_trip() {
+        let mesh = test_mesh();
+        let path = export_to_temp(&mesh, "planet.stl");
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let triangle_count = read_u32(&bytes, 80) as usize;
+        assert_eq!(triangle_count * 3, mesh.indices.len());
+        assert_eq!(bytes.len(), 84 + triangle_count * 50);
+
+        // STL has no index buffer, so every corner is a vertex of its own
+        let first_corner = &bytes[84 + 12..84 + 24];
+        let expected = mesh.position(mesh.indices[0] as usize);
+        for (i, chunk) in first_corner.chunks(4).enumerate() {
+            assert_eq!(f32::from_le_bytes(chunk.try_into().unwrap()), expected[i]);
+        }
+    }
+
+    #[test]
+    fn glb_round_trip() {
+        let mesh = test_mesh();
+        let path = export_to_temp(&mesh, "planet.glb");
+
+        let (document, buffers, _) = gltf::import(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+        let positions: Vec<[f32; 3]> = reader.read_positions().unwrap().collect();
+        let normals: Vec<[f32; 3]> = reader.read_normals().unwrap().collect();
+        let colors: Vec<[f32; 3]> = reader.read_colors(0).unwrap().into_rgb_f32().collect();
+        let indices: Vec<u32> = reader.read_indices().unwrap().into_u32().collect();
+
+        assert_eq!(positions.len(), mesh.vertex_count());
+        assert_eq!(normals.len(), mesh.vertex_count());
+        assert_eq!(colors.len(), mesh.vertex_count());
+        assert_eq!(indices, mesh.indices);
+    }
+
+    #[test]
+    fn unknown_extension_is_rejected() {
+        let mesh = test_mesh();
+        let error = mesh.export("planet.fbx").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_extension_is_rejected() {
+        use std::os::unix::ffi::OsStrExt;
+        let extension = std::ffi::OsStr::from_bytes(b"ob\xffj");
+        assert!(MeshFormat::from_ext(extension).is_err());
+    }
+}
@@ -0,0 +1,519 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::utils;
+
+// Number of GL objects currently alive, shown in the UI to catch leaks
+static LIVE_BUFFERS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_VERTEX_ARRAYS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_PROGRAMS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_TEXTURES: AtomicUsize = AtomicUsize::new(0);
+static LIVE_FRAMEBUFFERS: AtomicUsize = AtomicUsize::new(0);
+
+pub struct LiveObjects {
+    pub buffers: usize,
+    pub vertex_arrays: usize,
+    pub programs: usize,
+    pub textures: usize,
+    pub framebuffers: usize,
+}
+
+pub fn live_objects() -> LiveObjects {
+    LiveObjects {
+        buffers: LIVE_BUFFERS.load(Ordering::Relaxed),
+        vertex_arrays: LIVE_VERTEX_ARRAYS.load(Ordering::Relaxed),
+        programs: LIVE_PROGRAMS.load(Ordering::Relaxed),
+        textures: LIVE_TEXTURES.load(Ordering::Relaxed),
+        framebuffers: LIVE_FRAMEBUFFERS.load(Ordering::Relaxed),
+    }
+}
+
+pub struct Buffer {
+    id: u32,
+    target: gl::types::GLenum,
+}
+
+impl Buffer {
+    // Creates a buffer and binds it to `target`
+    pub unsafe fn new(target: gl::types::GLenum) -> Buffer {
+        let mut id: u32 = 0;
+        gl::GenBuffers(1, &mut id as *mut u32);
+        gl::BindBuffer(target, id);
+        LIVE_BUFFERS.fetch_add(1, Ordering::Relaxed);
+        Buffer { id, target }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub unsafe fn bind(&self) {
+        gl::BindBuffer(self.target, self.id);
+    }
+
+    // Binds to an indexed target such as a uniform block binding point
+    pub unsafe fn bind_base(&self, index: u32) {
+        gl::BindBufferBase(self.target, index, self.id);
+    }
+
+    pub unsafe fn upload<T>(&self, data: &[T], usage: gl::types::GLenum) {
+        self.bind();
+        gl::BufferData(
+            self.target,
+            utils::byte_size_of_array(data),
+            utils::pointer_to_array(data),
+            usage,
+        );
+    }
+
+    // Overwrites part of the buffer in place, `offset` is in bytes
+    pub unsafe fn upload_range<T>(&self, offset: isize, data: &[T]) {
+        self.bind();
+        gl::BufferSubData(
+            self.target,
+            offset,
+            utils::byte_size_of_array(data),
+            utils::pointer_to_array(data),
+        );
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteBuffers(1, &self.id) };
+        LIVE_BUFFERS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// A persistently mapped buffer split into segments that are written in turn,
+// so the CPU can fill one segment while the GPU still reads from the others
+pub struct RingBuffer {
+    buffer: Buffer,
+    mapped: *mut u8,
+    segment_size: usize,
+    fences: Vec<gl::types::GLsync>,
+    current: usize,
+}
+
+impl RingBuffer {
+    pub unsafe fn new(
+        target: gl::types::GLenum,
+        segment_size: usize,
+        segment_count: usize,
+    ) -> RingBuffer {
+        let buffer = Buffer::new(target);
+        let size = (segment_size * segment_count) as isize;
+        let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+        gl::BufferStorage(target, size, std::ptr::null(), flags);
+        let mapped = gl::MapBufferRange(target, 0, size, flags) as *mut u8;
+        assert!(!mapped.is_null(), "Failed to map ring buffer");
+
+        RingBuffer {
+            buffer,
+            mapped,
+            segment_size,
+            fences: vec![std::ptr::null(); segment_count],
+            current: 0,
+        }
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    // Byte offset of the segment the GPU should read from
+    pub fn offset(&self) -> isize {
+        (self.current * self.segment_size) as isize
+    }
+
+    // Copies `data` into the next free segment, waiting for the GPU to finish
+    // with it first, and makes that segment current
+    pub unsafe fn write(&mut self, data: &[u8]) -> isize {
+        assert!(
+            data.len() <= self.segment_size,
+            "Ring buffer segment too small"
+        );
+
+        self.current = (self.current + 1) % self.fences.len();
+        let fence = self.fences[self.current];
+        if !fence.is_null() {
+            gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, u64::MAX);
+            gl::DeleteSync(fence);
+            self.fences[self.current] = std::ptr::null();
+        }
+
+        std::ptr::copy_nonoverlapping(
+            data.as_ptr(),
+            self.mapped.add(self.current * self.segment_size),
+            data.len(),
+        );
+        self.offset()
+    }
+
+    // Call after the draw calls reading the current segment have been issued
+    pub unsafe fn fence(&mut self) {
+        let fence = &mut self.fences[self.current];
+        if !fence.is_null() {
+            gl::DeleteSync(*fence);
+        }
+        *fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+    }
+}
+
+impl Drop for RingBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            for fence in &self.fences {
+                if !fence.is_null() {
+                    gl::DeleteSync(*fence);
+                }
+            }
+            self.buffer.bind();
+            gl::UnmapBuffer(self.buffer.target);
+        }
+    }
+}
+
+// A vertex array owns the buffers it reads from, so they live exactly as long
+pub struct VertexArray {
+    id: u32,
+    buffers: Vec<Buffer>,
+    ring: Option<RingBuffer>,
+}
+
+impl VertexArray {
+    // Creates a vertex array and binds it
+    pub unsafe fn new() -> VertexArray {
+        let mut id: u32 = 0;
+        gl::GenVertexArrays(1, &mut id as *mut u32);
+        gl::BindVertexArray(id);
+        LIVE_VERTEX_ARRAYS.fetch_add(1, Ordering::Relaxed);
+        VertexArray {
+            id,
+            buffers: Vec::new(),
+            ring: None,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub unsafe fn bind(&self) {
+        gl::BindVertexArray(self.id);
+    }
+
+    pub fn attach(&mut self, buffer: Buffer) {
+        self.buffers.push(buffer);
+    }
+
+    pub fn buffers(&self) -> &[Buffer] {
+        &self.buffers
+    }
+
+    pub fn attach_ring(&mut self, ring: RingBuffer) {
+        self.ring = Some(ring);
+    }
+
+    pub fn ring_mut(&mut self) -> Option<&mut RingBuffer> {
+        self.ring.as_mut()
+    }
+
+    // Fences the ring buffer, if any, once this vertex array has been drawn
+    pub unsafe fn fence(&mut self) {
+        if let Some(ring) = &mut self.ring {
+            ring.fence();
+        }
+    }
+}
+
+impl Drop for VertexArray {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteVertexArrays(1, &self.id) };
+        LIVE_VERTEX_ARRAYS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct Program {
+    id: u32,
+}
+
+impl Program {
+    pub unsafe fn new() -> Program {
+        LIVE_PROGRAMS.fetch_add(1, Ordering::Relaxed);
+        Program {
+            id: gl::CreateProgram(),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub unsafe fn activate(&self) {
+        gl::UseProgram(self.id);
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteProgram(self.id) };
+        LIVE_PROGRAMS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct Texture {
+    id: u32,
+}
+
+impl Texture {
+    // Creates an immutable 2D texture of `format`, sampled without filtering
+    pub unsafe fn new_2d(format: gl::types::GLenum, width: u32, height: u32) -> Texture {
+        let mut id: u32 = 0;
+        gl::CreateTextures(gl::TEXTURE_2D, 1, &mut id as *mut u32);
+        gl::TextureStorage2D(id, 1, format, width as i32, height as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        LIVE_TEXTURES.fetch_add(1, Ordering::Relaxed);
+        Texture { id }
+    }
+
+    // Creates an immutable 1D texture of `format`, filtered linearly
+    pub unsafe fn new_1d(format: gl::types::GLenum, width: u32) -> Texture {
+        let mut id: u32 = 0;
+        gl::CreateTextures(gl::TEXTURE_1D, 1, &mut id as *mut u32);
+        gl::TextureStorage1D(id, 1, format, width as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        LIVE_TEXTURES.fetch_add(1, Ordering::Relaxed);
+        Texture { id }
+    }
+
+    // Creates a cube map, `size` texels along each edge, filtered linearly
+    pub unsafe fn new_cube(format: gl::types::GLenum, size: u32) -> Texture {
+        let mut id: u32 = 0;
+        gl::CreateTextures(gl::TEXTURE_CUBE_MAP, 1, &mut id as *mut u32);
+        gl::TextureStorage2D(id, 1, format, size as i32, size as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+        LIVE_TEXTURES.fetch_add(1, Ordering::Relaxed);
+        Texture { id }
+    }
+
+    // Creates a cube map array of `count` cubes, `size` texels along each edge,
+    // filtered linearly. Depth formats compare against a reference when
+    // sampled, for hardware filtered shadow lookups.
+    pub unsafe fn new_cube_array(format: gl::types::GLenum, size: u32, count: u32) -> Texture {
+        let mut id: u32 = 0;
+        gl::CreateTextures(gl::TEXTURE_CUBE_MAP_ARRAY, 1, &mut id as *mut u32);
+        gl::TextureStorage3D(id, 1, format, size as i32, size as i32, 6 * count as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl::TextureParameteri(id, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+        if format == gl::DEPTH_COMPONENT32F {
+            gl::TextureParameteri(
+                id,
+                gl::TEXTURE_COMPARE_MODE,
+                gl::COMPARE_REF_TO_TEXTURE as i32,
+            );
+            gl::TextureParameteri(id, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32);
+        }
+        LIVE_TEXTURES.fetch_add(1, Ordering::Relaxed);
+        Texture { id }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub unsafe fn bind(&self, unit: u32) {
+        gl::BindTextureUnit(unit, self.id);
+    }
+
+    // Sets both the minifying and magnifying filter, e.g. gl::LINEAR
+    pub unsafe fn set_filter(&self, filter: gl::types::GLenum) {
+        gl::TextureParameteri(self.id, gl::TEXTURE_MIN_FILTER, filter as i32);
+        gl::TextureParameteri(self.id, gl::TEXTURE_MAG_FILTER, filter as i32);
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteTextures(1, &self.id) };
+        LIVE_TEXTURES.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// An offscreen render target owning its colour and depth textures. The scene is
+// drawn into one so the depth format is ours to pick, which the window's
+// default framebuffer does not allow.
+pub struct Framebuffer {
+    id: u32,
+    width: u32,
+    height: u32,
+    color: Option<Texture>,
+    depth: Option<Texture>,
+}
+
+impl Framebuffer {
+    pub unsafe fn new(
+        width: u32,
+        height: u32,
+        color_format: gl::types::GLenum,
+        depth_format: gl::types::GLenum,
+    ) -> Framebuffer {
+        // Zero sized textures are invalid, which a minimized window asks for
+        let (width, height) = (width.max(1), height.max(1));
+        let color = Texture::new_2d(color_format, width, height);
+        let depth = Texture::new_2d(depth_format, width, height);
+
+        let mut id: u32 = 0;
+        gl::CreateFramebuffers(1, &mut id as *mut u32);
+        gl::NamedFramebufferTexture(id, gl::COLOR_ATTACHMENT0, color.id(), 0);
+        gl::NamedFramebufferTexture(id, gl::DEPTH_ATTACHMENT, depth.id(), 0);
+        LIVE_FRAMEBUFFERS.fetch_add(1, Ordering::Relaxed);
+
+        let status = gl::CheckNamedFramebufferStatus(id, gl::FRAMEBUFFER);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            panic!("Framebuffer incomplete: 0x{:x}", status);
+        }
+
+        Framebuffer {
+            id,
+            width,
+            height,
+            color: Some(color),
+            depth: Some(depth),
+        }
+    }
+
+    // Renders colour only, filtered linearly so it can be sampled between
+    // texels when scaled
+    pub unsafe fn color_only(
+        width: u32,
+        height: u32,
+        color_format: gl::types::GLenum,
+    ) -> Framebuffer {
+        let (width, height) = (width.max(1), height.max(1));
+        let color = Texture::new_2d(color_format, width, height);
+        color.set_filter(gl::LINEAR);
+
+        let mut id: u32 = 0;
+        gl::CreateFramebuffers(1, &mut id as *mut u32);
+        gl::NamedFramebufferTexture(id, gl::COLOR_ATTACHMENT0, color.id(), 0);
+        LIVE_FRAMEBUFFERS.fetch_add(1, Ordering::Relaxed);
+
+        let status = gl::CheckNamedFramebufferStatus(id, gl::FRAMEBUFFER);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            panic!("Framebuffer incomplete: 0x{:x}", status);
+        }
+
+        Framebuffer {
+            id,
+            width,
+            height,
+            color: Some(color),
+            depth: None,
+        }
+    }
+
+    // Renders depth only, into one layer of `depth` at a time as chosen with
+    // `attach_depth_layer`. The layers are `size` texels square.
+    pub unsafe fn layered_depth(depth: Texture, size: u32) -> Framebuffer {
+        let mut id: u32 = 0;
+        gl::CreateFramebuffers(1, &mut id as *mut u32);
+        gl::NamedFramebufferDrawBuffer(id, gl::NONE);
+        gl::NamedFramebufferReadBuffer(id, gl::NONE);
+        LIVE_FRAMEBUFFERS.fetch_add(1, Ordering::Relaxed);
+
+        let framebuffer = Framebuffer {
+            id,
+            width: size,
+            height: size,
+            color: None,
+            depth: Some(depth),
+        };
+        framebuffer.attach_depth_layer(0);
+        let status = gl::CheckNamedFramebufferStatus(id, gl::FRAMEBUFFER);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            panic!("Framebuffer incomplete: 0x{:x}", status);
+        }
+        framebuffer
+    }
+
+    pub unsafe fn attach_depth_layer(&self, layer: u32) {
+        if let Some(depth) = &self.depth {
+            gl::NamedFramebufferTextureLayer(
+                self.id,
+                gl::DEPTH_ATTACHMENT,
+                depth.id(),
+                0,
+                layer as i32,
+            );
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn color(&self) -> Option<&Texture> {
+        self.color.as_ref()
+    }
+
+    pub fn depth(&self) -> Option<&Texture> {
+        self.depth.as_ref()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // Copies the depth into `target`, a 2D texture of the same size and
+    // format, for passes that read the scene's depth while drawing into it
+    pub unsafe fn copy_depth(&self, target: &Texture) {
+        if let Some(depth) = &self.depth {
+            gl::CopyImageSubData(
+                depth.id(),
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                0,
+                target.id(),
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                0,
+                self.width as i32,
+                self.height as i32,
+                1,
+            );
+        }
+    }
+
+    // Draws into this framebuffer from now on, covering all of it
+    pub unsafe fn bind(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
+        gl::Viewport(0, 0, self.width as i32, self.height as i32);
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteFramebuffers(1, &self.id) };
+        LIVE_FRAMEBUFFERS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,405 @@
+use std::io;
+use std::path::Path;
+
+use crate::material::{Material, Shading};
+use crate::mesh::Mesh;
+
+fn invalid_data<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl Mesh {
+    // Loads a model from disk, picking the format from the file extension.
+    // Every primitive in the file is merged into a single mesh, and
+    // `default_material` is used wherever the file does not specify colours.
+    pub fn import(path: &str, default_material: Material) -> io::Result<Mesh> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+
+        let mut mesh = match extension {
+            "obj" => Mesh::import_obj(path, default_material)?,
+            "gltf" | "glb" => Mesh::import_gltf(path, default_material)?,
+            e => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unsupported model format: {}", e),
+                ))
+            }
+        };
+
+        if mesh.indices.is_empty() {
+            return Err(invalid_data(format!("No triangles found in {}", path)));
+        }
+        let vertex_count = mesh.vertices.len() / 3;
+        if mesh.indices.len() % 3 != 0
+            || mesh
+                .indices
+                .iter()
+                .any(|&index| index as usize >= vertex_count)
+        {
+            return Err(invalid_data(format!("Broken triangles in {}", path)));
+        }
+        if mesh.normals.len() != mesh.vertices.len() {
+            mesh.normals = mesh.smooth_normals();
+        }
+        mesh.index_count = mesh.indices.len() as i32;
+        Ok(mesh)
+    }
+
+    fn import_obj(path: &str, default_material: Material) -> io::Result<Mesh> {
+        let (models, materials) = tobj::load_obj(
+            path,
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            },
+        )
+        .map_err(invalid_data)?;
+
+        // A missing or broken .mtl file should not keep the geometry from loading
+        let materials = materials.unwrap_or_default();
+
+        let mut mesh = Mesh::empty();
+        for model in &models {
+            let material = model
+                .mesh
+                .material_id
+                .and_then(|id| materials.get(id))
+                .map(|m| Material {
+                    ambient: m.ambient.map_or(default_material.ambient, glm::Vec3::from),
+                    diffuse: m.diffuse.map_or(default_material.diffuse, glm::Vec3::from),
+                    specular: m
+                        .specular
+                        .map_or(default_material.specular, glm::Vec3::from),
+                    shininess: m.shininess.unwrap_or(default_material.shininess),
+                    ..default_material
+                })
+                .unwrap_or(default_material);
+
+            let vertex_count = model.mesh.positions.len() / 3;
+            let has_normals = model.mesh.normals.len() == vertex_count * 3;
+            let has_colors = model.mesh.vertex_color.len() == vertex_count * 3;
+
+            let first_index = mesh.vertices.len() as u32 / 3;
+            for i in 0..vertex_count {
+                let mut vertex_material = material;
+                if has_colors {
+                    let color = glm::make_vec3(&model.mesh.vertex_color[i * 3..i * 3 + 3]);
+                    vertex_material.ambient = color;
+                    vertex_material.diffuse = color;
+                }
+                mesh.push_vertex(
+                    glm::make_vec3(&model.mesh.positions[i * 3..i * 3 + 3]),
+                    has_normals.then(|| glm::make_vec3(&model.mesh.normals[i * 3..i * 3 + 3])),
+                    &vertex_material,
+                );
+            }
+            mesh.indices
+                .extend(model.mesh.indices.iter().map(|index| index + first_index));
+        }
+
+        mesh.drop_partial_normals();
+        Ok(mesh)
+    }
+
+    fn import_gltf(path: &str, default_material: Material) -> io::Result<Mesh> {
+        let (document, buffers, _) = gltf::import(path).map_err(invalid_data)?;
+
+        let mut mesh = Mesh::empty();
+        match document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+        {
+            Some(scene) => {
+                for node in scene.nodes() {
+                    mesh.append_gltf_node(
+                        &node,
+                        &glm::Mat4::identity(),
+                        &buffers,
+                        &default_material,
+                    )?;
+                }
+            }
+            None => {
+                for gltf_mesh in document.meshes() {
+                    mesh.append_gltf_mesh(
+                        &gltf_mesh,
+                        &glm::Mat4::identity(),
+                        &buffers,
+                        &default_material,
+                    )?;
+                }
+            }
+        }
+
+        mesh.drop_partial_normals();
+        Ok(mesh)
+    }
+
+    fn append_gltf_node(
+        &mut self,
+        node: &gltf::Node,
+        parent_transform: &glm::Mat4,
+        buffers: &[gltf::buffer::Data],
+        default_material: &Material,
+    ) -> io::Result<()> {
+        let local: [[f32; 4]; 4] = node.transform().matrix();
+        let transform = parent_transform * glm::Mat4::from(local);
+
+        if let Some(gltf_mesh) = node.mesh() {
+            self.append_gltf_mesh(&gltf_mesh, &transform, buffers, default_material)?;
+        }
+        for child in node.children() {
+            self.append_gltf_node(&child, &transform, buffers, default_material)?;
+        }
+        Ok(())
+    }
+
+    fn append_gltf_mesh(
+        &mut self,
+        gltf_mesh: &gltf::Mesh,
+        transform: &glm::Mat4,
+        buffers: &[gltf::buffer::Data],
+        default_material: &Material,
+    ) -> io::Result<()> {
+        let normal_matrix = glm::mat4_to_mat3(&glm::transpose(&glm::inverse(transform)));
+
+        for primitive in gltf_mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+            let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                Some(positions) => positions.collect(),
+                None => continue,
+            };
+            let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|n| n.collect());
+            let colors: Option<Vec<[f32; 3]>> =
+                reader.read_colors(0).map(|c| c.into_rgb_f32().collect());
+            let indices: Option<Vec<u32>> = reader.read_indices().map(|i| i.into_u32().collect());
+
+            // Every attribute needs a value for each vertex, and every index a
+            // vertex to point at
+            let vertex_count = positions.len();
+            if normals.as_ref().is_some_and(|n| n.len() != vertex_count)
+                || colors.as_ref().is_some_and(|c| c.len() != vertex_count)
+                || indices
+                    .as_ref()
+                    .is_some_and(|i| i.iter().any(|&index| index as usize >= vertex_count))
+            {
+                return Err(invalid_data(format!(
+                    "Primitive {} of mesh {} does not line up with its {} vertices",
+                    primitive.index(),
+                    gltf_mesh.index(),
+                    vertex_count
+                )));
+            }
+
+            let pbr = primitive.material().pbr_metallic_roughness();
+            let [r, g, b, _] = pbr.base_color_factor();
+            let base_color = glm::vec3(r, g, b);
+            let material = Material {
+                ambient: base_color,
+                diffuse: base_color,
+                // Rough approximation of the metallic/roughness model in Phong terms,
+                // for when the model is switched over to Phong
+                specular: default_material.specular * (1.0 - pbr.roughness_factor()),
+                shininess: default_material.shininess,
+                shading: Shading::Pbr,
+                roughness: pbr.roughness_factor(),
+                metallic: pbr.metallic_factor(),
+            };
+
+            let first_index = self.vertices.len() as u32 / 3;
+            for (i, position) in positions.iter().enumerate() {
+                let mut vertex_material = material;
+                if let Some(colors) = &colors {
+                    let color = glm::Vec3::from(colors[i]).component_mul(&base_color);
+                    vertex_material.ambient = color;
+                    vertex_material.diffuse = color;
+                }
+                let position = transform * glm::Vec3::from(*position).push(1.0);
+                self.push_vertex(
+                    position.xyz(),
+                    normals
+                        .as_ref()
+                        .map(|n| (normal_matrix * glm::Vec3::from(n[i])).normalize()),
+                    &vertex_material,
+                );
+            }
+
+            match indices {
+                Some(indices) => self
+                    .indices
+                    .extend(indices.iter().map(|index| index + first_index)),
+                None => self
+                    .indices
+                    .extend(first_index..first_index + positions.len() as u32),
+            }
+        }
+        Ok(())
+    }
+
+    // If only some primitives came with normals they no longer line up with
+    // the vertices, so throw them away and let `import` recompute all of them
+    fn drop_partial_normals(&mut self) {
+        if self.normals.len() != self.vertices.len() {
+            self.normals.clear();
+        }
+    }
+
+    fn smooth_normals(&self) -> Vec<f32> {
+        let mut vertex_normals = vec![glm::vec3(0.0, 0.0, 0.0); self.vertices.len() / 3];
+        for cell in self.indices.chunks(3) {
+            let [a, b, c] = [cell[0], cell[1], cell[2]]
+                .map(|i| glm::make_vec3(&self.vertices[i as usize * 3..i as usize * 3 + 3]));
+            let triangle_normal = glm::cross(&(b - a), &(c - a));
+
+            for &i in cell {
+                vertex_normals[i as usize] += triangle_normal;
+            }
+        }
+
+        let mut vec = Vec::new();
+        for normal in &vertex_normals {
+            if normal.norm() > 0.0 {
+                vec.extend(&glm::normalize(normal));
+            } else {
+                vec.extend(normal);
+            }
+        }
+        vec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::biome::Biome;
+    use crate::noise::Noise;
+    use crate::sphere::Sphere;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "studious-system-import-{}-{}",
+                std::process::id(),
+                name
+            ))
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    fn assert_round_trip(extension: &str) {
+        let exported = Sphere::new(2, Material::new()).displaced_mesh(&Noise::new(), &Biome::new());
+        let path = temp_path(&format!("planet.{}", extension));
+        exported.export(&path).unwrap();
+
+        let imported = Mesh::import(&path, Material::new()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Loaders may drop unreferenced vertices, so compare the triangles corner by corner
+        assert_eq!(imported.index_count, exported.index_count);
+        assert_eq!(imported.normals.len(), imported.vertices.len());
+        assert_eq!(
+            imported.material.shininess.len(),
+            imported.vertices.len() / 3
+        );
+        for (&a, &b) in imported.indices.iter().zip(&exported.indices) {
+            let (a, b) = (a as usize * 3, b as usize * 3);
+            assert_eq!(imported.vertices[a..a + 3], exported.vertices[b..b + 3]);
+        }
+    }
+
+    #[test]
+    fn imports_exported_obj() {
+        assert_round_trip("obj");
+    }
+
+    #[test]
+    fn imports_exported_glb() {
+        assert_round_trip("glb");
+    }
+
+    #[test]
+    fn obj_materials_and_missing_normals() {
+        let obj_path = temp_path("quad.obj");
+        let mtl_path = temp_path("quad.mtl");
+        let mtl_name = Path::new(&mtl_path).file_name().unwrap().to_str().unwrap();
+        std::fs::write(
+            &obj_path,
+            format!(
+                "mtllib {}\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nusemtl hull\nf 1 2 3 4\n",
+                mtl_name
+            ),
+        )
+        .unwrap();
+        std::fs::write(&mtl_path, "newmtl hull\nKd 0.25 0.5 0.75\nNs 8\n").unwrap();
+
+        let mesh = Mesh::import(&obj_path, Material::new()).unwrap();
+        std::fs::remove_file(&obj_path).unwrap();
+        std::fs::remove_file(&mtl_path).unwrap();
+
+        // The quad is triangulated and gets normals facing +z
+        assert_eq!(mesh.index_count, 6);
+        assert_eq!(mesh.normals[..3], [0.0, 0.0, 1.0]);
+        assert_eq!(mesh.material.diffuse[..3], [0.25, 0.5, 0.75]);
+        assert_eq!(mesh.material.ambient[..3], [1.0, 0.7, 0.81]);
+        assert_eq!(mesh.material.shininess[0], 8.0);
+    }
+
+    #[test]
+    fn gltf_indices_past_the_vertices_are_rejected() {
+        let gltf_path = temp_path("broken.gltf");
+        let bin_path = temp_path("broken.bin");
+        let bin_name = Path::new(&bin_path).file_name().unwrap().to_str().unwrap();
+
+        // One triangle whose last corner points at a vertex that is not there
+        let mut bin = Vec::new();
+        for value in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            bin.extend(value.to_le_bytes());
+        }
+        for index in [0u16, 1, 7, 0] {
+            bin.extend(index.to_le_bytes());
+        }
+        std::fs::write(&bin_path, &bin).unwrap();
+        std::fs::write(
+            &gltf_path,
+            format!(
+                r#"{{
+                    "asset": {{"version": "2.0"}},
+                    "buffers": [{{"uri": "{}", "byteLength": 44}}],
+                    "bufferViews": [
+                        {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+                        {{"buffer": 0, "byteOffset": 36, "byteLength": 6}}
+                    ],
+                    "accessors": [
+                        {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                          "min": [0, 0, 0], "max": [1, 1, 0]}},
+                        {{"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}}
+                    ],
+                    "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "indices": 1}}]}}]
+                }}"#,
+                bin_name
+            ),
+        )
+        .unwrap();
+
+        let result = Mesh::import(&gltf_path, Material::new());
+        std::fs::remove_file(&gltf_path).unwrap();
+        std::fs::remove_file(&bin_path).unwrap();
+        assert!(matches!(result, Err(e) if e.kind() == io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn unknown_extension_is_rejected() {
+        let result = Mesh::import("ship.fbx", Material::new());
+        assert!(matches!(result, Err(e) if e.kind() == io::ErrorKind::InvalidInput));
+    }
+}
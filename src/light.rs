@@ -1,6 +1,171 @@
+use serde::{Deserialize, Serialize};
+
+use crate::shadow::MAX_SHADOW_LIGHTS;
+
+// Must match MAX_LIGHTS in common.glsl
+pub const MAX_LIGHTS: usize = 16;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    // Shines in every direction from `position`, fading with distance
+    Point,
+    // Parallel rays along `direction` from infinitely far away
+    Directional,
+    // A point light limited to a cone around `direction`
+    Spot,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Light {
-    pub position: glm::Vec3,
+    pub kind: LightKind,
+    pub position: glm::DVec3,
+    pub direction: glm::Vec3,
     pub ambient: glm::Vec3,
     pub diffuse: glm::Vec3,
     pub specular: glm::Vec3,
+    // Constant, linear and quadratic terms of 1 / (c + l d + q d²)
+    pub attenuation: glm::Vec3,
+    // Spot lights are at full strength inside the inner angle and fade out
+    // towards the outer one, both measured from `direction` in radians
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+impl Light {
+    pub fn point(position: glm::DVec3) -> Light {
+        Light {
+            kind: LightKind::Point,
+            position,
+            direction: glm::vec3(0.0, -1.0, 0.0),
+            ambient: glm::vec3(0.2, 0.2, 0.2),
+            diffuse: glm::vec3(0.5, 0.5, 0.5),
+            specular: glm::vec3(1.0, 1.0, 1.0),
+            attenuation: glm::vec3(1.0, 0.0, 0.0),
+            inner_angle: 12.5_f32.to_radians(),
+            outer_angle: 17.5_f32.to_radians(),
+        }
+    }
+
+    // Indices of the lights that get a shadow map: the first point and spot
+    // lights, up to MAX_SHADOW_LIGHTS
+    pub fn shadow_casters(lights: &[Light]) -> Vec<usize> {
+        lights
+            .iter()
+            .take(MAX_LIGHTS)
+            .enumerate()
+            .filter(|(_, light)| light.kind != LightKind::Directional)
+            .map(|(i, _)| i)
+            .take(MAX_SHADOW_LIGHTS)
+            .collect()
+    }
+
+    // Lights up to MAX_LIGHTS in the std140 layout of the `Lights` block, with
+    // positions relative to `origin`. `shadow_casters` are the lights with a
+    // shadow map, in the order of the maps.
+    pub fn uniform_block(
+        lights: &[Light],
+        origin: &glm::DVec3,
+        shadow_casters: &[usize],
+    ) -> LightBlock {
+        let mut block = LightBlock {
+            count: lights.len().min(MAX_LIGHTS) as i32,
+            _padding: [0; 3],
+            lights: [GpuLight::default(); MAX_LIGHTS],
+        };
+        for (i, (gpu_light, light)) in block.lights.iter_mut().zip(lights).enumerate() {
+            *gpu_light = GpuLight {
+                position: (light.position - origin).cast::<f32>().into(),
+                kind: light.kind as i32,
+                direction: glm::normalize(&light.direction).into(),
+                cos_inner: light.inner_angle.cos(),
+                ambient: light.ambient.into(),
+                cos_outer: light.outer_angle.max(light.inner_angle).cos(),
+                diffuse: light.diffuse.into(),
+                constant: light.attenuation.x,
+                specular: light.specular.into(),
+                linear: light.attenuation.y,
+                quadratic: light.attenuation.z,
+                shadow: shadow_casters
+                    .iter()
+                    .position(|&caster| caster == i)
+                    .map_or(-1, |map| map as i32),
+                _padding: [0.0; 2],
+            };
+        }
+        block
+    }
+}
+
+// One light as the shaders see it. Each vec3 shares its 16 bytes with the
+// scalar after it, as std140 allows.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct GpuLight {
+    position: [f32; 3],
+    kind: i32,
+    direction: [f32; 3],
+    cos_inner: f32,
+    ambient: [f32; 3],
+    cos_outer: f32,
+    diffuse: [f32; 3],
+    constant: f32,
+    specular: [f32; 3],
+    linear: f32,
+    quadratic: f32,
+    // Index of the light's shadow map, -1 for none
+    shadow: i32,
+    _padding: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct LightBlock {
+    count: i32,
+    _padding: [i32; 3],
+    lights: [GpuLight; MAX_LIGHTS],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_matches_std140() {
+        assert_eq!(std::mem::size_of::<GpuLight>(), 96);
+        assert_eq!(std::mem::size_of::<LightBlock>(), 16 + 96 * MAX_LIGHTS);
+    }
+
+    #[test]
+    fn positions_are_relative_to_the_origin() {
+        let mut spot = Light::point(glm::DVec3::new(1.0e12 + 3.0, 0.0, 0.0));
+        spot.kind = LightKind::Spot;
+        spot.direction = glm::vec3(0.0, 0.0, -2.0);
+        let lights = vec![spot; MAX_LIGHTS + 2];
+
+        let block = Light::uniform_block(&lights, &glm::DVec3::new(1.0e12, 0.0, 0.0), &[]);
+        assert_eq!(block.count, MAX_LIGHTS as i32);
+        assert_eq!(block.lights[0].position, [3.0, 0.0, 0.0]);
+        assert_eq!(block.lights[0].direction, [0.0, 0.0, -1.0]);
+        assert_eq!(block.lights[0].kind, 2);
+        assert!(block.lights[0].cos_inner > block.lights[0].cos_outer);
+        assert_eq!(block.lights[0].shadow, -1);
+    }
+
+    #[test]
+    fn directional_lights_cast_no_shadows() {
+        let mut sun = Light::point(glm::DVec3::new(0.0, 0.0, 0.0));
+        sun.kind = LightKind::Directional;
+        let mut lights = vec![sun];
+        lights.extend(vec![
+            Light::point(glm::DVec3::new(0.0, 0.0, 0.0));
+            MAX_SHADOW_LIGHTS + 1
+        ]);
+
+        let casters = Light::shadow_casters(&lights);
+        assert_eq!(casters, (1..=MAX_SHADOW_LIGHTS).collect::<Vec<_>>());
+        let block = Light::uniform_block(&lights, &glm::DVec3::new(0.0, 0.0, 0.0), &casters);
+        assert_eq!(block.lights[0].shadow, -1);
+        assert_eq!(block.lights[2].shadow, 1);
+        assert_eq!(block.lights[MAX_SHADOW_LIGHTS + 1].shadow, -1);
+    }
 }
@@ -1,79 +1,137 @@
+// Every `unsafe fn` in this crate is unsafe for the same reason: it issues raw
+// OpenGL calls and requires a current context on the calling thread.
+#![allow(clippy::missing_safety_doc)]
+
 extern crate nalgebra_glm as glm;
 use std::ptr;
 
 use glutin::event::{
+    DeviceEvent,
     ElementState::{Pressed, Released},
-    Event, KeyboardInput,
+    Event, KeyboardInput, MouseButton, MouseScrollDelta,
     VirtualKeyCode::{self, *},
     WindowEvent,
 };
 use glutin::event_loop::ControlFlow;
-
+use glutin::window::{CursorGrabMode, Window};
+
+pub mod atmosphere;
+pub mod background;
+pub mod biome;
+pub mod camera;
+pub mod camera_path;
+pub mod clouds;
+pub mod export;
+pub mod gpu;
+pub mod import;
 pub mod light;
 pub mod planet;
+pub mod post;
+pub mod preset;
+pub mod rings;
+pub mod scene_file;
 pub mod scenenode;
+pub mod shadow;
 pub mod sphere;
-use imgui::{CollapsingHeader, Condition};
-use light::Light;
+pub mod star;
+use atmosphere::Atmosphere;
+use background::{Background, Sky, Starfield, SKYBOX_FACES};
+use biome::BiomeStop;
+use camera::{Camera, CameraMode, DepthMode};
+use camera_path::{CameraPath, Keyframe};
+use clouds::Clouds;
+use imgui::{CollapsingHeader, Condition, SliderFlags};
+use light::{Light, LightKind, MAX_LIGHTS};
 use noise::Noise;
 use planet::Planet;
+use post::{PostProcess, PostSettings, ToneMapper};
+use preset::Preset;
+use rings::{RingLayer, RingStop, Rings};
+use scene_file::SceneFile;
+use scenenode::{NodeId, SceneGraph, SceneNode, Terrain};
+use shader::ShaderType;
+use shadow::{ShadowMaps, ShadowSettings};
+use star::Star;
 pub mod material;
-use material::Material;
+use material::{Material, Shading};
+use mesh::VertexUpdates;
+use vertex_format::MESH_VERTEX_FORMAT;
 pub mod mesh;
 pub mod noise;
 pub mod shader;
 pub mod utils;
 pub mod vertex;
+pub mod vertex_format;
 
 // initial window size
 const INITIAL_SCREEN_W: u32 = 800;
 const INITIAL_SCREEN_H: u32 = 600;
 
+const USER_PRESETS_PATH: &str = "./presets.ron";
+// Perlin noise for the terrain and clouds, linked into each stage that uses it
+const NOISE_SHADER: &str = "./shaders/noise.glsl";
+// The lights, noise parameters and constants the shaders share
+const COMMON_SHADER: &str = "./shaders/common.glsl";
+// Texels along each edge of a shadow cube face
+const SHADOW_MAP_RESOLUTION: u32 = 1024;
+
+// The scene graph is laid out relative to `origin`, the camera, so only the
+// lights have to be moved over to match
+#[allow(clippy::too_many_arguments)]
 unsafe fn draw_scene(
-    nodes: &mut Vec<scenenode::SceneNode>,
+    scene: &mut SceneGraph,
     view_projection_matrix: &glm::Mat4,
-    light: &Light,
-    cam_pos: &glm::Vec3,
-    noise: &Noise,
+    lights: &[Light],
+    light_buffer: &gpu::Buffer,
+    shadow_casters: &[usize],
+    origin: &glm::DVec3,
+    log_depth: f32,
+    time: f32,
 ) {
-    for node in nodes {
-        let mut model_matrix = glm::Mat4::identity();
-        model_matrix = glm::translation(&glm::vec3(
-            node.reference_point.x * -1.0,
-            node.reference_point.y * -1.0,
-            node.reference_point.z * -1.0,
-        )) * model_matrix;
-
-        model_matrix = glm::rotation(node.rotation.x, &glm::vec3(1.0, 0.0, 0.0)) * model_matrix;
-        model_matrix = glm::rotation(node.rotation.y, &glm::vec3(0.0, 1.0, 0.0)) * model_matrix;
-        model_matrix = glm::rotation(node.rotation.z, &glm::vec3(0.0, 0.0, 1.0)) * model_matrix;
+    let cam_pos = glm::vec3(0.0, 0.0, 0.0);
+    light_buffer.upload(
+        std::slice::from_ref(&Light::uniform_block(lights, origin, shadow_casters)),
+        gl::DYNAMIC_DRAW,
+    );
+    light_buffer.bind_base(0);
 
-        model_matrix = glm::scale(&model_matrix, &node.scale);
+    scene.update_world_matrices();
 
-        model_matrix = glm::translation(&node.reference_point) * model_matrix;
-        model_matrix = glm::translation(&node.position) * model_matrix;
+    for (_, node) in scene.iter_mut() {
+        let model_matrix = *node.world_matrix();
+        let vao = match &mut node.vao {
+            Some(vao) => vao,
+            None => continue,
+        };
 
         let transformation_matrix: glm::Mat4 = view_projection_matrix * model_matrix;
 
         gl::UseProgram(node.shader_program);
-        gl::BindVertexArray(node.vao_id);
+        vao.bind();
 
         gl::UniformMatrix4fv(10, 1, gl::TRUE, transformation_matrix.as_ptr());
         gl::UniformMatrix4fv(11, 1, gl::TRUE, model_matrix.as_ptr());
         gl::Uniform3fv(12, 1, cam_pos.as_ptr());
 
-        gl::Uniform3fv(13, 1, light.position.as_ptr());
-        gl::Uniform3fv(14, 1, light.ambient.as_ptr());
-        gl::Uniform3fv(15, 1, light.diffuse.as_ptr());
-        gl::Uniform3fv(16, 1, light.specular.as_ptr());
+        gl::Uniform1f(41, log_depth);
+
+        if let Some(emission) = &node.emission {
+            gl::Uniform3fv(42, 1, emission.as_ptr());
+        }
+
+        if let Some(terrain) = &node.terrain {
+            upload_terrain(terrain);
 
-        gl::Uniform1f(17, noise.strength);
-        gl::Uniform1f(18, noise.base_roughness);
-        gl::Uniform1f(19, noise.roughness);
-        gl::Uniform1f(20, noise.persistence);
-        gl::Uniform3fv(21, 1, noise.center.as_ptr());
-        gl::Uniform1i(22, noise.num_layers as i32);
-        gl::Uniform1f(23, noise.min_value);
+            let planet_radius = model_matrix.column(0).xyz().norm();
+            match &node.clouds {
+                Some(clouds) if clouds.shadows => {
+                    gl::Uniform1i(70, 1);
+                    upload_clouds(clouds, time);
+                    gl::Uniform1f(81, planet_radius * (1.0 + clouds.altitude));
+                }
+                _ => gl::Uniform1i(70, 0),
+            }
+        }
 
         gl::DrawElements(
             gl::TRIANGLES,
@@ -81,10 +139,533 @@ unsafe fn draw_scene(
             gl::UNSIGNED_INT,
             ptr::null(),
         );
+        vao.fence();
+    }
+}
+
+// Fills the screen with what lies behind the scene: the skybox if there is
+// one, otherwise the sky colour and Milky Way with the stars over them.
+// Depth is neither tested nor written, so this goes first.
+unsafe fn draw_background(
+    sky: &Sky,
+    starfield: &Starfield,
+    view_projection_matrix: &glm::Mat4,
+    sky_program: u32,
+    stars_program: u32,
+) {
+    gl::Disable(gl::DEPTH_TEST);
+    gl::DepthMask(gl::FALSE);
+
+    gl::UseProgram(sky_program);
+    let inverse_view_projection = glm::inverse(view_projection_matrix);
+    gl::UniformMatrix4fv(10, 1, gl::TRUE, inverse_view_projection.as_ptr());
+    let use_skybox = sky.bind_skybox(3);
+    gl::Uniform1i(100, use_skybox as i32);
+    gl::Uniform3fv(101, 1, starfield.sky_color.as_ptr());
+    gl::Uniform1i(102, starfield.milky_way as i32);
+    gl::Uniform1f(103, starfield.milky_way_brightness);
+    gl::Uniform3fv(104, 1, glm::normalize(&starfield.galactic_pole).as_ptr());
+    gl::Uniform3fv(105, 1, starfield.noise_offset().as_ptr());
+    sky.bind_empty();
+    gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+    if !use_skybox {
+        gl::UseProgram(stars_program);
+        gl::UniformMatrix4fv(10, 1, gl::TRUE, view_projection_matrix.as_ptr());
+        let star_count = sky.bind_stars();
+        gl::Enable(gl::PROGRAM_POINT_SIZE);
+        gl::DrawElements(gl::POINTS, star_count, gl::UNSIGNED_INT, ptr::null());
+        gl::Disable(gl::PROGRAM_POINT_SIZE);
+    }
+
+    gl::DepthMask(gl::TRUE);
+    gl::Enable(gl::DEPTH_TEST);
+}
+
+// Draws the rings of every node that has them with `program`, blended over the
+// solid scene already drawn and seen from either side
+unsafe fn draw_rings(
+    scene: &SceneGraph,
+    program: u32,
+    view_projection_matrix: &glm::Mat4,
+    log_depth: f32,
+) {
+    gl::UseProgram(program);
+    gl::DepthMask(gl::FALSE);
+    gl::Disable(gl::CULL_FACE);
+
+    for (_, node) in scene.iter() {
+        let layer = match &node.rings {
+            Some(layer) => layer,
+            None => continue,
+        };
+        let model_matrix = node.world_matrix();
+        let transformation_matrix: glm::Mat4 = view_projection_matrix * model_matrix;
+        let planet_radius = model_matrix.column(0).xyz().norm();
+        let (inner_radius, outer_radius) = layer.rings.edges();
+
+        gl::UniformMatrix4fv(10, 1, gl::TRUE, transformation_matrix.as_ptr());
+        gl::UniformMatrix4fv(11, 1, gl::TRUE, model_matrix.as_ptr());
+        gl::Uniform1f(41, log_depth);
+        gl::Uniform1f(90, planet_radius);
+        gl::Uniform1f(91, inner_radius * planet_radius);
+        gl::Uniform1f(92, outer_radius * planet_radius);
+        layer.bind_profile(2);
+
+        layer.vao.bind();
+        gl::DrawElements(
+            gl::TRIANGLES,
+            layer.index_count,
+            gl::UNSIGNED_INT,
+            ptr::null(),
+        );
+    }
+
+    gl::Enable(gl::CULL_FACE);
+    gl::DepthMask(gl::TRUE);
+}
+
+// Draws the cloud layer of every node that has one, blended over the solid
+// scene already drawn. `shell` is a unit sphere fitted around each planet and
+// turned with it, for clouds.frag to lay the pattern out on.
+unsafe fn draw_clouds(
+    scene: &SceneGraph,
+    shell: &mut SceneNode,
+    view_projection_matrix: &glm::Mat4,
+    log_depth: f32,
+    time: f32,
+) {
+    let vao = match &mut shell.vao {
+        Some(vao) => vao,
+        None => return,
+    };
+    gl::UseProgram(shell.shader_program);
+    vao.bind();
+
+    // Thin clouds let the ground show through, and do not hide the air
+    // drawn after them
+    gl::DepthMask(gl::FALSE);
+
+    for (_, node) in scene.iter() {
+        let clouds = match &node.clouds {
+            Some(clouds) => clouds,
+            None => continue,
+        };
+        let world_matrix = node.world_matrix();
+        let center: glm::Vec3 = world_matrix.column(3).xyz();
+        let cloud_radius = world_matrix.column(0).xyz().norm() * (1.0 + clouds.altitude);
+
+        let scale = 1.0 + clouds.altitude;
+        let model_matrix = world_matrix * glm::scaling(&glm::vec3(scale, scale, scale));
+        let transformation_matrix: glm::Mat4 = view_projection_matrix * model_matrix;
+
+        // From below only the sky side of the layer is left to draw on
+        if glm::length(&center) < cloud_radius {
+            gl::CullFace(gl::FRONT);
+        } else {
+            gl::CullFace(gl::BACK);
+        }
+
+        gl::UniformMatrix4fv(10, 1, gl::TRUE, transformation_matrix.as_ptr());
+        gl::UniformMatrix4fv(11, 1, gl::TRUE, model_matrix.as_ptr());
+        gl::Uniform1f(41, log_depth);
+        upload_clouds(clouds, time);
+        gl::Uniform3fv(82, 1, clouds.color.as_ptr());
+
+        gl::DrawElements(
+            gl::TRIANGLES,
+            shell.index_count,
+            gl::UNSIGNED_INT,
+            ptr::null(),
+        );
+    }
+
+    gl::CullFace(gl::BACK);
+    gl::DepthMask(gl::TRUE);
+}
+
+// Draws the air around every node with an atmosphere, blended over the solid
+// scene already drawn. `shell` is a unit sphere scaled up around each one for
+// atmosphere.frag to trace the scattering through, as far as the solid scene
+// in `scene_depth`.
+unsafe fn draw_atmospheres(
+    scene: &SceneGraph,
+    shell: &mut SceneNode,
+    scene_depth: &gpu::Texture,
+    view_projection_matrix: &glm::Mat4,
+    depth_mode: DepthMode,
+    log_depth: f32,
+) {
+    let vao = match &mut shell.vao {
+        Some(vao) => vao,
+        None => return,
+    };
+    gl::UseProgram(shell.shader_program);
+    vao.bind();
+
+    // Depth buffer values back to normalized device coordinates first, then
+    // out of the projection, in double precision as the planes are far apart
+    let depth_to_ndc = match depth_mode {
+        DepthMode::ReversedZ => glm::DMat4::identity(),
+        DepthMode::Standard | DepthMode::Logarithmic => {
+            glm::translation(&glm::DVec3::new(0.0, 0.0, -1.0))
+                * glm::scaling(&glm::DVec3::new(1.0, 1.0, 2.0))
+        }
+    };
+    let window_to_world: glm::Mat4 =
+        (glm::inverse(&view_projection_matrix.cast::<f64>()) * depth_to_ndc).cast();
+    gl::UniformMatrix4fv(68, 1, gl::TRUE, window_to_world.as_ptr());
+    scene_depth.bind(6);
+
+    // The air lets through what is behind it, and does not hide what is
+    // drawn after it. It finds what it covers from `scene_depth` itself, so
+    // the far side of the shell still shows from inside.
+    gl::DepthMask(gl::FALSE);
+    gl::Disable(gl::DEPTH_TEST);
+    gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+
+    for (_, node) in scene.iter() {
+        let atmosphere = match &node.atmosphere {
+            Some(atmosphere) => atmosphere,
+            None => continue,
+        };
+        let world_matrix = node.world_matrix();
+        let center: glm::Vec3 = world_matrix.column(3).xyz();
+        let planet_radius = world_matrix.column(0).xyz().norm();
+        let atmosphere_radius = planet_radius * (1.0 + atmosphere.height);
+
+        // Somewhat larger than the shell, as the mesh's flat faces cut inside it
+        let shell_radius = atmosphere_radius * 1.02;
+        let model_matrix = glm::translation(&center)
+            * glm::scaling(&glm::vec3(shell_radius, shell_radius, shell_radius));
+        let transformation_matrix: glm::Mat4 = view_projection_matrix * model_matrix;
+
+        // From inside only the far side of the shell is left to draw on
+        if glm::length(&center) < shell_radius {
+            gl::CullFace(gl::FRONT);
+        } else {
+            gl::CullFace(gl::BACK);
+        }
+
+        gl::UniformMatrix4fv(10, 1, gl::TRUE, transformation_matrix.as_ptr());
+        gl::UniformMatrix4fv(11, 1, gl::TRUE, model_matrix.as_ptr());
+        gl::Uniform1f(41, log_depth);
+
+        // The atmosphere is described in planet radii, the shader works in world units
+        let density = atmosphere.density / planet_radius;
+        gl::Uniform3fv(60, 1, center.as_ptr());
+        gl::Uniform1f(61, planet_radius);
+        gl::Uniform1f(62, atmosphere_radius);
+        gl::Uniform3fv(63, 1, (atmosphere.rayleigh * density).as_ptr());
+        gl::Uniform1f(64, atmosphere.rayleigh_scale_height * planet_radius);
+        gl::Uniform1f(65, atmosphere.mie * density);
+        gl::Uniform1f(66, atmosphere.mie_scale_height * planet_radius);
+        gl::Uniform1f(67, atmosphere.mie_anisotropy);
+
+        gl::DrawElements(
+            gl::TRIANGLES,
+            shell.index_count,
+            gl::UNSIGNED_INT,
+            ptr::null(),
+        );
+    }
+
+    gl::CullFace(gl::BACK);
+    gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+    gl::Enable(gl::DEPTH_TEST);
+    gl::DepthMask(gl::TRUE);
+}
+
+// A `Noise` struct uniform whose first member is at `location`
+unsafe fn upload_noise(location: i32, noise: &Noise) {
+    gl::Uniform1f(location, noise.strength);
+    gl::Uniform1f(location + 1, noise.base_roughness);
+    gl::Uniform1f(location + 2, noise.roughness);
+    gl::Uniform1f(location + 3, noise.persistence);
+    gl::Uniform3fv(location + 4, 1, noise.center.as_ptr());
+    gl::Uniform1i(location + 5, noise.num_layers as i32);
+    gl::Uniform1f(location + 6, noise.min_value);
+}
+
+// Cloud pattern uniforms shared by clouds.frag and shape.frag
+unsafe fn upload_clouds(clouds: &Clouds, time: f32) {
+    upload_noise(71, &clouds.noise);
+    gl::Uniform1f(78, clouds.coverage);
+    gl::Uniform1f(79, clouds.density);
+    gl::Uniform1f(80, clouds.drift(time));
+}
+
+// Noise and biome uniforms of shape.vert
+unsafe fn upload_terrain(terrain: &Terrain) {
+    upload_noise(17, &terrain.noise);
+
+    let (biome_heights, biome_colors, biome_roughness) = terrain.biome.uniform_arrays();
+    gl::Uniform1i(24, biome_heights.len() as i32);
+    if !biome_heights.is_empty() {
+        gl::Uniform1fv(25, biome_heights.len() as i32, biome_heights.as_ptr());
+        gl::Uniform3fv(33, biome_heights.len() as i32, biome_colors.as_ptr());
+        gl::Uniform1fv(50, biome_heights.len() as i32, biome_roughness.as_ptr());
+    }
+}
+
+// Renders the distance from each of `light_positions` to everything around it
+// into its cube in `shadow_maps`. Terrain is displaced by `terrain_program`
+// like it is on screen, other nodes use `program`. Glowing nodes are skipped,
+// as the light sits inside them.
+unsafe fn draw_shadows(
+    scene: &mut SceneGraph,
+    shadow_maps: &ShadowMaps,
+    light_positions: &[glm::Vec3],
+    settings: &ShadowSettings,
+    program: u32,
+    terrain_program: u32,
+) {
+    // Plain depth, nearest wins
+    apply_depth_mode(DepthMode::Standard);
+    scene.update_world_matrices();
+
+    for (index, light_position) in light_positions.iter().enumerate() {
+        let face_matrices = ShadowMaps::face_matrices(light_position, settings.range);
+        for (face, face_matrix) in face_matrices.iter().enumerate() {
+            shadow_maps.bind_face(index, face);
+
+            for (_, node) in scene.iter_mut() {
+                if node.emission.is_some() {
+                    continue;
+                }
+                let model_matrix = *node.world_matrix();
+                let vao = match &mut node.vao {
+                    Some(vao) => vao,
+                    None => continue,
+                };
+
+                let transformation_matrix: glm::Mat4 = face_matrix * model_matrix;
+
+                match &node.terrain {
+                    Some(terrain) => {
+                        gl::UseProgram(terrain_program);
+                        upload_terrain(terrain);
+                    }
+                    None => gl::UseProgram(program),
+                }
+                vao.bind();
+
+                gl::UniformMatrix4fv(10, 1, gl::TRUE, transformation_matrix.as_ptr());
+                gl::UniformMatrix4fv(11, 1, gl::TRUE, model_matrix.as_ptr());
+                gl::Uniform3fv(46, 1, light_position.as_ptr());
+                gl::Uniform1f(43, settings.range);
+
+                gl::DrawElements(
+                    gl::TRIANGLES,
+                    node.index_count,
+                    gl::UNSIGNED_INT,
+                    ptr::null(),
+                );
+                vao.fence();
+            }
+        }
+    }
+}
+
+// Sets up depth testing for `mode`, to be called before clearing the depth buffer
+unsafe fn apply_depth_mode(mode: DepthMode) {
+    match mode {
+        DepthMode::ReversedZ => {
+            gl::ClipControl(gl::LOWER_LEFT, gl::ZERO_TO_ONE);
+            gl::DepthFunc(gl::GREATER);
+            gl::ClearDepth(0.0);
+        }
+        DepthMode::Standard | DepthMode::Logarithmic => {
+            gl::ClipControl(gl::LOWER_LEFT, gl::NEGATIVE_ONE_TO_ONE);
+            gl::DepthFunc(gl::LESS);
+            gl::ClearDepth(1.0);
+        }
+    }
+}
+
+// Each planet spins inside a system node that carries its position, and moons
+// hang off an orbit pivot inside the system node of the planet they circle
+struct PlanetNodes {
+    system: NodeId,
+    orbit: Option<NodeId>,
+    body: NodeId,
+}
+
+// A model imported into the scene. Its place is kept in double precision like
+// the planets, and laid out around the camera with them every frame.
+struct ModelNode {
+    node: NodeId,
+    position: glm::DVec3,
+}
+
+fn build_scene(
+    planets: &[Planet],
+    scene_node: impl Fn(&Planet) -> SceneNode,
+) -> (SceneGraph, Vec<PlanetNodes>) {
+    let mut scene = SceneGraph::new();
+    let mut planet_nodes: Vec<PlanetNodes> = Vec::with_capacity(planets.len());
+
+    for planet in planets {
+        let nodes = add_planet_nodes(&mut scene, &planet_nodes, planet, scene_node(planet));
+        planet_nodes.push(nodes);
+    }
+    (scene, planet_nodes)
+}
+
+// `planet_nodes` holds the nodes of the planets before this one, including its parent
+fn add_planet_nodes(
+    scene: &mut SceneGraph,
+    planet_nodes: &[PlanetNodes],
+    planet: &Planet,
+    body: SceneNode,
+) -> PlanetNodes {
+    let parent_system = planet
+        .parent
+        .and_then(|parent| planet_nodes.get(parent))
+        .map(|nodes| nodes.system);
+    let orbit = parent_system
+        .map(|system| scene.add(SceneNode::empty(glm::vec3(0.0, 0.0, 0.0)), Some(system)));
+    let system = scene.add(SceneNode::empty(planet.position.cast()), orbit);
+    let body = scene.add(body, Some(system));
+
+    PlanetNodes {
+        system,
+        orbit,
+        body,
+    }
+}
+
+// Hides the cursor and keeps it in the window so mouse movement can steer the
+// free camera indefinitely
+fn capture_cursor(window: &Window, captured: bool) {
+    let grabbed = if captured {
+        window
+            .set_cursor_grab(CursorGrabMode::Confined)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Locked))
+    } else {
+        window.set_cursor_grab(CursorGrabMode::None)
+    };
+    if let Err(e) = grabbed {
+        eprintln!("Could not grab the cursor: {}", e);
     }
+    window.set_cursor_visible(!captured);
+}
+
+// Removes the planet at `index` along with every moon circling it, and returns
+// where each of the old indices went
+fn remove_planet(
+    scene: &mut SceneGraph,
+    planets: &mut Vec<Planet>,
+    planet_nodes: &mut Vec<PlanetNodes>,
+    index: usize,
+) -> Vec<Option<usize>> {
+    let nodes = &planet_nodes[index];
+    scene.remove(nodes.orbit.unwrap_or(nodes.system));
+
+    // Parents come before their moons, so one pass finds the whole family
+    let mut removed = vec![false; planets.len()];
+    for i in index..planets.len() {
+        removed[i] = i == index || planets[i].parent.is_some_and(|parent| removed[parent]);
+    }
+
+    // Moons refer to their parent by index, which shifts down past removed planets
+    let mut new_index = Vec::with_capacity(planets.len());
+    let mut kept = 0;
+    for &r in &removed {
+        if r {
+            new_index.push(None);
+        } else {
+            new_index.push(Some(kept));
+            kept += 1;
+        }
+    }
+
+    let mut i = 0;
+    planets.retain(|_| {
+        i += 1;
+        !removed[i - 1]
+    });
+    let mut i = 0;
+    planet_nodes.retain(|_| {
+        i += 1;
+        !removed[i - 1]
+    });
+    for planet in planets.iter_mut() {
+        planet.parent = planet.parent.and_then(|parent| new_index[parent]);
+    }
+    new_index
+}
+
+// Planets circling nothing are placed relative to `origin`, the camera, so
+// the single precision scene graph only ever holds small offsets around it.
+// Moons stay relative to their parent.
+fn update_planet_nodes(
+    scene: &mut SceneGraph,
+    planets: &[Planet],
+    planet_nodes: &[PlanetNodes],
+    time: f32,
+    origin: &glm::DVec3,
+) {
+    for (planet, nodes) in planets.iter().zip(planet_nodes) {
+        let position: glm::Vec3 = match planet.parent {
+            Some(_) => planet.position.cast(),
+            None => (planet.position - origin).cast(),
+        };
+        if scene[nodes.system].position() != position {
+            scene[nodes.system].set_position(position);
+        }
+        let radius = planet.radius as f32;
+        if scene[nodes.body].scale() != glm::vec3(radius, radius, radius) {
+            scene[nodes.body].set_scale(glm::vec3(radius, radius, radius));
+        }
+        scene[nodes.body].set_orientation(planet.orientation_at(time));
+        scene[nodes.body].emission = planet.star.map(|star| star.emission());
+        scene[nodes.body].atmosphere = planet.atmosphere;
+        scene[nodes.body].clouds = planet.clouds;
+        match (&mut scene[nodes.body].rings, &planet.rings) {
+            (Some(layer), Some(rings)) => unsafe { layer.update(rings) },
+            (layer, rings) => {
+                *layer = rings.as_ref().map(|rings| unsafe { RingLayer::new(rings) });
+            }
+        }
+        if let Some(terrain) = &mut scene[nodes.body].terrain {
+            terrain.noise = planet.noise;
+            terrain.biome.clone_from(&planet.biome);
+        }
+        if let Some(orbit) = nodes.orbit {
+            scene[orbit].set_orientation(planet.orbit_orientation_at(time));
+        }
+    }
+}
+
+fn update_model_nodes(scene: &mut SceneGraph, models: &[ModelNode], origin: &glm::DVec3) {
+    for model in models {
+        let position: glm::Vec3 = (model.position - origin).cast();
+        if scene[model.node].position() != position {
+            scene[model.node].set_position(position);
+        }
+    }
+}
+
+// The value following `--scene` on the command line, if any
+fn scene_argument() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--scene" {
+            return args.next();
+        }
+    }
+    None
 }
 
 fn main() {
+    // Fail before opening a window if the requested scene can not be read
+    let initial_scene = match scene_argument() {
+        Some(path) => SceneFile::load(&path)
+            .unwrap_or_else(|e| panic!("Failed to load scene {}: {}", path, e)),
+        None => SceneFile::default(),
+    };
+
     let event_loop = glutin::event_loop::EventLoop::new();
     let window_builder = glutin::window::WindowBuilder::new()
         .with_title("studios-systems")
@@ -111,7 +692,7 @@ fn main() {
     let mut winit_platform = imgui_winit_support::WinitPlatform::init(&mut imgui);
     winit_platform.attach_window(
         imgui.io_mut(),
-        &context.window(),
+        context.window(),
         imgui_winit_support::HiDpiMode::Rounded,
     );
 
@@ -137,81 +718,234 @@ fn main() {
         gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
     }
 
-    let mut planet_detail = 6;
+    // The scene is drawn here first, in floating point so suns can be brighter
+    // than white, and with a floating point depth buffer so reversed-Z keeps
+    // its precision all the way out
+    let new_scene_framebuffer = |width, height| unsafe {
+        let framebuffer = gpu::Framebuffer::new(width, height, gl::RGBA16F, gl::DEPTH_COMPONENT32F);
+        // Filtered, as the bloom samples it between texels
+        if let Some(color) = framebuffer.color() {
+            color.set_filter(gl::LINEAR);
+        }
+        framebuffer
+    };
+    let mut scene_framebuffer = new_scene_framebuffer(window_size.0, window_size.1);
+    // What the atmospheres read the scene's depth from, as they cannot read
+    // the buffer they are drawn into
+    let new_scene_depth = |framebuffer: &gpu::Framebuffer| unsafe {
+        gpu::Texture::new_2d(
+            gl::DEPTH_COMPONENT32F,
+            framebuffer.width(),
+            framebuffer.height(),
+        )
+    };
+    let mut scene_depth = new_scene_depth(&scene_framebuffer);
+    let mut post_process = unsafe { PostProcess::new(window_size.0, window_size.1) };
+    let mut post_settings = PostSettings::new();
+
+    let mut persistent_mapping = false;
 
-    let shape_shader = unsafe {
+    let mut shape_shader = unsafe {
         shader::ShaderBuilder::new()
+            .include(COMMON_SHADER)
             .attach_file("./shaders/shape.vert")
+            .attach_file_as(NOISE_SHADER, ShaderType::Vertex)
             .attach_file("./shaders/shape.frag")
+            .attach_file_as(NOISE_SHADER, ShaderType::Fragment)
             .link()
     };
 
-    let light_shader = unsafe {
+    let mut light_shader = unsafe {
         shader::ShaderBuilder::new()
             .attach_file("./shaders/light.vert")
             .attach_file("./shaders/light.frag")
             .link()
     };
 
-    let material = Material {
-        ambient: glm::vec3(1.0, 0.7, 0.81),
-        diffuse: glm::vec3(1.0, 0.5, 0.31),
-        specular: glm::vec3(0.5, 0.5, 0.5),
-        shininess: 32.0,
+    // Imported models are lit like planets, but without the terrain displacement
+    let mut model_shader = unsafe {
+        shader::ShaderBuilder::new()
+            .include(COMMON_SHADER)
+            .attach_file("./shaders/light.vert")
+            .attach_file("./shaders/shape.frag")
+            .attach_file_as(NOISE_SHADER, ShaderType::Fragment)
+            .link()
+    };
+
+    // Distance from a light to what it shines on, for shadows. Terrain keeps
+    // its displacement, so mountains shade the valleys around them.
+    let shadow_shader = unsafe {
+        shader::ShaderBuilder::new()
+            .attach_file("./shaders/light.vert")
+            .attach_file("./shaders/shadow.frag")
+            .link()
+    };
+    let terrain_shadow_shader = unsafe {
+        shader::ShaderBuilder::new()
+            .include(COMMON_SHADER)
+            .attach_file("./shaders/shape.vert")
+            .attach_file_as(NOISE_SHADER, ShaderType::Vertex)
+            .attach_file("./shaders/shadow.frag")
+            .link()
+    };
+
+    // Scattering through the air around planets
+    let mut atmosphere_shader = unsafe {
+        shader::ShaderBuilder::new()
+            .include(COMMON_SHADER)
+            .attach_file("./shaders/light.vert")
+            .attach_file("./shaders/atmosphere.frag")
+            .link()
+    };
+
+    // The sky behind everything, and the stars in it
+    let sky_shader = unsafe {
+        shader::ShaderBuilder::new()
+            .include(COMMON_SHADER)
+            .attach_file("./shaders/sky.vert")
+            .attach_file("./shaders/sky.frag")
+            .attach_file_as(NOISE_SHADER, ShaderType::Fragment)
+            .link()
+    };
+    let stars_shader = unsafe {
+        shader::ShaderBuilder::new()
+            .attach_file("./shaders/stars.vert")
+            .attach_file("./shaders/stars.frag")
+            .link()
     };
 
-    let mut light_source = Light {
-        position: glm::vec3(0.0, 10.0, 0.0),
-        ambient: glm::vec3(0.2, 0.2, 0.2),
-        diffuse: glm::vec3(0.5, 0.5, 0.5),
-        specular: glm::vec3(1.0, 1.0, 1.0),
+    // Ring systems, shaded by the planet they circle
+    let mut rings_shader = unsafe {
+        shader::ShaderBuilder::new()
+            .include(COMMON_SHADER)
+            .attach_file("./shaders/light.vert")
+            .attach_file("./shaders/rings.frag")
+            .link()
     };
 
-    let light_material = Material {
-        ambient: glm::vec3(1.0, 1.0, 1.0),
-        diffuse: light_source.diffuse,
-        specular: light_source.specular,
-        shininess: 32.0,
+    // Cloud layers, with the same noise the terrain is made of
+    let mut clouds_shader = unsafe {
+        shader::ShaderBuilder::new()
+            .include(COMMON_SHADER)
+            .attach_file("./shaders/light.vert")
+            .attach_file("./shaders/clouds.frag")
+            .attach_file_as(NOISE_SHADER, ShaderType::Fragment)
+            .link()
     };
 
-    let mut planet_1 = Planet::new(
-        100.0,
-        glm::vec3(0.0, 0.0, 0.0),
+    // Bloom and tone mapping, from the scene's framebuffer to the window
+    let bloom_downsample_shader = unsafe {
+        shader::ShaderBuilder::new()
+            .attach_file("./shaders/post.vert")
+            .attach_file("./shaders/bloom_downsample.frag")
+            .link()
+    };
+    let bloom_upsample_shader = unsafe {
+        shader::ShaderBuilder::new()
+            .attach_file("./shaders/post.vert")
+            .attach_file("./shaders/bloom_upsample.frag")
+            .link()
+    };
+    let tonemap_shader = unsafe {
+        shader::ShaderBuilder::new()
+            .attach_file("./shaders/post.vert")
+            .attach_file("./shaders/tonemap.frag")
+            .link()
+    };
+
+    // Whether the programs writing their own depth are built for logarithmic
+    // depth, which they are not to begin with
+    let mut shaders_log_depth = false;
+
+    // Stars are drawn unlit, everything else with displaced, lit terrain that
+    // can be updated in place
+    let shape_program = shape_shader.program_id();
+    let light_program = light_shader.program_id();
+    let planet_scene_node = move |planet: &Planet, persistent_mapping: bool| {
+        if planet.star.is_some() {
+            planet.generate_scene_node(light_program, VertexUpdates::Static)
+        } else if persistent_mapping {
+            planet.generate_scene_node(shape_program, VertexUpdates::PersistentRing)
+        } else {
+            planet.generate_scene_node(shape_program, VertexUpdates::SubData)
+        }
+    };
+
+    let material = Material::new();
+
+    // Filled in from `pending_scene` at the start of the first frame, and again
+    // whenever a scene is loaded from the UI
+    let mut pending_scene = Some(initial_scene);
+    let mut planets: Vec<Planet> = Vec::new();
+    let mut scene = SceneGraph::new();
+    let mut planet_nodes: Vec<PlanetNodes> = Vec::new();
+    let mut model_nodes: Vec<ModelNode> = Vec::new();
+    // The planet the settings window edits
+    let mut selected: Option<usize> = None;
+
+    let mut lights: Vec<Light> = Vec::new();
+    let mut background = Background::default();
+    let mut sky = unsafe { Sky::new(&background) };
+    let mut selected_light: Option<usize> = None;
+    // Backs the `Lights` uniform block of shape.frag
+    let light_buffer = unsafe { gpu::Buffer::new(gl::UNIFORM_BUFFER) };
+
+    // Drawn around each planet with an atmosphere, scaled to fit
+    let mut atmosphere_shell = SceneNode::from_mesh(
+        &sphere::Sphere::new(3, material).mesh,
+        atmosphere_shader.program_id(),
         glm::vec3(0.0, 0.0, 0.0),
-        0.0,
-        material,
-        planet_detail,
+        VertexUpdates::Static,
     );
 
-    let sun = Planet::new(
-        100.0,
-        light_source.position,
+    // Drawn around each planet with clouds, finer so the layer stays round
+    // close up
+    let mut cloud_shell = SceneNode::from_mesh(
+        &sphere::Sphere::new(4, material).mesh,
+        clouds_shader.program_id(),
         glm::vec3(0.0, 0.0, 0.0),
-        0.0,
-        light_material,
-        planet_detail,
+        VertexUpdates::Static,
     );
 
-    let mut scene = vec![
-        sun.generate_scene_node(light_shader.program_id),
-        planet_1.generate_scene_node(shape_shader.program_id),
-    ];
+    let mut shadow_settings = ShadowSettings::new();
+    let shadow_maps = unsafe { ShadowMaps::new(SHADOW_MAP_RESOLUTION) };
 
     let first_frame_time = std::time::Instant::now();
     let mut previous_frame_time = first_frame_time;
 
-    let mut cam_pos: glm::Vec3 = glm::vec3(0.0, 0.0, 5.0);
-    let mut cam_dir: glm::Vec3 = glm::vec3(0.0, 0.0, 0.0);
-    let mut yaw: f32 = -90.0;
-    let mut pitch: f32 = 0.0;
-
-    let mut cam_front: glm::Vec3 = glm::vec3(0.0, 0.0, -1.0);
-    let cam_up: glm::Vec3 = glm::vec3(0.0, 1.0, 0.0);
-
-    let move_speed: f32 = 10.0;
-    let cam_speed: f32 = 100.0;
-
-    let mut noise = noise::Noise::new();
+    let mut camera = Camera::new(glm::vec3(0.0, 0.0, 5.0));
+    let cam_speed: f32 = 100.0_f32.to_radians();
+    // Radians per pixel of mouse movement
+    let mouse_sensitivity: f32 = 0.005;
+
+    // Keyframed flythrough, and how far into it playback is
+    let mut camera_path = CameraPath::new();
+    let mut playback: Option<f32> = None;
+    let mut loop_playback = false;
+    let mut keyframe_time: f32 = 0.0;
+
+    // Mouse input gathered between frames, ignored while imgui is using the mouse
+    let mut dragging = false;
+    // Free mode hides the cursor and turns with every mouse movement while captured
+    let mut mouse_captured = false;
+    let mut mouse_delta = glm::vec2(0.0, 0.0);
+    let mut scroll: f32 = 0.0;
+
+    // Built-in presets come first, followed by the ones users saved
+    let builtin_preset_count = Preset::builtin().len();
+    let mut presets = Preset::builtin();
+    match Preset::load_user(USER_PRESETS_PATH) {
+        Ok(user_presets) => presets.extend(user_presets),
+        Err(e) => println!("Failed to load {}: {}", USER_PRESETS_PATH, e),
+    }
+    let mut preset_index: usize = 0;
+    let mut preset_name = String::new();
+    let mut scene_path = String::from("./scenes/scene.ron");
+    let mut model_path = String::new();
+    let mut ring_image_path = String::new();
+    let mut skybox_paths: [String; 6] = Default::default();
+    let mut crater_radius: f32 = 0.3;
+    let mut crater_depth: f32 = 0.1;
 
     // Start the event loop -- This is where window events are initially handled
     event_loop.run(move |event, _, control_flow| {
@@ -273,7 +1007,7 @@ fn main() {
                 let gl_window = context.window();
 
                 winit_platform
-                    .prepare_frame(imgui.io_mut(), &gl_window)
+                    .prepare_frame(imgui.io_mut(), gl_window)
                     .expect("Failed to prepare frame");
 
                 gl_window.request_redraw();
@@ -281,7 +1015,7 @@ fn main() {
             Event::RedrawRequested(_) => {
                 // Compute time passed since the previous frame and since the start of the program
                 let now = std::time::Instant::now();
-                let _elapsed = now.duration_since(first_frame_time).as_secs_f32();
+                let elapsed = now.duration_since(first_frame_time).as_secs_f32();
                 let delta_time = now.duration_since(previous_frame_time).as_secs_f32();
                 previous_frame_time = now;
 
@@ -295,7 +1029,7 @@ fn main() {
 
                     winit_platform.attach_window(
                         imgui.io_mut(),
-                        &context.window(),
+                        context.window(),
                         imgui_winit_support::HiDpiMode::Default,
                     );
 
@@ -305,152 +1039,1131 @@ fn main() {
                     unsafe {
                         gl::Viewport(0, 0, window_size.0 as i32, window_size.1 as i32);
                     }
+                    scene_framebuffer = new_scene_framebuffer(window_size.0, window_size.1);
+                    scene_depth = new_scene_depth(&scene_framebuffer);
+                    post_process = unsafe { PostProcess::new(window_size.0, window_size.1) };
                 }
 
                 // Handle keyboard input
+                let mut movement = glm::vec3(0.0, 0.0, 0.0);
+                let mut turn = glm::vec3(0.0, 0.0, 0.0);
                 for key in pressed_keys.iter() {
                     match key {
                         // The `VirtualKeyCode` enum is defined here:
                         //    https://docs.rs/winit/0.25.0/winit/event/enum.VirtualKeyCode.html
-                        VirtualKeyCode::D => {
-                            //print
-                            cam_pos += move_speed
-                                * delta_time
-                                * glm::normalize(&glm::cross(&cam_front, &cam_up));
-                        }
-                        VirtualKeyCode::A => {
-                            cam_pos -= move_speed
-                                * delta_time
-                                * glm::normalize(&glm::cross(&cam_front, &cam_up));
-                        }
+                        VirtualKeyCode::D => movement.x += 1.0,
+                        VirtualKeyCode::A => movement.x -= 1.0,
 
-                        VirtualKeyCode::Space => {
-                            cam_pos += move_speed * delta_time * cam_up;
-                        }
-                        VirtualKeyCode::LShift => {
-                            cam_pos -= move_speed * delta_time * cam_up;
-                        }
+                        VirtualKeyCode::Space => movement.y += 1.0,
+                        VirtualKeyCode::LShift => movement.y -= 1.0,
 
-                        VirtualKeyCode::W => {
-                            cam_pos += move_speed * delta_time * cam_front;
-                        }
-                        VirtualKeyCode::S => {
-                            cam_pos -= move_speed * delta_time * cam_front;
-                        }
+                        VirtualKeyCode::W => movement.z -= 1.0,
+                        VirtualKeyCode::S => movement.z += 1.0,
 
-                        VirtualKeyCode::Up => {
-                            pitch += delta_time * cam_speed;
-                        }
-                        VirtualKeyCode::Down => {
-                            pitch -= delta_time * cam_speed;
-                        }
+                        VirtualKeyCode::Up => turn.y += 1.0,
+                        VirtualKeyCode::Down => turn.y -= 1.0,
 
-                        VirtualKeyCode::Left => {
-                            yaw -= delta_time * cam_speed;
-                        }
-                        VirtualKeyCode::Right => {
-                            yaw += delta_time * cam_speed;
-                        }
+                        VirtualKeyCode::Left => turn.x += 1.0,
+                        VirtualKeyCode::Right => turn.x -= 1.0,
+
+                        VirtualKeyCode::Z => turn.z += 1.0,
+                        VirtualKeyCode::X => turn.z -= 1.0,
 
                         // default handler:
                         _ => {}
                     }
                 }
 
-                unsafe {
-                    let mut transformation_matrix: glm::Mat4 = glm::identity();
-                    let mut view_matrix: glm::Mat4 = glm::identity();
-
-                    cam_dir.x = yaw.to_radians().cos() * pitch.to_radians().cos();
-                    cam_dir.y = pitch.to_radians().sin();
-                    cam_dir.z = yaw.to_radians().sin() * pitch.to_radians().cos();
-
-                    cam_front = glm::normalize(&cam_dir);
-
-                    let look_at: glm::Mat4 =
-                        glm::look_at(&cam_pos, &(cam_pos + cam_front), &cam_up);
-
-                    view_matrix = look_at * view_matrix;
-
-                    let projection_matrix: glm::Mat4 =
-                        glm::perspective(window_aspect_ratio, glm::half_pi(), 1.0, 100.0);
+                match camera.mode {
+                    CameraMode::Free => {
+                        // Empty scenes fly at a fixed pace
+                        let world_positions = Planet::world_positions(&planets, elapsed);
+                        let altitude = camera
+                            .altitude(
+                                world_positions
+                                    .into_iter()
+                                    .zip(planets.iter().map(|planet| planet.radius)),
+                            )
+                            .unwrap_or(10.0);
+                        let speed = camera.speed * altitude.max(0.01) as f32;
+                        let look = mouse_delta * mouse_sensitivity;
+                        camera.fly(
+                            movement * speed * delta_time,
+                            glm::vec3(
+                                turn.y * cam_speed * delta_time - look.y,
+                                turn.x * cam_speed * delta_time - look.x,
+                                turn.z * cam_speed * delta_time,
+                            ),
+                        );
+                    }
+                    CameraMode::Orbit => {
+                        camera.rotate_orbit(
+                            turn.x * cam_speed * delta_time - mouse_delta.x * mouse_sensitivity,
+                            turn.y * cam_speed * delta_time - mouse_delta.y * mouse_sensitivity,
+                        );
+                        camera.zoom(scroll - movement.z * delta_time * 10.0);
+                    }
+                }
+                mouse_delta = glm::vec2(0.0, 0.0);
+                scroll = 0.0;
 
-                    transformation_matrix = projection_matrix * view_matrix * transformation_matrix;
+                if mouse_captured && camera.mode != CameraMode::Free {
+                    mouse_captured = false;
+                    capture_cursor(context.window(), false);
+                }
 
-                    // Clear the color and depth buffers
-                    gl::ClearColor(0.035, 0.046, 0.078, 1.0); // night sky, full opacity
-                    gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+                if let Some(scene_file) = pending_scene.take() {
+                    planets = scene_file.planets();
+                    lights = scene_file.lights();
+                    background = scene_file.background();
+                    // Shown in the UI for the scene's own skybox, if it has one
+                    skybox_paths = background.skybox.clone().unwrap_or_default();
+                    selected_light = None;
+                    shadow_settings.fit_to(&planets);
+                    camera = scene_file.camera.to_camera();
+                    camera_path = scene_file.camera_path();
+                    keyframe_time = camera_path.duration();
+                    playback = None;
+                    selected = planets.iter().position(|planet| planet.star.is_none());
+
+                    // Dropping the old graph releases its GL objects
+                    (scene, planet_nodes) = build_scene(&planets, |planet| {
+                        planet_scene_node(planet, persistent_mapping)
+                    });
+                    model_nodes.clear();
+                }
 
+                unsafe {
                     let window = context.window();
 
                     let ui = imgui.frame();
 
+                    let planet_detail = selected.map_or(0, |i| planets[i].detail());
                     let mut new_detail_ui = planet_detail;
+                    let mut follow = None;
+                    let mut star_toggled = false;
+                    let mut add_planet = false;
+                    let mut duplicate_planet = false;
+                    let mut delete_planet = false;
+                    let mut apply_preset = false;
+                    let mut save_preset = false;
+                    let mut save_scene = false;
+                    let mut load_scene = false;
+                    let mut export_extension = None;
+                    let mut load_model = false;
+                    let mut impact = false;
+                    let mut new_persistent_mapping = persistent_mapping;
 
                     ui.window("Settings")
                         .size([300.0, 500.0], Condition::FirstUseEver)
                         .build(|| {
                             ui.text(format!("FPS: {}", (1.0 / delta_time).ceil()));
+                            if cfg!(debug_assertions) {
+                                let live = gpu::live_objects();
+                                ui.text(format!(
+                                    "GL objects: {} buffers, {} VAOs, {} programs",
+                                    live.buffers, live.vertex_arrays, live.programs
+                                ));
+                                ui.text(format!(
+                                    "            {} textures, {} framebuffers",
+                                    live.textures, live.framebuffers
+                                ));
+                            }
+                            ui.separator();
+
+                            ui.input_text("scene", &mut scene_path).build();
+                            save_scene = ui.button("Save scene");
+                            ui.same_line();
+                            load_scene = ui.button("Load scene");
+
+                            ui.separator();
+
+                            if CollapsingHeader::new("Camera").build(ui) {
+                                let mut orbit = camera.mode == CameraMode::Orbit;
+                                if ui.checkbox("orbit", &mut orbit) {
+                                    camera.set_mode(if orbit {
+                                        CameraMode::Orbit
+                                    } else {
+                                        CameraMode::Free
+                                    });
+                                }
+
+                                // Entry 0 stops following, the rest are the planets
+                                let mut names = vec!["None"];
+                                names.extend(planets.iter().map(|p| p.name.as_str()));
+                                let mut target = camera.target.map_or(0, |t| t + 1);
+                                if ui.combo_simple_string("follow", &mut target, &names) {
+                                    follow = Some(target.checked_sub(1));
+                                }
+                                ui.slider("transition", 0.0, 5.0, &mut camera.transition_time);
+                                ui.slider("fly speed", 0.1, 5.0, &mut camera.speed);
+                                if camera.mode == CameraMode::Free {
+                                    ui.text("Right click to capture the mouse, Z/X to roll");
+                                }
+
+                                let mut fov = camera.fov.to_degrees();
+                                if ui.slider("fov", 10.0, 120.0, &mut fov) {
+                                    camera.fov = fov.to_radians();
+                                }
+
+                                let depth_modes = [
+                                    DepthMode::Standard,
+                                    DepthMode::ReversedZ,
+                                    DepthMode::Logarithmic,
+                                ];
+                                let mut depth_index = depth_modes
+                                    .iter()
+                                    .position(|&mode| mode == camera.depth_mode)
+                                    .unwrap_or(0);
+                                if ui.combo_simple_string(
+                                    "depth",
+                                    &mut depth_index,
+                                    &["Standard", "Reversed-Z", "Logarithmic"],
+                                ) {
+                                    camera.depth_mode = depth_modes[depth_index];
+                                }
+                                if ui.input_float("near", &mut camera.near).build() {
+                                    camera.near = camera.near.clamp(1e-6, camera.far * 0.5);
+                                }
+                                if ui.input_float("far", &mut camera.far).build() {
+                                    camera.far = camera.far.max(camera.near * 2.0);
+                                }
+                            }
+
+                            if CollapsingHeader::new("Camera path").build(ui) {
+                                let mut remove = None;
+                                for (i, keyframe) in camera_path.keyframes().iter().enumerate() {
+                                    let _id = ui.push_id_usize(i);
+                                    ui.text(format!("{:.2}s", keyframe.time));
+                                    ui.same_line();
+                                    if ui.small_button("Go to") {
+                                        playback = None;
+                                        camera.set_mode(CameraMode::Free);
+                                        keyframe.apply(&mut camera);
+                                    }
+                                    ui.same_line();
+                                    if ui.small_button("Remove") {
+                                        remove = Some(i);
+                                    }
+                                }
+                                if let Some(i) = remove {
+                                    camera_path.remove(i);
+                                }
+
+                                ui.input_float("time", &mut keyframe_time).build();
+                                if ui.button("Add keyframe") {
+                                    camera_path
+                                        .insert(Keyframe::capture(keyframe_time.max(0.0), &camera));
+                                    keyframe_time = camera_path.duration() + 2.0;
+                                }
+
+                                match playback {
+                                    Some(time) => {
+                                        if ui.button("Stop") {
+                                            playback = None;
+                                        }
+                                        ui.same_line();
+                                        ui.text(format!(
+                                            "{:.2} / {:.2}s",
+                                            time,
+                                            camera_path.duration()
+                                        ));
+                                    }
+                                    None => {
+                                        if ui.button("Play") && !camera_path.is_empty() {
+                                            // The path drives the camera, so keep orbit out of the way
+                                            camera.set_mode(CameraMode::Free);
+                                            playback = Some(0.0);
+                                        }
+                                    }
+                                }
+                                ui.checkbox("loop", &mut loop_playback);
+                            }
+
+                            if CollapsingHeader::new("Lights").build(ui) {
+                                for (i, light) in lights.iter().enumerate() {
+                                    let label = format!("{:?} light##light{}", light.kind, i);
+                                    if ui
+                                        .selectable_config(&label)
+                                        .selected(selected_light == Some(i))
+                                        .build()
+                                    {
+                                        selected_light = Some(i);
+                                    }
+                                }
+                                if lights.len() < MAX_LIGHTS && ui.button("Add light") {
+                                    // New lights start out where the camera is
+                                    lights.push(Light::point(camera.position));
+                                    selected_light = Some(lights.len() - 1);
+                                }
+                                if let Some(i) = selected_light {
+                                    ui.same_line();
+                                    if ui.button("Remove light") {
+                                        lights.remove(i);
+                                        selected_light = None;
+                                    }
+                                }
+
+                                if let Some(light) = selected_light.map(|i| &mut lights[i]) {
+                                    let _id = ui.push_id("light");
+                                    let kinds =
+                                        [LightKind::Point, LightKind::Directional, LightKind::Spot];
+                                    let mut kind_index =
+                                        kinds.iter().position(|&k| k == light.kind).unwrap_or(0);
+                                    if ui.combo_simple_string(
+                                        "kind",
+                                        &mut kind_index,
+                                        &["Point", "Directional", "Spot"],
+                                    ) {
+                                        light.kind = kinds[kind_index];
+                                    }
+
+                                    if light.kind != LightKind::Directional {
+                                        let mut position: [f64; 3] = light.position.into();
+                                        if ui.input_scalar_n("position", &mut position).build() {
+                                            light.position = position.into();
+                                        }
+                                    }
+                                    if light.kind != LightKind::Point {
+                                        let mut direction: [f32; 3] = light.direction.into();
+                                        if ui.input_float3("direction", &mut direction).build()
+                                            && direction != [0.0; 3]
+                                        {
+                                            light.direction = direction.into();
+                                        }
+                                    }
+
+                                    let mut ambient: [f32; 3] = light.ambient.into();
+                                    let mut diffuse: [f32; 3] = light.diffuse.into();
+                                    let mut specular: [f32; 3] = light.specular.into();
+                                    if ui.color_edit3("ambient", &mut ambient) {
+                                        light.ambient = ambient.into();
+                                    }
+                                    if ui.color_edit3("diffuse", &mut diffuse) {
+                                        light.diffuse = diffuse.into();
+                                    }
+                                    if ui.color_edit3("specular", &mut specular) {
+                                        light.specular = specular.into();
+                                    }
+
+                                    if light.kind != LightKind::Directional {
+                                        // Constant, linear and quadratic falloff
+                                        let mut attenuation: [f32; 3] = light.attenuation.into();
+                                        if ui.input_float3("attenuation", &mut attenuation).build()
+                                        {
+                                            light.attenuation = attenuation.into();
+                                        }
+                                    }
+                                    if light.kind == LightKind::Spot {
+                                        let mut inner = light.inner_angle.to_degrees();
+                                        let mut outer = light.outer_angle.to_degrees();
+                                        if ui.slider("inner angle", 0.0, 90.0, &mut inner) {
+                                            light.inner_angle = inner.to_radians();
+                                        }
+                                        if ui.slider("outer angle", 0.0, 90.0, &mut outer) {
+                                            light.outer_angle = outer.to_radians();
+                                        }
+                                    }
+                                }
+                            }
+
+                            if CollapsingHeader::new("Shadows").build(ui) {
+                                let _id = ui.push_id("shadows");
+                                ui.checkbox("enabled", &mut shadow_settings.enabled);
+                                ui.text(format!(
+                                    "The first {} point and spot lights cast shadows",
+                                    shadow::MAX_SHADOW_LIGHTS
+                                ));
+                                ui.slider_config("range", 1.0, 1.0e9)
+                                    .flags(SliderFlags::LOGARITHMIC)
+                                    .build(&mut shadow_settings.range);
+                                ui.slider("bias", 0.0, 0.01, &mut shadow_settings.bias);
+                                ui.slider("softness", 0.0, 0.05, &mut shadow_settings.softness);
+                            }
+
+                            if CollapsingHeader::new("Background").build(ui) {
+                                let _id = ui.push_id("background");
+                                let starfield = &mut background.starfield;
+                                let mut seed = starfield.seed as i32;
+                                if ui.input_int("seed", &mut seed).build() {
+                                    starfield.seed = seed.max(0) as u64;
+                                }
+                                ui.slider("stars", 0, 50000, &mut starfield.count);
+                                ui.slider("brightness", 0.0, 5.0, &mut starfield.brightness);
+                                let mut sky_color: [f32; 3] = starfield.sky_color.into();
+                                if ui.color_edit3("sky color", &mut sky_color) {
+                                    starfield.sky_color = sky_color.into();
+                                }
+                                ui.checkbox("milky way", &mut starfield.milky_way);
+                                if starfield.milky_way {
+                                    ui.slider(
+                                        "milky way brightness",
+                                        0.0,
+                                        2.0,
+                                        &mut starfield.milky_way_brightness,
+                                    );
+                                    let mut pole: [f32; 3] = starfield.galactic_pole.into();
+                                    if ui.input_float3("galactic pole", &mut pole).build()
+                                        && glm::length(&glm::Vec3::from(pole)) > 0.0
+                                    {
+                                        starfield.galactic_pole = pole.into();
+                                    }
+                                }
+
+                                ui.text("Skybox images");
+                                for (path, face) in skybox_paths.iter_mut().zip(SKYBOX_FACES) {
+                                    ui.input_text(face, path).build();
+                                }
+                                if ui.button("Load skybox") {
+                                    background.skybox = Some(skybox_paths.clone());
+                                }
+                                ui.same_line();
+                                if ui.button("Starfield") {
+                                    background.skybox = None;
+                                }
+                            }
+
+                            if CollapsingHeader::new("Post processing").build(ui) {
+                                let _id = ui.push_id("post");
+                                let mut tone_mapper_index = ToneMapper::ALL
+                                    .iter()
+                                    .position(|&t| t == post_settings.tone_mapper)
+                                    .unwrap_or(0);
+                                let names = ToneMapper::ALL.map(|t| t.name());
+                                if ui.combo_simple_string(
+                                    "tone mapper",
+                                    &mut tone_mapper_index,
+                                    &names,
+                                ) {
+                                    post_settings.tone_mapper = ToneMapper::ALL[tone_mapper_index];
+                                }
+                                ui.slider("exposure", 0.05, 8.0, &mut post_settings.exposure);
+                                ui.checkbox("bloom", &mut post_settings.bloom);
+                                if post_settings.bloom {
+                                    ui.slider(
+                                        "threshold",
+                                        0.1,
+                                        10.0,
+                                        &mut post_settings.bloom_threshold,
+                                    );
+                                    ui.slider(
+                                        "intensity",
+                                        0.0,
+                                        1.0,
+                                        &mut post_settings.bloom_intensity,
+                                    );
+                                }
+                            }
+
                             ui.separator();
 
-                            if CollapsingHeader::new("Lightsource").build(ui) {
-                                ui.slider("Ambient r", 0.0, 1.0, &mut light_source.ambient.x);
-                                ui.slider("Ambient g", 0.0, 1.0, &mut light_source.ambient.y);
-                                ui.slider("Ambient b", 0.0, 1.0, &mut light_source.ambient.z);
+                            if CollapsingHeader::new("Bodies").default_open(true).build(ui) {
+                                for (i, planet) in planets.iter().enumerate() {
+                                    // Moons are indented under the planet they circle
+                                    let mut depth = 0;
+                                    let mut parent = planet.parent;
+                                    while let Some(p) = parent {
+                                        depth += 1;
+                                        parent = planets[p].parent;
+                                    }
+                                    let label =
+                                        format!("{}{}##body{}", "  ".repeat(depth), planet.name, i);
+                                    if ui
+                                        .selectable_config(&label)
+                                        .selected(selected == Some(i))
+                                        .build()
+                                    {
+                                        selected = Some(i);
+                                    }
+                                }
+                                add_planet = ui.button("Add");
+                                ui.same_line();
+                                duplicate_planet = ui.button("Duplicate");
+                                ui.same_line();
+                                delete_planet = ui.button("Remove");
+                            }
 
-                                ui.slider("Diffuse r", 0.0, 1.0, &mut light_source.diffuse.x);
-                                ui.slider("Diffuse g", 0.0, 1.0, &mut light_source.diffuse.y);
-                                ui.slider("Diffuse b", 0.0, 1.0, &mut light_source.diffuse.z);
+                            if let Some(planet) = selected.map(|i| &mut planets[i]) {
+                                ui.separator();
+
+                                ui.input_text("name", &mut planet.name).build();
+                                ui.input_scalar("mass", &mut planet.mass).build();
+
+                                let mut position: [f64; 3] = planet.position.into();
+                                if ui.input_scalar_n("position", &mut position).build() {
+                                    planet.position = position.into();
+                                }
+                                let mut velocity: [f64; 3] = planet.velocity.into();
+                                if ui.input_scalar_n("velocity", &mut velocity).build() {
+                                    planet.velocity = velocity.into();
+                                }
+
+                                let mut radius = planet.radius as f32;
+                                if ui.slider("radius", 0.01, 10.0, &mut radius) {
+                                    planet.radius = radius as f64;
+                                }
+                                ui.slider("Details", 1, 10, &mut new_detail_ui);
+                                if planet.parent.is_some() {
+                                    ui.slider(
+                                        "orbital period",
+                                        0.0,
+                                        120.0,
+                                        &mut planet.orbital_period,
+                                    );
+                                }
+
+                                if CollapsingHeader::new("Star").build(ui) {
+                                    let mut is_star = planet.star.is_some();
+                                    if ui.checkbox("star", &mut is_star) {
+                                        planet.star = is_star.then(Star::new);
+                                        star_toggled = true;
+                                    }
+                                    if let Some(star) = &mut planet.star {
+                                        ui.slider(
+                                            "temperature (K)",
+                                            1000.0,
+                                            40000.0,
+                                            &mut star.temperature,
+                                        );
+                                        ui.slider("intensity", 0.0, 10.0, &mut star.intensity);
+                                        let color: [f32; 3] = star.color().into();
+                                        ui.color_button(
+                                            "colour",
+                                            [color[0], color[1], color[2], 1.0],
+                                        );
+                                    }
+                                }
+
+                                if CollapsingHeader::new("Atmosphere").build(ui) {
+                                    let _id = ui.push_id("atmosphere");
+                                    let mut has_atmosphere = planet.atmosphere.is_some();
+                                    if ui.checkbox("atmosphere", &mut has_atmosphere) {
+                                        planet.atmosphere = has_atmosphere.then(Atmosphere::new);
+                                    }
+                                    if let Some(atmosphere) = &mut planet.atmosphere {
+                                        ui.text("Lengths are in planet radii");
+                                        ui.slider("height", 0.01, 1.0, &mut atmosphere.height);
+                                        ui.slider("density", 0.0, 5.0, &mut atmosphere.density);
+                                        let mut rayleigh: [f32; 3] = atmosphere.rayleigh.into();
+                                        if ui.input_float3("rayleigh", &mut rayleigh).build() {
+                                            atmosphere.rayleigh = rayleigh.into();
+                                        }
+                                        ui.slider(
+                                            "rayleigh scale height",
+                                            0.001,
+                                            0.2,
+                                            &mut atmosphere.rayleigh_scale_height,
+                                        );
+                                        ui.slider("mie", 0.0, 50.0, &mut atmosphere.mie);
+                                        ui.slider(
+                                            "mie scale height",
+                                            0.001,
+                                            0.2,
+                                            &mut atmosphere.mie_scale_height,
+                                        );
+                                        ui.slider(
+                                            "mie anisotropy",
+                                            0.0,
+                                            0.99,
+                                            &mut atmosphere.mie_anisotropy,
+                                        );
+                                        // The sun's colour seen from the ground at noon
+                                        let zenith: [f32; 3] =
+                                            atmosphere.zenith_transmittance().into();
+                                        ui.color_button(
+                                            "zenith transmittance",
+                                            [zenith[0], zenith[1], zenith[2], 1.0],
+                                        );
+                                    }
+                                }
+
+                                if CollapsingHeader::new("Clouds").build(ui) {
+                                    let _id = ui.push_id("clouds");
+                                    let mut has_clouds = planet.clouds.is_some();
+                                    if ui.checkbox("clouds", &mut has_clouds) {
+                                        planet.clouds = has_clouds.then(Clouds::new);
+                                    }
+                                    if let Some(clouds) = &mut planet.clouds {
+                                        ui.slider("altitude", 0.0, 0.5, &mut clouds.altitude);
+                                        ui.slider("coverage", 0.0, 1.0, &mut clouds.coverage);
+                                        ui.slider("density", 0.0, 1.0, &mut clouds.density);
+                                        let mut color: [f32; 3] = clouds.color.into();
+                                        if ui.color_edit3("color", &mut color) {
+                                            clouds.color = color.into();
+                                        }
+                                        ui.slider("wind speed", -1.0, 1.0, &mut clouds.wind_speed);
+                                        ui.checkbox("shadows", &mut clouds.shadows);
+
+                                        let noise = &mut clouds.noise;
+                                        ui.slider(
+                                            "base roughness",
+                                            0.0,
+                                            10.0,
+                                            &mut noise.base_roughness,
+                                        );
+                                        ui.slider("roughness", 0.0, 10.0, &mut noise.roughness);
+                                        ui.slider("persistence", 0.0, 1.0, &mut noise.persistence);
+                                        ui.slider("layers", 1, 10, &mut noise.num_layers);
+                                        ui.slider("center x", -1.0, 1.0, &mut noise.center[0]);
+                                        ui.slider("center y", -1.0, 1.0, &mut noise.center[1]);
+                                        ui.slider("center z", -1.0, 1.0, &mut noise.center[2]);
+                                    }
+                                }
+
+                                if CollapsingHeader::new("Rings").build(ui) {
+                                    let _id = ui.push_id("rings");
+                                    let mut has_rings = planet.rings.is_some();
+                                    if ui.checkbox("rings", &mut has_rings) {
+                                        planet.rings = has_rings.then(Rings::new);
+                                    }
+                                    if let Some(rings) = &mut planet.rings {
+                                        ui.text("Radii are in planet radii");
+                                        ui.slider(
+                                            "inner radius",
+                                            1.0,
+                                            5.0,
+                                            &mut rings.inner_radius,
+                                        );
+                                        ui.slider(
+                                            "outer radius",
+                                            rings.inner_radius + rings::MIN_WIDTH,
+                                            6.0,
+                                            &mut rings.outer_radius,
+                                        );
+                                        // Moving the inner edge out pushes the outer one along
+                                        rings.outer_radius = rings.edges().1;
+
+                                        // Read once the path is complete, not on every keystroke
+                                        ui.input_text("image", &mut ring_image_path).build();
+                                        if ui.button("Use image") {
+                                            rings.image = Some(ring_image_path.clone());
+                                        }
+                                        ui.same_line();
+                                        if ui.button("Use gradient") {
+                                            rings.image = None;
+                                        }
+
+                                        if rings.image.is_none() {
+                                            let mut remove_stop = None;
+                                            for (i, stop) in rings.stops.iter_mut().enumerate() {
+                                                let _id = ui.push_id_usize(i);
+                                                ui.slider("position", 0.0, 1.0, &mut stop.position);
+                                                let mut color: [f32; 3] = stop.color.into();
+                                                if ui.color_edit3("color", &mut color) {
+                                                    stop.color = color.into();
+                                                }
+                                                ui.slider("density", 0.0, 1.0, &mut stop.density);
+                                                if ui.button("Remove stop") {
+                                                    remove_stop = Some(i);
+                                                }
+                                            }
+                                            if let Some(i) = remove_stop {
+                                                rings.stops.remove(i);
+                                            }
+                                            if ui.button("Add stop") {
+                                                let last = rings.stops.last().copied();
+                                                rings.stops.push(RingStop {
+                                                    position: 1.0,
+                                                    color: last
+                                                        .map_or(glm::vec3(1.0, 1.0, 1.0), |stop| {
+                                                            stop.color
+                                                        }),
+                                                    density: last.map_or(1.0, |stop| stop.density),
+                                                });
+                                            }
+                                        }
+                                    }
+                                }
+
+                                if CollapsingHeader::new("Rotation").build(ui) {
+                                    let mut tilt_degrees = planet.axial_tilt.to_degrees();
+                                    if ui.slider("axial tilt", 0.0, 180.0, &mut tilt_degrees) {
+                                        planet.axial_tilt = tilt_degrees.to_radians();
+                                    }
+                                    ui.slider(
+                                        "day length",
+                                        0.0,
+                                        120.0,
+                                        &mut planet.rotation_period,
+                                    );
+                                    ui.slider(
+                                        "precession period",
+                                        0.0,
+                                        1000.0,
+                                        &mut planet.precession_period,
+                                    );
+                                }
+
+                                if CollapsingHeader::new("Material").build(ui) {
+                                    let _id = ui.push_id("material");
+                                    let mut material = planet.material();
+                                    let mut ambient: [f32; 3] = material.ambient.into();
+                                    let mut diffuse: [f32; 3] = material.diffuse.into();
+                                    let mut specular: [f32; 3] = material.specular.into();
+                                    let mut changed = ui.color_edit3("ambient", &mut ambient);
+                                    changed |= ui.color_edit3("diffuse", &mut diffuse);
+                                    changed |= ui.color_edit3("specular", &mut specular);
+                                    changed |=
+                                        ui.slider("shininess", 1.0, 256.0, &mut material.shininess);
+
+                                    let shadings = [Shading::Phong, Shading::Pbr];
+                                    let mut shading_index = shadings
+                                        .iter()
+                                        .position(|&s| s == material.shading)
+                                        .unwrap_or(0);
+                                    if ui.combo_simple_string(
+                                        "shading",
+                                        &mut shading_index,
+                                        &["Phong", "PBR"],
+                                    ) {
+                                        material.shading = shadings[shading_index];
+                                        changed = true;
+                                    }
+                                    if material.shading == Shading::Pbr {
+                                        ui.text("Diffuse is the albedo, biomes set the roughness");
+                                        changed |= ui.slider(
+                                            "roughness",
+                                            0.0,
+                                            1.0,
+                                            &mut material.roughness,
+                                        );
+                                        changed |=
+                                            ui.slider("metallic", 0.0, 1.0, &mut material.metallic);
+                                    }
+                                    if changed {
+                                        material.ambient = ambient.into();
+                                        material.diffuse = diffuse.into();
+                                        material.specular = specular.into();
+                                        planet.set_material(material);
+                                    }
+                                }
+
+                                if CollapsingHeader::new("Noise").build(ui) {
+                                    let _id = ui.push_id("noise");
+                                    let noise = &mut planet.noise;
+                                    ui.slider("strength", 0.0, 10.0, &mut noise.strength);
+
+                                    ui.slider(
+                                        "base roughness",
+                                        0.0,
+                                        10.0,
+                                        &mut noise.base_roughness,
+                                    );
+                                    ui.slider("roughness", 0.0, 10.0, &mut noise.roughness);
+
+                                    ui.slider("persistence", 0.0, 1.0, &mut noise.persistence);
+                                    ui.slider("layers", 1, 10, &mut noise.num_layers);
+                                    ui.slider("min value", 0.0, 10.0, &mut noise.min_value);
+
+                                    ui.slider("center x", -1.0, 1.0, &mut noise.center[0]);
+                                    ui.slider("center y", -1.0, 1.0, &mut noise.center[1]);
+                                    ui.slider("center z", -1.0, 1.0, &mut noise.center[2]);
+                                }
+
+                                if CollapsingHeader::new("Biome").build(ui) {
+                                    let mut remove_stop = None;
+                                    for (i, stop) in planet.biome.stops.iter_mut().enumerate() {
+                                        let _id = ui.push_id_usize(i);
+                                        ui.slider("height", 0.0, 1.0, &mut stop.height);
+                                        let mut color: [f32; 3] = stop.color.into();
+                                        if ui.color_edit3("color", &mut color) {
+                                            stop.color = color.into();
+                                        }
+                                        ui.slider("roughness", 0.0, 1.0, &mut stop.roughness);
+                                        if ui.button("Remove stop") {
+                                            remove_stop = Some(i);
+                                        }
+                                    }
+                                    if let Some(i) = remove_stop {
+                                        planet.biome.stops.remove(i);
+                                    }
+                                    if planet.biome.stops.len() < biome::MAX_BIOME_STOPS
+                                        && ui.button("Add stop")
+                                    {
+                                        let height =
+                                            planet.biome.stops.last().map_or(0.0, |s| s.height);
+                                        planet.biome.stops.push(BiomeStop {
+                                            height,
+                                            color: planet.material().diffuse,
+                                            roughness: planet.material().roughness,
+                                        });
+                                    }
+                                }
+
+                                if CollapsingHeader::new("Presets").build(ui) {
+                                    let names: Vec<&str> =
+                                        presets.iter().map(|p| p.name.as_str()).collect();
+                                    ui.combo_simple_string("preset", &mut preset_index, &names);
+                                    apply_preset = ui.button("Apply");
+
+                                    ui.input_text("preset name", &mut preset_name).build();
+                                    save_preset = ui.button("Save as preset");
+                                }
+                            }
 
-                                ui.slider("Specular r", 0.0, 1.0, &mut light_source.specular.x);
-                                ui.slider("Specular g", 0.0, 1.0, &mut light_source.specular.y);
-                                ui.slider("Specular b", 0.0, 1.0, &mut light_source.specular.z);
+                            ui.separator();
+                            ui.text("Export");
+
+                            for (i, extension) in ["obj", "ply", "stl", "glb"].iter().enumerate() {
+                                if i > 0 {
+                                    ui.same_line();
+                                }
+                                if ui.button(extension) {
+                                    export_extension = Some(extension);
+                                }
                             }
 
                             ui.separator();
+                            ui.text("Models");
 
-                            ui.slider("Details", 1, 10, &mut new_detail_ui);
+                            ui.input_text("path", &mut model_path).build();
+                            load_model = ui.button("Load");
 
                             ui.separator();
-                            ui.text("Noise");
+                            ui.text("Impacts");
+
+                            ui.slider("crater radius", 0.05, 1.0, &mut crater_radius);
+                            ui.slider("crater depth", 0.0, 0.5, &mut crater_depth);
+                            ui.checkbox("persistent mapping", &mut new_persistent_mapping);
+                            impact = ui.button("Impact");
+                        });
+
+                    if save_scene {
+                        let scene_file = SceneFile::capture(
+                            &planets,
+                            &lights,
+                            &background,
+                            &camera,
+                            &camera_path,
+                        );
+                        let result = match std::path::Path::new(&scene_path).parent() {
+                            Some(dir) if !dir.as_os_str().is_empty() => {
+                                std::fs::create_dir_all(dir).map_err(|e| e.to_string())
+                            }
+                            _ => Ok(()),
+                        }
+                        .and_then(|_| scene_file.save(&scene_path));
+                        match result {
+                            Ok(()) => println!("Saved scene to {}", scene_path),
+                            Err(e) => println!("Failed to save {}: {}", scene_path, e),
+                        }
+                    }
+
+                    if load_scene {
+                        match SceneFile::load(&scene_path) {
+                            Ok(scene_file) => pending_scene = Some(scene_file),
+                            Err(e) => println!("Failed to load {}: {}", scene_path, e),
+                        }
+                    }
+
+                    if let (Some(extension), Some(i)) = (export_extension, selected) {
+                        let path = format!("./exports/planet.{}", extension);
+                        let result = std::fs::create_dir_all("./exports")
+                            .and_then(|_| planets[i].displaced_mesh().export(&path));
+                        match result {
+                            Ok(()) => println!("Exported planet to {}", path),
+                            Err(e) => println!("Failed to export {}: {}", path, e),
+                        }
+                    }
+
+                    if load_model {
+                        match mesh::Mesh::import(&model_path, material) {
+                            Ok(model) => {
+                                // Set beside the centre of the scene, and moved
+                                // into place around the camera each frame
+                                let position = glm::DVec3::new(3.0, 0.0, 0.0);
+                                let node = scene.add(
+                                    SceneNode::from_mesh(
+                                        &model,
+                                        model_shader.program_id(),
+                                        (position - camera.position).cast(),
+                                        VertexUpdates::Static,
+                                    ),
+                                    None,
+                                );
+                                model_nodes.push(ModelNode { node, position });
+                            }
+                            Err(e) => println!("Failed to load {}: {}", model_path, e),
+                        }
+                    }
 
-                            ui.slider("strength", 0.0, 10.0, &mut noise.strength);
+                    if let Some(i) = selected {
+                        if save_preset && !preset_name.is_empty() {
+                            let preset = Preset::capture(&preset_name, &planets[i]);
+                            // Saving under an existing user preset's name overwrites it
+                            match presets[builtin_preset_count..]
+                                .iter()
+                                .position(|p| p.name == preset_name)
+                            {
+                                Some(j) => presets[builtin_preset_count + j] = preset,
+                                None => presets.push(preset),
+                            }
+                            match Preset::save_user(
+                                &presets[builtin_preset_count..],
+                                USER_PRESETS_PATH,
+                            ) {
+                                Ok(()) => println!("Saved preset {}", preset_name),
+                                Err(e) => println!("Failed to save {}: {}", USER_PRESETS_PATH, e),
+                            }
+                        }
+
+                        if apply_preset {
+                            presets[preset_index].apply(&mut planets[i]);
+                        }
+
+                        if new_detail_ui != planet_detail {
+                            planets[i]
+                                .get_sphere()
+                                .generate_with_new_detail(new_detail_ui);
+                        }
+                        // Stars are drawn with a different program
+                        if new_detail_ui != planet_detail || star_toggled {
+                            scene.replace_geometry(
+                                planet_nodes[i].body,
+                                planet_scene_node(&planets[i], persistent_mapping),
+                            );
+                        }
+
+                        if impact {
+                            // Wander over the surface so consecutive impacts land apart
+                            let direction = glm::vec3(
+                                elapsed.sin(),
+                                (elapsed * 1.3).cos(),
+                                (elapsed * 0.7).sin(),
+                            );
+                            planets[i].get_sphere().add_crater(
+                                direction,
+                                crater_radius,
+                                crater_depth,
+                            );
+                        }
+                    }
+
+                    if new_persistent_mapping != persistent_mapping {
+                        persistent_mapping = new_persistent_mapping;
+                        for (planet, nodes) in planets.iter().zip(&planet_nodes) {
+                            scene.replace_geometry(
+                                nodes.body,
+                                planet_scene_node(planet, persistent_mapping),
+                            );
+                        }
+                    }
+
+                    if add_planet {
+                        let mut planet = Planet::new(
+                            1.0,
+                            glm::DVec3::new(3.0 * planets.len() as f64, 0.0, 0.0),
+                            glm::DVec3::new(0.0, 0.0, 0.0),
+                            0.5,
+                            material,
+                            5,
+                        );
+                        planet.name = format!("Planet {}", planets.len() + 1);
+                        let nodes = add_planet_nodes(
+                            &mut scene,
+                            &planet_nodes,
+                            &planet,
+                            planet_scene_node(&planet, persistent_mapping),
+                        );
+                        planets.push(planet);
+                        planet_nodes.push(nodes);
+                        selected = Some(planets.len() - 1);
+                    }
 
-                            ui.slider("base roughness", 0.0, 10.0, &mut noise.base_roughness);
-                            ui.slider("roughness", 0.0, 10.0, &mut noise.roughness);
+                    if let Some(i) = selected {
+                        if duplicate_planet {
+                            // The copy shares the parent, and sits next to the original
+                            let mut planet = planets[i].clone();
+                            planet.name = format!("{} copy", planet.name);
+                            planet.position.x += 3.0 * planet.radius;
+                            let nodes = add_planet_nodes(
+                                &mut scene,
+                                &planet_nodes,
+                                &planet,
+                                planet_scene_node(&planet, persistent_mapping),
+                            );
+                            planets.push(planet);
+                            planet_nodes.push(nodes);
+                            selected = Some(planets.len() - 1);
+                        } else if delete_planet {
+                            let new_index =
+                                remove_planet(&mut scene, &mut planets, &mut planet_nodes, i);
+                            camera.target = camera
+                                .target
+                                .and_then(|t| new_index.get(t).copied().flatten());
+                            selected = if planets.is_empty() {
+                                None
+                            } else {
+                                Some(i.min(planets.len() - 1))
+                            };
+                        }
+                    }
 
-                            ui.slider("persistence", 0.0, 1.0, &mut noise.persistence);
-                            ui.slider("layers", 1, 10, &mut noise.num_layers);
-                            ui.slider("min value", 0.0, 10.0, &mut noise.min_value);
+                    for (planet, nodes) in planets.iter_mut().zip(&planet_nodes) {
+                        if let Some(vao) = &mut scene[nodes.body].vao {
+                            planet
+                                .get_sphere()
+                                .mesh
+                                .upload_dirty(vao, &MESH_VERTEX_FORMAT);
+                        }
+                    }
 
-                            ui.slider("center x", -1.0, 1.0, &mut noise.center[0]);
-                            ui.slider("center y", -1.0, 1.0, &mut noise.center[1]);
-                            ui.slider("center z", -1.0, 1.0, &mut noise.center[2]);
+                    if let Some(target) = follow {
+                        // Stand off far enough to see the whole planet
+                        let distance = target.map_or(camera.distance, |t| {
+                            (planets[t].radius as f32 * 4.0).max(0.5)
                         });
+                        camera.follow(target, distance);
+                        camera.set_mode(CameraMode::Orbit);
+                    }
 
-                    if new_detail_ui != planet_detail {
-                        planet_detail = new_detail_ui;
-                        scene.remove(1);
+                    let world_positions = Planet::world_positions(&planets, elapsed);
+                    let target_position =
+                        camera.target.and_then(|t| world_positions.get(t)).copied();
+                    camera.update(delta_time, target_position);
+
+                    if let Some(time) = playback {
+                        let duration = camera_path.duration();
+                        let time = time + delta_time;
+                        playback = if time <= duration {
+                            Some(time)
+                        } else if loop_playback && duration > 0.0 {
+                            Some(time % duration)
+                        } else {
+                            None
+                        };
+                        if let Some(keyframe) = camera_path.sample(time.min(duration)) {
+                            keyframe.apply(&mut camera);
+                        }
+                    }
 
-                        planet_1
-                            .get_sphere()
-                            .generate_with_new_detail(planet_detail);
+                    // With the camera settled, lay the scene out around it
+                    update_planet_nodes(
+                        &mut scene,
+                        &planets,
+                        &planet_nodes,
+                        elapsed,
+                        &camera.position,
+                    );
+                    update_model_nodes(&mut scene, &model_nodes, &camera.position);
+
+                    let projection_matrix = camera.projection_matrix(window_aspect_ratio);
+                    let transformation_matrix = projection_matrix * camera.view_matrix();
+
+                    // Stars go first so they are never the ones left out
+                    let scene_lights: Vec<Light> = planets
+                        .iter()
+                        .zip(&world_positions)
+                        .filter_map(|(planet, &position)| {
+                            planet.star.map(|star| star.light(position))
+                        })
+                        .chain(lights.iter().copied())
+                        .collect();
+
+                    let shadow_casters = if shadow_settings.enabled {
+                        Light::shadow_casters(&scene_lights)
+                    } else {
+                        Vec::new()
+                    };
+                    let shadow_positions: Vec<glm::Vec3> = shadow_casters
+                        .iter()
+                        .map(|&i| (scene_lights[i].position - camera.position).cast::<f32>())
+                        .collect();
+                    draw_shadows(
+                        &mut scene,
+                        &shadow_maps,
+                        &shadow_positions,
+                        &shadow_settings,
+                        shadow_shader.program_id(),
+                        terrain_shadow_shader.program_id(),
+                    );
 
-                        scene.push(planet_1.generate_scene_node(shape_shader.program_id))
+                    // Writing depth from a fragment shader turns off early
+                    // depth testing, so only logarithmic depth compiles it in
+                    let log_depth = camera.depth_mode == DepthMode::Logarithmic;
+                    if log_depth != shaders_log_depth {
+                        let defines: &[&str] = if log_depth { &["LOG_DEPTH"] } else { &[] };
+                        for shader in [
+                            &mut shape_shader,
+                            &mut light_shader,
+                            &mut model_shader,
+                            &mut atmosphere_shader,
+                            &mut rings_shader,
+                            &mut clouds_shader,
+                        ] {
+                            shader.rebuild(defines);
+                        }
+                        shaders_log_depth = log_depth;
                     }
 
-                    winit_platform.prepare_render(&ui, &window);
-                    renderer.render(&mut imgui);
+                    // Clear the color and depth buffers
+                    scene_framebuffer.bind();
+                    apply_depth_mode(camera.depth_mode);
+                    gl::ClearColor(0.035, 0.046, 0.078, 1.0); // night sky, full opacity
+                    gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+
+                    sky.update(&background);
+                    draw_background(
+                        &sky,
+                        &background.starfield,
+                        &transformation_matrix,
+                        sky_shader.program_id(),
+                        stars_shader.program_id(),
+                    );
 
-                    scene[1].rotation.y += 0.005;
+                    // Shadow maps for the lit shaders, used by the lights given one above
+                    shadow_maps.bind_texture(1);
+                    for program in [shape_program, model_shader.program_id()] {
+                        gl::UseProgram(program);
+                        gl::Uniform1f(43, shadow_settings.range);
+                        gl::Uniform1f(44, shadow_settings.bias);
+                        gl::Uniform1f(45, shadow_settings.softness);
+                    }
 
                     draw_scene(
                         &mut scene,
                         &transformation_matrix,
-                        &light_source,
-                        &cam_pos,
-                        &noise,
+                        &scene_lights,
+                        &light_buffer,
+                        &shadow_casters,
+                        &camera.position,
+                        camera.log_depth_coefficient(),
+                        elapsed,
+                    );
+                    draw_rings(
+                        &scene,
+                        rings_shader.program_id(),
+                        &transformation_matrix,
+                        camera.log_depth_coefficient(),
+                    );
+                    draw_clouds(
+                        &scene,
+                        &mut cloud_shell,
+                        &transformation_matrix,
+                        camera.log_depth_coefficient(),
+                        elapsed,
                     );
+                    scene_framebuffer.copy_depth(&scene_depth);
+                    draw_atmospheres(
+                        &scene,
+                        &mut atmosphere_shell,
+                        &scene_depth,
+                        &transformation_matrix,
+                        camera.depth_mode,
+                        camera.log_depth_coefficient(),
+                    );
+
+                    // The UI goes on top of the finished scene, after tone
+                    // mapping so it keeps its own colours
+                    post_process.apply(
+                        &scene_framebuffer,
+                        &post_settings,
+                        bloom_downsample_shader.program_id(),
+                        bloom_upsample_shader.program_id(),
+                        tonemap_shader.program_id(),
+                    );
+                    winit_platform.prepare_render(ui, window);
+                    renderer.render(&mut imgui);
                 }
 
                 // Display the new color buffer on the display
@@ -462,6 +2175,48 @@ fn main() {
             } => *control_flow = ControlFlow::Exit,
             event => {
                 winit_platform.handle_event(imgui.io_mut(), context.window(), &event);
+
+                // Mouse drags and scrolling steer the orbit camera unless aimed at the UI
+                let ui_wants_mouse = imgui.io().want_capture_mouse;
+                match event {
+                    Event::WindowEvent {
+                        event:
+                            WindowEvent::MouseInput {
+                                state,
+                                button: MouseButton::Left,
+                                ..
+                            },
+                        ..
+                    } => dragging = state == Pressed && !ui_wants_mouse,
+                    Event::WindowEvent {
+                        event:
+                            WindowEvent::MouseInput {
+                                state: Pressed,
+                                button: MouseButton::Right,
+                                ..
+                            },
+                        ..
+                    } if camera.mode == CameraMode::Free && (mouse_captured || !ui_wants_mouse) => {
+                        mouse_captured = !mouse_captured;
+                        capture_cursor(context.window(), mouse_captured);
+                    }
+                    Event::WindowEvent {
+                        event: WindowEvent::MouseWheel { delta, .. },
+                        ..
+                    } if !ui_wants_mouse => {
+                        scroll += match delta {
+                            MouseScrollDelta::LineDelta(_, y) => y,
+                            MouseScrollDelta::PixelDelta(position) => position.y as f32 / 50.0,
+                        };
+                    }
+                    Event::DeviceEvent {
+                        event: DeviceEvent::MouseMotion { delta },
+                        ..
+                    } if dragging || mouse_captured => {
+                        mouse_delta += glm::vec2(delta.0 as f32, delta.1 as f32);
+                    }
+                    _ => {}
+                }
             }
         }
     });
@@ -1,7 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+// How shape.frag turns a material and the lights into a colour
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Shading {
+    // Classic ambient, diffuse and specular terms with a shininess exponent
+    Phong,
+    // Cook-Torrance microfacet BRDF. The diffuse colour is the albedo and only
+    // `roughness` and `metallic` shape the highlights.
+    Pbr,
+}
+
+impl Shading {
+    // Per vertex value of the `shading` attribute, see shape.frag
+    pub fn attribute(self) -> f32 {
+        match self {
+            Shading::Phong => 0.0,
+            Shading::Pbr => 1.0,
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct Material {
     pub ambient: glm::Vec3,
     pub diffuse: glm::Vec3,
     pub specular: glm::Vec3,
     pub shininess: f32,
+    pub shading: Shading,
+    // 0 for a mirror finish up to 1 for fully matte
+    pub roughness: f32,
+    // 0 for dielectrics, 1 for bare metal
+    pub metallic: f32,
+}
+
+impl Material {
+    // A warm, slightly shiny Phong surface, what a body is given until it is
+    // told otherwise
+    pub fn new() -> Material {
+        Material {
+            ambient: glm::vec3(1.0, 0.7, 0.81),
+            diffuse: glm::vec3(1.0, 0.5, 0.31),
+            specular: glm::vec3(0.5, 0.5, 0.5),
+            shininess: 32.0,
+            shading: Shading::Phong,
+            roughness: 0.5,
+            metallic: 0.0,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material::new()
+    }
 }
@@ -1,14 +1,21 @@
-use std::ptr;
+use std::ops::Range;
 
-use crate::utils;
+use crate::gpu::{Buffer, RingBuffer, VertexArray};
+use crate::material::Material;
+use crate::vertex_format::{VertexFormat, MESH_VERTEX_FORMAT};
 
+#[derive(Clone)]
 pub struct MeshMaterial {
     pub ambient: Vec<f32>,
     pub diffuse: Vec<f32>,
     pub specular: Vec<f32>,
     pub shininess: Vec<f32>,
+    pub roughness: Vec<f32>,
+    pub metallic: Vec<f32>,
+    pub shading: Vec<f32>,
 }
 
+#[derive(Clone)]
 pub struct Mesh {
     pub vertices: Vec<f32>,
     pub indices: Vec<u32>,
@@ -17,157 +24,130 @@ pub struct Mesh {
     pub material: MeshMaterial,
 
     pub index_count: i32,
+
+    // Vertices changed on the CPU that still have to be uploaded
+    pub dirty: Option<Range<usize>>,
+}
+
+#[derive(Clone, Copy)]
+pub enum VertexUpdates {
+    // Vertex data is uploaded once and never changes
+    Static,
+    // Dirty ranges are written in place with glBufferSubData
+    SubData,
+    // The whole vertex buffer is rewritten into the next segment of a
+    // persistently mapped ring buffer, so uploads never stall on the GPU
+    PersistentRing,
 }
 
+// Number of frames the ring buffer can have in flight
+const RING_SEGMENTS: usize = 3;
+
 impl Mesh {
-    pub unsafe fn create_vao(&self) -> u32 {
-        let mut vao_ids: u32 = 0;
-        gl::GenVertexArrays(1, &mut vao_ids as *mut u32);
-        gl::BindVertexArray(vao_ids);
-
-        let mut vbo_ids: u32 = 0;
-        gl::GenBuffers(1, &mut vbo_ids as *mut u32);
-        gl::BindBuffer(gl::ARRAY_BUFFER, vbo_ids);
-
-        gl::BufferData(
-            gl::ARRAY_BUFFER,
-            utils::byte_size_of_array(&self.vertices),
-            utils::pointer_to_array(&self.vertices),
-            gl::STATIC_DRAW,
-        );
-
-        gl::VertexAttribPointer(
-            0,
-            3,
-            gl::FLOAT,
-            gl::FALSE,
-            utils::size_of::<f32>() * 3,
-            ptr::null(),
-        );
-        gl::EnableVertexAttribArray(0);
-
-        //Ambient buffer
-        let mut ambient_vbo_ids: u32 = 1;
-        gl::GenBuffers(1, &mut ambient_vbo_ids as *mut u32);
-        gl::BindBuffer(gl::ARRAY_BUFFER, ambient_vbo_ids);
-
-        gl::BufferData(
-            gl::ARRAY_BUFFER,
-            utils::byte_size_of_array(&self.material.ambient),
-            utils::pointer_to_array(&self.material.ambient),
-            gl::STATIC_DRAW,
-        );
-
-        gl::VertexAttribPointer(
-            1,
-            3,
-            gl::FLOAT,
-            gl::FALSE,
-            utils::size_of::<f32>() * 3,
-            ptr::null(),
-        );
-        gl::EnableVertexAttribArray(1);
-
-        //Diffuse buffer
-        let mut diffuse_vbo_ids: u32 = 2;
-        gl::GenBuffers(1, &mut diffuse_vbo_ids as *mut u32);
-        gl::BindBuffer(gl::ARRAY_BUFFER, diffuse_vbo_ids);
-
-        gl::BufferData(
-            gl::ARRAY_BUFFER,
-            utils::byte_size_of_array(&self.material.diffuse),
-            utils::pointer_to_array(&self.material.diffuse),
-            gl::STATIC_DRAW,
-        );
-
-        gl::VertexAttribPointer(
-            2,
-            3,
-            gl::FLOAT,
-            gl::FALSE,
-            utils::size_of::<f32>() * 3,
-            ptr::null(),
-        );
-        gl::EnableVertexAttribArray(2);
-
-        //Specular buffer
-        let mut specular_vbo_ids: u32 = 3;
-        gl::GenBuffers(1, &mut specular_vbo_ids as *mut u32);
-        gl::BindBuffer(gl::ARRAY_BUFFER, specular_vbo_ids);
-
-        gl::BufferData(
-            gl::ARRAY_BUFFER,
-            utils::byte_size_of_array(&self.material.specular),
-            utils::pointer_to_array(&self.material.specular),
-            gl::STATIC_DRAW,
-        );
-
-        gl::VertexAttribPointer(
-            3,
-            3,
-            gl::FLOAT,
-            gl::FALSE,
-            utils::size_of::<f32>() * 3,
-            ptr::null(),
-        );
-        gl::EnableVertexAttribArray(3);
-
-        //Shininess buffer
-        let mut shininess_vbo_ids: u32 = 4;
-        gl::GenBuffers(1, &mut shininess_vbo_ids as *mut u32);
-        gl::BindBuffer(gl::ARRAY_BUFFER, shininess_vbo_ids);
-
-        gl::BufferData(
-            gl::ARRAY_BUFFER,
-            utils::byte_size_of_array(&self.material.shininess),
-            utils::pointer_to_array(&self.material.shininess),
-            gl::STATIC_DRAW,
-        );
-
-        gl::VertexAttribPointer(
-            4,
-            1,
-            gl::FLOAT,
-            gl::FALSE,
-            utils::size_of::<f32>() * 1,
-            ptr::null(),
-        );
-        gl::EnableVertexAttribArray(4);
-
-        //Normal buffer
-        let mut normvec_vbo_ids: u32 = 5;
-        gl::GenBuffers(1, &mut normvec_vbo_ids as *mut u32);
-        gl::BindBuffer(gl::ARRAY_BUFFER, normvec_vbo_ids);
-
-        gl::BufferData(
-            gl::ARRAY_BUFFER,
-            utils::byte_size_of_array(&self.normals),
-            utils::pointer_to_array(&self.normals),
-            gl::STATIC_DRAW,
-        );
-
-        gl::VertexAttribPointer(
-            5,
-            3,
-            gl::FLOAT,
-            gl::FALSE,
-            utils::size_of::<f32>() * 3,
-            ptr::null(),
-        );
-        gl::EnableVertexAttribArray(5);
-
-        let mut ibo_ids: u32 = 0;
-        gl::GenBuffers(1, &mut ibo_ids as *mut u32);
-        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ibo_ids);
-
-        // * Fill it with data
-        gl::BufferData(
-            gl::ELEMENT_ARRAY_BUFFER,
-            utils::byte_size_of_array(&self.indices),
-            utils::pointer_to_array(&self.indices),
-            gl::STATIC_DRAW,
-        );
-
-        vao_ids
+    // A mesh without vertices, to be filled with `push_vertex`
+    pub fn empty() -> Mesh {
+        Mesh {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            normals: Vec::new(),
+            material: MeshMaterial {
+                ambient: Vec::new(),
+                diffuse: Vec::new(),
+                specular: Vec::new(),
+                shininess: Vec::new(),
+                roughness: Vec::new(),
+                metallic: Vec::new(),
+                shading: Vec::new(),
+            },
+
+            index_count: 0,
+            dirty: None,
+        }
+    }
+
+    // Adds a vertex with `material`. Normals are left out when the source has
+    // none, to be computed once the mesh is complete.
+    pub fn push_vertex(
+        &mut self,
+        position: glm::Vec3,
+        normal: Option<glm::Vec3>,
+        material: &Material,
+    ) {
+        self.vertices.extend(&position);
+        if let Some(normal) = normal {
+            self.normals.extend(&normal);
+        }
+        self.material.ambient.extend(&material.ambient);
+        self.material.diffuse.extend(&material.diffuse);
+        self.material.specular.extend(&material.specular);
+        self.material.shininess.push(material.shininess);
+        self.material.roughness.push(material.roughness);
+        self.material.metallic.push(material.metallic);
+        self.material.shading.push(material.shading.attribute());
+    }
+
+    pub unsafe fn create_vao(&self) -> VertexArray {
+        self.create_vao_with_format(&MESH_VERTEX_FORMAT, VertexUpdates::Static)
+    }
+
+    pub unsafe fn create_vao_with_format(
+        &self,
+        format: &VertexFormat,
+        updates: VertexUpdates,
+    ) -> VertexArray {
+        let mut vao = VertexArray::new();
+
+        // All attributes share one interleaved buffer
+        let vertex_data = format.interleave(self);
+        match updates {
+            VertexUpdates::Static | VertexUpdates::SubData => {
+                let vbo = Buffer::new(gl::ARRAY_BUFFER);
+                let usage = match updates {
+                    VertexUpdates::Static => gl::STATIC_DRAW,
+                    _ => gl::DYNAMIC_DRAW,
+                };
+                vbo.upload(&vertex_data, usage);
+                format.enable_attributes(&vbo);
+                vao.attach(vbo);
+            }
+            VertexUpdates::PersistentRing => {
+                let mut ring = RingBuffer::new(gl::ARRAY_BUFFER, vertex_data.len(), RING_SEGMENTS);
+                ring.write(&vertex_data);
+                format.enable_attributes(ring.buffer());
+                format.bind_vertex_buffer(ring.buffer(), ring.offset());
+                vao.attach_ring(ring);
+            }
+        }
+
+        let ibo = Buffer::new(gl::ELEMENT_ARRAY_BUFFER);
+        ibo.upload(&self.indices, gl::STATIC_DRAW);
+        vao.attach(ibo);
+
+        vao
+    }
+
+    // Flags vertices whose data changed since the last upload
+    pub fn mark_dirty(&mut self, vertices: Range<usize>) {
+        self.dirty = match self.dirty.take() {
+            Some(dirty) => Some(dirty.start.min(vertices.start)..dirty.end.max(vertices.end)),
+            None => Some(vertices),
+        };
+    }
+
+    // Sends the dirty vertices to a vertex array made by `create_vao_with_format`
+    pub unsafe fn upload_dirty(&mut self, vao: &mut VertexArray, format: &VertexFormat) {
+        let dirty = match self.dirty.take() {
+            Some(dirty) if !dirty.is_empty() => dirty,
+            _ => return,
+        };
+
+        vao.bind();
+        if let Some(ring) = vao.ring_mut() {
+            let offset = ring.write(&format.interleave(self));
+            format.bind_vertex_buffer(ring.buffer(), offset);
+        } else {
+            let offset = (dirty.start * format.stride()) as isize;
+            vao.buffers()[0].upload_range(offset, &format.interleave_range(self, dirty));
+        }
     }
 }
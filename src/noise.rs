@@ -1,3 +1,4 @@
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Noise {
     pub strength: f32,
     pub base_roughness: f32,
@@ -21,3 +22,157 @@ impl Noise {
         }
     }
 }
+
+impl Noise {
+    // CPU version of the displacement applied in shape.vert, so exported
+    // meshes match what is rendered
+    pub fn evaluate(&self, position: &glm::Vec3) -> f32 {
+        let mut noise_value = 0.0;
+        let mut frequency = self.base_roughness;
+        let mut amplitude = 1.0;
+
+        for _ in 0..self.num_layers {
+            let v = cnoise(&(position * frequency + self.center));
+            noise_value += (v + 1.0) * 0.5 * amplitude;
+            frequency *= self.roughness;
+            amplitude *= self.persistence;
+        }
+
+        noise_value *= self.strength;
+        (noise_value - self.min_value).max(0.0)
+    }
+
+    // Roughly the highest value `evaluate` can return, as every layer of Perlin
+    // noise stays close to [-1, 1]
+    pub fn max_value(&self) -> f32 {
+        let amplitude: f32 = (0..self.num_layers)
+            .map(|layer| self.persistence.powi(layer as i32))
+            .sum();
+        self.strength * amplitude - self.min_value
+    }
+
+    // Displacement at `position` scaled to [0, 1], as used for biome colours
+    pub fn elevation(&self, position: &glm::Vec3) -> f32 {
+        let max_value = self.max_value();
+        if max_value > 0.0 {
+            (self.evaluate(position) / max_value).min(1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Port of the classic Perlin 3D noise by Stefan Gustavson used in shape.vert.
+// Kept line for line with the GLSL version so both produce the same terrain.
+fn mod289(x: f32) -> f32 {
+    x - 289.0 * (x / 289.0).floor()
+}
+
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+fn step(edge: f32, x: f32) -> f32 {
+    if x < edge {
+        0.0
+    } else {
+        1.0
+    }
+}
+
+fn permute(x: glm::Vec4) -> glm::Vec4 {
+    x.map(|v| mod289((v * 34.0 + 1.0) * v))
+}
+
+fn taylor_inv_sqrt(r: glm::Vec4) -> glm::Vec4 {
+    r.map(|v| 1.792_842_9 - 0.853_734_7 * v)
+}
+
+fn fade(t: glm::Vec3) -> glm::Vec3 {
+    t.map(|v| v * v * v * (v * (v * 6.0 - 15.0) + 10.0))
+}
+
+fn gradients(ixy: glm::Vec4) -> (glm::Vec4, glm::Vec4, glm::Vec4) {
+    let gx = ixy / 7.0;
+    let mut gy = gx.map(|v| fract(v.floor() / 7.0) - 0.5);
+    let mut gx = gx.map(fract);
+    let gz = glm::vec4(0.5, 0.5, 0.5, 0.5) - gx.abs() - gy.abs();
+    let sz = gz.map(|v| step(v, 0.0));
+    gx -= sz.component_mul(&gx.map(|v| step(0.0, v) - 0.5));
+    gy -= sz.component_mul(&gy.map(|v| step(0.0, v) - 0.5));
+    (gx, gy, gz)
+}
+
+fn cnoise(p: &glm::Vec3) -> f32 {
+    let pi0 = p.map(f32::floor);
+    let pi1 = (pi0 + glm::vec3(1.0, 1.0, 1.0)).map(mod289);
+    let pi0 = pi0.map(mod289);
+    let pf0 = p.map(fract);
+    let pf1 = pf0 - glm::vec3(1.0, 1.0, 1.0);
+    let ix = glm::vec4(pi0.x, pi1.x, pi0.x, pi1.x);
+    let iy = glm::vec4(pi0.y, pi0.y, pi1.y, pi1.y);
+    let iz0 = glm::vec4(pi0.z, pi0.z, pi0.z, pi0.z);
+    let iz1 = glm::vec4(pi1.z, pi1.z, pi1.z, pi1.z);
+
+    let ixy = permute(permute(ix) + iy);
+    let ixy0 = permute(ixy + iz0);
+    let ixy1 = permute(ixy + iz1);
+
+    let (gx0, gy0, gz0) = gradients(ixy0);
+    let (gx1, gy1, gz1) = gradients(ixy1);
+
+    let mut g000 = glm::vec3(gx0.x, gy0.x, gz0.x);
+    let mut g100 = glm::vec3(gx0.y, gy0.y, gz0.y);
+    let mut g010 = glm::vec3(gx0.z, gy0.z, gz0.z);
+    let mut g110 = glm::vec3(gx0.w, gy0.w, gz0.w);
+    let mut g001 = glm::vec3(gx1.x, gy1.x, gz1.x);
+    let mut g101 = glm::vec3(gx1.y, gy1.y, gz1.y);
+    let mut g011 = glm::vec3(gx1.z, gy1.z, gz1.z);
+    let mut g111 = glm::vec3(gx1.w, gy1.w, gz1.w);
+
+    let norm0 = taylor_inv_sqrt(glm::vec4(
+        g000.dot(&g000),
+        g010.dot(&g010),
+        g100.dot(&g100),
+        g110.dot(&g110),
+    ));
+    g000 *= norm0.x;
+    g010 *= norm0.y;
+    g100 *= norm0.z;
+    g110 *= norm0.w;
+    let norm1 = taylor_inv_sqrt(glm::vec4(
+        g001.dot(&g001),
+        g011.dot(&g011),
+        g101.dot(&g101),
+        g111.dot(&g111),
+    ));
+    g001 *= norm1.x;
+    g011 *= norm1.y;
+    g101 *= norm1.z;
+    g111 *= norm1.w;
+
+    let n000 = g000.dot(&pf0);
+    let n100 = g100.dot(&glm::vec3(pf1.x, pf0.y, pf0.z));
+    let n010 = g010.dot(&glm::vec3(pf0.x, pf1.y, pf0.z));
+    let n110 = g110.dot(&glm::vec3(pf1.x, pf1.y, pf0.z));
+    let n001 = g001.dot(&glm::vec3(pf0.x, pf0.y, pf1.z));
+    let n101 = g101.dot(&glm::vec3(pf1.x, pf0.y, pf1.z));
+    let n011 = g011.dot(&glm::vec3(pf0.x, pf1.y, pf1.z));
+    let n111 = g111.dot(&pf1);
+
+    let fade_xyz = fade(pf0);
+    let n_z = glm::mix(
+        &glm::vec4(n000, n100, n010, n110),
+        &glm::vec4(n001, n101, n011, n111),
+        fade_xyz.z,
+    );
+    let n_yz = glm::mix(&n_z.xy(), &glm::vec2(n_z.z, n_z.w), fade_xyz.y);
+    let n_xyz = glm::mix_scalar(n_yz.x, n_yz.y, fade_xyz.x);
+    2.2 * n_xyz
+}
@@ -1,29 +1,77 @@
-use crate::scenenode::SceneNode;
+use crate::atmosphere::Atmosphere;
+use crate::biome::Biome;
+use crate::clouds::Clouds;
+use crate::mesh::{Mesh, VertexUpdates};
+use crate::noise::Noise;
+use crate::rings::{RingLayer, Rings};
+use crate::scenenode::{SceneNode, Terrain};
 use crate::sphere::Sphere;
+use crate::star::Star;
 use crate::Material;
 
+#[derive(Clone)]
 pub struct Planet {
+    pub name: String,
     pub mass: f64,
-    pub position: glm::Vec3,
-    pub velocity: glm::Vec3,
+    // Double precision so bodies far from the origin still sit still
+    pub position: glm::DVec3,
+    pub velocity: glm::DVec3,
     pub radius: f64,
+
+    // Angle between the spin axis and the orbital plane normal, in radians
+    pub axial_tilt: f32,
+    // Seconds per full turn relative to the stars, 0 for no spin
+    pub rotation_period: f32,
+    // Seconds per full turn of the spin axis around the orbital plane normal,
+    // 0 for no precession
+    pub precession_period: f32,
+
+    // Index of the planet this one orbits, if any. `position` is then relative
+    // to that planet and turns around it once every `orbital_period` seconds.
+    pub parent: Option<usize>,
+    pub orbital_period: f32,
+
+    // Stars are drawn unlit with the light shader and light up the others
+    pub star: Option<Star>,
+
+    // Terrain displacement and the colours it is painted with
+    pub noise: Noise,
+    pub biome: Biome,
+
+    // Air drawn as a glowing shell around the planet
+    pub atmosphere: Option<Atmosphere>,
+    pub clouds: Option<Clouds>,
+    pub rings: Option<Rings>,
+
     sphere_object: Sphere,
 }
 
 impl Planet {
     pub fn new(
         mass: f64,
-        position: glm::Vec3,
-        velocity: glm::Vec3,
+        position: glm::DVec3,
+        velocity: glm::DVec3,
         radius: f64,
         material: Material,
         detail: u32,
     ) -> Planet {
         Planet {
+            name: String::from("Planet"),
             mass,
             position,
             velocity,
             radius,
+            axial_tilt: 0.0,
+            rotation_period: 0.0,
+            precession_period: 0.0,
+            parent: None,
+            orbital_period: 0.0,
+            star: None,
+            noise: Noise::new(),
+            biome: Biome::new(),
+            atmosphere: None,
+            clouds: None,
+            rings: None,
             sphere_object: Sphere::new(detail, material),
         }
     }
@@ -31,15 +79,177 @@ impl Planet {
         &mut self.sphere_object
     }
 
-    pub fn generate_scene_node(&self, shader_id: u32) -> SceneNode {
-        SceneNode {
-            vao_id: unsafe { self.sphere_object.mesh.create_vao() },
-            index_count: self.sphere_object.mesh.index_count,
-            position: self.position,
-            reference_point: glm::vec3(0.0, 0.0, 0.0),
-            rotation: glm::vec3(0.0, 0.0, 0.0),
-            scale: glm::vec3(1.0, 1.0, 1.0),
-            shader_program: shader_id,
+    pub fn detail(&self) -> u32 {
+        self.sphere_object.detail()
+    }
+
+    pub fn material(&self) -> Material {
+        self.sphere_object.mesh_material
+    }
+
+    pub fn set_material(&mut self, material: Material) {
+        self.sphere_object.set_material(material);
+    }
+
+    // The surface as drawn, with the planet's own noise and biome baked in
+    pub fn displaced_mesh(&self) -> Mesh {
+        self.sphere_object.displaced_mesh(&self.noise, &self.biome)
+    }
+
+    // Direction of the spin axis at `time`, with the orbital plane normal along +y
+    pub fn spin_axis(&self, time: f32) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.tilt_at(time), &glm::vec3(0.0, 1.0, 0.0))
+    }
+
+    // Orientation of the planet `time` seconds in: tilted away from the orbital
+    // plane normal, spinning around its own axis, with the axis precessing
+    pub fn orientation_at(&self, time: f32) -> glm::Quat {
+        let spin = glm::quat_angle_axis(
+            Planet::turns(time, self.rotation_period) * glm::two_pi::<f32>(),
+            &glm::vec3(0.0, 1.0, 0.0),
+        );
+        self.tilt_at(time) * spin
+    }
+
+    // Rotation of the orbit around the parent planet `time` seconds in
+    pub fn orbit_orientation_at(&self, time: f32) -> glm::Quat {
+        glm::quat_angle_axis(
+            Planet::turns(time, self.orbital_period) * glm::two_pi::<f32>(),
+            &glm::vec3(0.0, 1.0, 0.0),
+        )
+    }
+
+    // Where each planet is `time` seconds in, following moons around their
+    // parents. Parents have to come before their moons.
+    pub fn world_positions(planets: &[Planet], time: f32) -> Vec<glm::DVec3> {
+        let mut positions: Vec<glm::DVec3> = Vec::with_capacity(planets.len());
+        for planet in planets {
+            let position = match planet.parent.and_then(|parent| positions.get(parent)) {
+                Some(parent_position) => {
+                    let orbit: glm::DQuat = glm::convert(planet.orbit_orientation_at(time));
+                    parent_position + glm::quat_rotate_vec3(&orbit, &planet.position)
+                }
+                None => planet.position,
+            };
+            positions.push(position);
         }
+        positions
+    }
+
+    fn tilt_at(&self, time: f32) -> glm::Quat {
+        // Precession runs opposite to the spin, like the Earth's
+        let precession = glm::quat_angle_axis(
+            -Planet::turns(time, self.precession_period) * glm::two_pi::<f32>(),
+            &glm::vec3(0.0, 1.0, 0.0),
+        );
+        let tilt = glm::quat_angle_axis(self.axial_tilt, &glm::vec3(0.0, 0.0, 1.0));
+        precession * tilt
+    }
+
+    fn turns(time: f32, period: f32) -> f32 {
+        if period == 0.0 {
+            0.0
+        } else {
+            (time / period).fract()
+        }
+    }
+
+    // The node is scaled to the planet's radius and sits at the origin, meant to
+    // be placed under a node carrying the planet's position
+    pub fn generate_scene_node(&self, shader_id: u32, updates: VertexUpdates) -> SceneNode {
+        let mut node = SceneNode::from_mesh(
+            &self.sphere_object.mesh,
+            shader_id,
+            glm::vec3(0.0, 0.0, 0.0),
+            updates,
+        );
+        let radius = self.radius as f32;
+        node.set_scale(glm::vec3(radius, radius, radius));
+        node.atmosphere = self.atmosphere;
+        node.clouds = self.clouds;
+        node.rings = self
+            .rings
+            .as_ref()
+            .map(|rings| unsafe { RingLayer::new(rings) });
+        match self.star {
+            Some(star) => node.emission = Some(star.emission()),
+            None => {
+                node.terrain = Some(Terrain {
+                    noise: self.noise,
+                    biome: self.biome.clone(),
+                })
+            }
+        }
+        node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn planet() -> Planet {
+        let material = Material::new();
+        Planet::new(
+            1.0,
+            glm::DVec3::new(0.0, 0.0, 0.0),
+            glm::DVec3::new(0.0, 0.0, 0.0),
+            1.0,
+            material,
+            1,
+        )
+    }
+
+    fn assert_close(a: glm::Vec3, b: glm::Vec3) {
+        assert!(glm::distance(&a, &b) < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn spin_keeps_the_tilted_axis_fixed() {
+        let mut planet = planet();
+        planet.axial_tilt = 23.44_f32.to_radians();
+        planet.rotation_period = 10.0;
+
+        let axis = planet.spin_axis(0.0);
+        assert!((glm::angle(&axis, &glm::vec3(0.0, 1.0, 0.0)) - planet.axial_tilt).abs() < 1e-5);
+
+        // The pole stays put through a day while the rest of the surface turns
+        for time in [0.0, 2.5, 5.0, 7.5] {
+            let orientation = planet.orientation_at(time);
+            assert_close(
+                glm::quat_rotate_vec3(&orientation, &glm::vec3(0.0, 1.0, 0.0)),
+                axis,
+            );
+        }
+        let x = glm::vec3(1.0, 0.0, 0.0);
+        let sunrise = glm::quat_rotate_vec3(&planet.orientation_at(0.0), &x);
+        let noon = glm::quat_rotate_vec3(&planet.orientation_at(2.5), &x);
+        assert!((glm::angle(&sunrise, &noon) - glm::half_pi::<f32>()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn precession_sweeps_the_axis_around() {
+        let mut planet = planet();
+        planet.axial_tilt = 0.4;
+        planet.precession_period = 100.0;
+
+        let start = planet.spin_axis(0.0);
+        let half = planet.spin_axis(50.0);
+        assert_close(half, glm::vec3(-start.x, start.y, -start.z));
+        assert_close(planet.spin_axis(100.0), start);
+    }
+
+    #[test]
+    fn moons_circle_far_away_parents_precisely() {
+        let mut sun = planet();
+        sun.position = glm::DVec3::new(1.5e11, 0.0, 0.0);
+        let mut moon = planet();
+        moon.parent = Some(0);
+        moon.position = glm::DVec3::new(0.0, 0.0, 2.0);
+        moon.orbital_period = 40.0;
+
+        let positions = Planet::world_positions(&[sun, moon], 10.0);
+        let offset = positions[1] - positions[0];
+        assert!(glm::distance(&offset, &glm::DVec3::new(2.0, 0.0, 0.0)) < 1e-6);
     }
 }
@@ -0,0 +1,230 @@
+use crate::gpu::{Framebuffer, VertexArray};
+
+// Halvings of the scene the bloom is blurred over, each one spreading it twice
+// as far
+pub const BLOOM_LEVELS: usize = 6;
+
+// Texture units the passes read from, clear of those the scene uses
+const SOURCE_UNIT: u32 = 4;
+const BLOOM_UNIT: u32 = 5;
+
+// Maps the unbounded light of the scene into what the screen can show. Must
+// match the numbering in tonemap.frag.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapper {
+    // c / (1 + c), gentle but greys out the brightest colours
+    Reinhard,
+    // Narkowicz's fit of the ACES filmic curve, with more contrast
+    Aces,
+    // 1 - e^-c, like film exposed for longer
+    Exposure,
+}
+
+impl ToneMapper {
+    pub const ALL: [ToneMapper; 3] = [ToneMapper::Reinhard, ToneMapper::Aces, ToneMapper::Exposure];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneMapper::Reinhard => "Reinhard",
+            ToneMapper::Aces => "ACES",
+            ToneMapper::Exposure => "Exposure",
+        }
+    }
+}
+
+// Tunables for the HDR pipeline, edited from the UI
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostSettings {
+    pub tone_mapper: ToneMapper,
+    // Scales the scene's light before tone mapping
+    pub exposure: f32,
+    pub bloom: bool,
+    // Brightness above which light spills into the bloom, eased in over a
+    // knee of half that
+    pub bloom_threshold: f32,
+    // Share of the blurred light added back onto the scene
+    pub bloom_intensity: f32,
+}
+
+impl PostSettings {
+    pub fn new() -> PostSettings {
+        PostSettings {
+            tone_mapper: ToneMapper::Aces,
+            exposure: 1.0,
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.15,
+        }
+    }
+}
+
+impl Default for PostSettings {
+    fn default() -> PostSettings {
+        PostSettings::new()
+    }
+}
+
+// Sizes of the bloom levels under a `width` by `height` scene, halving each
+// time and stopping before they vanish
+pub fn bloom_sizes(width: u32, height: u32) -> Vec<(u32, u32)> {
+    let mut sizes = Vec::with_capacity(BLOOM_LEVELS);
+    let (mut width, mut height) = (width, height);
+    while sizes.len() < BLOOM_LEVELS && width > 1 && height > 1 {
+        width /= 2;
+        height /= 2;
+        sizes.push((width, height));
+    }
+    sizes
+}
+
+// Takes the HDR scene to the screen: bloom is blurred out of its bright parts
+// by downsampling into a chain of ever smaller framebuffers and adding them
+// back up, then the sum is tone mapped into the window's framebuffer
+pub struct PostProcess {
+    bloom: Vec<Framebuffer>,
+    // Nothing to read, but drawing needs a vertex array bound
+    empty: VertexArray,
+}
+
+impl PostProcess {
+    pub unsafe fn new(width: u32, height: u32) -> PostProcess {
+        PostProcess {
+            bloom: bloom_sizes(width, height)
+                .into_iter()
+                .map(|(width, height)| Framebuffer::color_only(width, height, gl::RGBA16F))
+                .collect(),
+            empty: VertexArray::new(),
+        }
+    }
+
+    // Draws `scene` to the window, leaving the window bound for the UI to go
+    // on top. Depth testing and blending are turned off and back on.
+    pub unsafe fn apply(
+        &self,
+        scene: &Framebuffer,
+        settings: &PostSettings,
+        downsample_program: u32,
+        upsample_program: u32,
+        tonemap_program: u32,
+    ) {
+        gl::Disable(gl::DEPTH_TEST);
+        gl::Disable(gl::BLEND);
+        self.empty.bind();
+
+        let bloom = settings.bloom && !self.bloom.is_empty();
+        if bloom {
+            // Each level is a filtered half of the one above, starting from
+            // only the bright parts of the scene
+            gl::UseProgram(downsample_program);
+            gl::Uniform1f(111, settings.bloom_threshold);
+            let mut source = scene;
+            for (level, target) in self.bloom.iter().enumerate() {
+                target.bind();
+                bind_color(source, SOURCE_UNIT);
+                gl::Uniform2f(
+                    110,
+                    1.0 / source.width() as f32,
+                    1.0 / source.height() as f32,
+                );
+                gl::Uniform1i(112, (level == 0) as i32);
+                gl::DrawArrays(gl::TRIANGLES, 0, 3);
+                source = target;
+            }
+
+            // Back up the chain, each level blurred onto the larger one so the
+            // first ends up holding the glow at every scale
+            gl::UseProgram(upsample_program);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::ONE, gl::ONE);
+            for pair in self.bloom.windows(2).rev() {
+                let (target, source) = (&pair[0], &pair[1]);
+                target.bind();
+                bind_color(source, SOURCE_UNIT);
+                gl::Uniform2f(
+                    110,
+                    1.0 / source.width() as f32,
+                    1.0 / source.height() as f32,
+                );
+                gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+            gl::Disable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(0, 0, scene.width() as i32, scene.height() as i32);
+        gl::UseProgram(tonemap_program);
+        bind_color(scene, SOURCE_UNIT);
+        if let Some(first) = self.bloom.first() {
+            bind_color(first, BLOOM_UNIT);
+        }
+        gl::Uniform1i(113, bloom as i32);
+        gl::Uniform1f(114, settings.bloom_intensity);
+        gl::Uniform1f(115, settings.exposure);
+        gl::Uniform1i(116, settings.tone_mapper as i32);
+        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+        gl::Enable(gl::BLEND);
+        gl::Enable(gl::DEPTH_TEST);
+    }
+}
+
+unsafe fn bind_color(framebuffer: &Framebuffer, unit: u32) {
+    if let Some(color) = framebuffer.color() {
+        color.bind(unit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One channel through the curve, as tonemap.frag does it after scaling by
+    // the exposure. The shader can not run here, so its curves are copied.
+    fn map(tone_mapper: ToneMapper, value: f32) -> f32 {
+        match tone_mapper {
+            ToneMapper::Reinhard => value / (1.0 + value),
+            ToneMapper::Aces => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                ((value * (a * value + b)) / (value * (c * value + d) + e)).clamp(0.0, 1.0)
+            }
+            ToneMapper::Exposure => 1.0 - (-value).exp(),
+        }
+    }
+
+    #[test]
+    fn tone_mappers_fit_everything_on_screen() {
+        for tone_mapper in ToneMapper::ALL {
+            assert_eq!(map(tone_mapper, 0.0), 0.0, "{}", tone_mapper.name());
+            let mut previous = 0.0;
+            for i in 1..200 {
+                let mapped = map(tone_mapper, i as f32 * 0.25);
+                assert!(
+                    mapped >= previous && mapped <= 1.0,
+                    "{}",
+                    tone_mapper.name()
+                );
+                previous = mapped;
+            }
+            // Bright light is kept apart from merely lit surfaces
+            assert!(map(tone_mapper, 4.0) > map(tone_mapper, 1.0) + 0.1);
+        }
+    }
+
+    #[test]
+    fn bloom_levels_halve_until_they_run_out() {
+        assert_eq!(
+            bloom_sizes(1920, 1080),
+            vec![
+                (960, 540),
+                (480, 270),
+                (240, 135),
+                (120, 67),
+                (60, 33),
+                (30, 16)
+            ]
+        );
+        assert_eq!(bloom_sizes(8, 4), vec![(4, 2), (2, 1)]);
+        assert_eq!(bloom_sizes(1, 1), Vec::new());
+    }
+}
@@ -0,0 +1,293 @@
+use serde::{Deserialize, Serialize};
+
+use crate::atmosphere::Atmosphere;
+use crate::clouds::Clouds;
+use crate::material::Shading;
+use crate::planet::Planet;
+use crate::rings::Rings;
+use crate::scene_file::{
+    AtmosphereDesc, BiomeStopDesc, CloudsDesc, MaterialDesc, NoiseDesc, RingsDesc,
+};
+
+// A named look for a planet: its terrain, how the terrain is coloured and the
+// physical parameters that go with it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    pub mass: f64,
+    pub radius: f64,
+    pub axial_tilt: f32,
+    pub rotation_period: f32,
+    pub noise: NoiseDesc,
+    pub biome: Vec<BiomeStopDesc>,
+    pub material: MaterialDesc,
+    // Presets saved before atmospheres existed have none
+    #[serde(default)]
+    pub atmosphere: Option<AtmosphereDesc>,
+    #[serde(default)]
+    pub clouds: Option<CloudsDesc>,
+    #[serde(default)]
+    pub rings: Option<RingsDesc>,
+}
+
+impl Preset {
+    pub fn capture(name: &str, planet: &Planet) -> Preset {
+        Preset {
+            name: String::from(name),
+            mass: planet.mass,
+            radius: planet.radius,
+            axial_tilt: planet.axial_tilt,
+            rotation_period: planet.rotation_period,
+            noise: NoiseDesc::from(&planet.noise),
+            biome: BiomeStopDesc::from_biome(&planet.biome),
+            material: MaterialDesc::from(&planet.material()),
+            atmosphere: planet.atmosphere.as_ref().map(AtmosphereDesc::from),
+            clouds: planet.clouds.as_ref().map(CloudsDesc::from),
+            rings: planet.rings.as_ref().map(RingsDesc::from),
+        }
+    }
+
+    pub fn apply(&self, planet: &mut Planet) {
+        planet.mass = self.mass;
+        planet.radius = self.radius;
+        planet.axial_tilt = self.axial_tilt;
+        planet.rotation_period = self.rotation_period;
+        planet.set_material(self.material.to_material());
+        planet.noise = self.noise.to_noise();
+        planet.biome = BiomeStopDesc::to_biome(&self.biome);
+        planet.atmosphere = self.atmosphere.as_ref().map(AtmosphereDesc::to_atmosphere);
+        planet.clouds = self.clouds.as_ref().map(CloudsDesc::to_clouds);
+        planet.rings = self.rings.as_ref().map(RingsDesc::to_rings);
+    }
+
+    // User presets live in a single RON file, a missing file meaning none yet
+    pub fn load_user(path: &str) -> Result<Vec<Preset>, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => ron::from_str(&text).map_err(|e| e.to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub fn save_user(presets: &[Preset], path: &str) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(presets, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| e.to_string())
+    }
+
+    pub fn builtin() -> Vec<Preset> {
+        vec![
+            Preset {
+                name: String::from("Earth-like"),
+                mass: 100.0,
+                radius: 1.0,
+                axial_tilt: 23.44_f32.to_radians(),
+                rotation_period: 20.0,
+                noise: noise(0.15, 1.5, 2.2, 0.5, 5, 0.1),
+                biome: vec![
+                    stop(0.0, [0.05, 0.15, 0.45], 0.1),
+                    stop(0.03, [0.76, 0.7, 0.5], 0.8),
+                    stop(0.1, [0.2, 0.5, 0.15], 0.9),
+                    stop(0.4, [0.1, 0.3, 0.1], 0.9),
+                    stop(0.6, [0.4, 0.35, 0.3], 0.7),
+                    stop(0.8, [0.95, 0.95, 0.95], 0.4),
+                ],
+                material: pbr(material([0.2, 0.5, 0.15], [0.3, 0.3, 0.3], 16.0)),
+                atmosphere: Some(AtmosphereDesc::from(&Atmosphere::new())),
+                clouds: Some(CloudsDesc::from(&Clouds::new())),
+                rings: None,
+            },
+            Preset {
+                name: String::from("Desert"),
+                mass: 60.0,
+                radius: 0.8,
+                axial_tilt: 25.0_f32.to_radians(),
+                rotation_period: 25.0,
+                noise: noise(0.1, 1.2, 2.0, 0.45, 4, 0.0),
+                biome: vec![
+                    stop(0.0, [0.55, 0.35, 0.2], 0.9),
+                    stop(0.4, [0.85, 0.65, 0.4], 0.85),
+                    stop(0.7, [0.93, 0.8, 0.55], 0.8),
+                    stop(1.0, [0.6, 0.4, 0.3], 0.9),
+                ],
+                material: material([0.85, 0.65, 0.4], [0.1, 0.1, 0.1], 8.0),
+                atmosphere: Some(atmosphere(0.3, 20.0)),
+                clouds: None,
+                rings: None,
+            },
+            Preset {
+                name: String::from("Ice"),
+                mass: 40.0,
+                radius: 0.7,
+                axial_tilt: 5.0_f32.to_radians(),
+                rotation_period: 30.0,
+                noise: noise(0.06, 2.0, 2.0, 0.5, 3, 0.02),
+                biome: vec![
+                    stop(0.0, [0.6, 0.75, 0.9], 0.2),
+                    stop(0.3, [0.85, 0.92, 1.0], 0.3),
+                    stop(1.0, [1.0, 1.0, 1.0], 0.5),
+                ],
+                material: pbr(material([0.9, 0.95, 1.0], [0.8, 0.8, 0.8], 64.0)),
+                atmosphere: Some(atmosphere(0.4, 6.72)),
+                clouds: Some(clouds(0.3, [0.95, 0.97, 1.0])),
+                rings: None,
+            },
+            Preset {
+                name: String::from("Gas giant"),
+                mass: 3000.0,
+                radius: 3.0,
+                axial_tilt: 3.0_f32.to_radians(),
+                rotation_period: 10.0,
+                // Barely any relief, the noise only swirls the colours
+                noise: noise(0.005, 0.8, 3.0, 0.6, 6, 0.0),
+                biome: vec![
+                    stop(0.0, [0.55, 0.4, 0.3], 0.6),
+                    stop(0.3, [0.85, 0.75, 0.6], 0.6),
+                    stop(0.5, [0.7, 0.5, 0.35], 0.6),
+                    stop(0.7, [0.95, 0.9, 0.8], 0.6),
+                    stop(1.0, [0.75, 0.45, 0.3], 0.6),
+                ],
+                material: material([0.85, 0.75, 0.6], [0.05, 0.05, 0.05], 4.0),
+                atmosphere: Some(atmosphere(2.0, 6.72)),
+                clouds: None,
+                rings: Some(RingsDesc::from(&Rings::new())),
+            },
+            Preset {
+                name: String::from("Lava"),
+                mass: 90.0,
+                radius: 0.9,
+                axial_tilt: 10.0_f32.to_radians(),
+                rotation_period: 15.0,
+                noise: noise(0.12, 1.8, 2.3, 0.5, 5, 0.05),
+                biome: vec![
+                    stop(0.0, [1.0, 0.45, 0.05], 0.3),
+                    stop(0.05, [0.6, 0.1, 0.02], 0.6),
+                    stop(0.2, [0.15, 0.08, 0.06], 0.9),
+                    stop(1.0, [0.05, 0.05, 0.05], 0.95),
+                ],
+                material: material([0.15, 0.08, 0.06], [0.4, 0.3, 0.3], 32.0),
+                atmosphere: Some(atmosphere(1.5, 15.0)),
+                clouds: Some(clouds(0.5, [0.25, 0.2, 0.2])),
+                rings: None,
+            },
+            Preset {
+                name: String::from("Moon"),
+                mass: 1.0,
+                radius: 0.27,
+                axial_tilt: 6.7_f32.to_radians(),
+                rotation_period: 0.0,
+                noise: noise(0.05, 2.5, 2.5, 0.55, 6, 0.0),
+                biome: vec![
+                    stop(0.0, [0.3, 0.3, 0.3], 0.95),
+                    stop(0.6, [0.55, 0.55, 0.55], 0.9),
+                    stop(1.0, [0.75, 0.75, 0.75], 0.85),
+                ],
+                material: material([0.55, 0.55, 0.55], [0.1, 0.1, 0.1], 8.0),
+                atmosphere: None,
+                clouds: None,
+                rings: None,
+            },
+        ]
+    }
+}
+
+fn noise(
+    strength: f32,
+    base_roughness: f32,
+    roughness: f32,
+    persistence: f32,
+    num_layers: u32,
+    min_value: f32,
+) -> NoiseDesc {
+    NoiseDesc {
+        strength,
+        base_roughness,
+        roughness,
+        persistence,
+        center: [0.0, 0.0, 0.0],
+        num_layers,
+        min_value,
+    }
+}
+
+fn stop(height: f32, color: [f32; 3], roughness: f32) -> BiomeStopDesc {
+    BiomeStopDesc {
+        height,
+        color,
+        roughness,
+    }
+}
+
+fn material(color: [f32; 3], specular: [f32; 3], shininess: f32) -> MaterialDesc {
+    MaterialDesc {
+        ambient: color,
+        diffuse: color,
+        specular,
+        shininess,
+        shading: Shading::Phong,
+        roughness: 0.5,
+        metallic: 0.0,
+    }
+}
+
+// Earth's air thinned or thickened, with more or less haze
+fn atmosphere(density: f32, mie: f32) -> AtmosphereDesc {
+    AtmosphereDesc::from(&Atmosphere {
+        density,
+        mie,
+        ..Atmosphere::new()
+    })
+}
+
+fn clouds(coverage: f32, color: [f32; 3]) -> CloudsDesc {
+    CloudsDesc::from(&Clouds {
+        coverage,
+        color: glm::Vec3::from(color),
+        ..Clouds::new()
+    })
+}
+
+// Same colours shaded physically, roughness coming from the biome
+fn pbr(material: MaterialDesc) -> MaterialDesc {
+    MaterialDesc {
+        shading: Shading::Pbr,
+        ..material
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_presets_are_usable() {
+        for preset in Preset::builtin() {
+            assert!(preset.noise.num_layers > 0, "{}", preset.name);
+            assert!(
+                preset.biome.windows(2).all(|w| w[0].height <= w[1].height),
+                "{} biome stops out of order",
+                preset.name
+            );
+            assert!(preset.biome.len() <= crate::biome::MAX_BIOME_STOPS);
+        }
+    }
+
+    #[test]
+    fn user_presets_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "studious-system-presets-{}.ron",
+                std::process::id()
+            ))
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(Preset::load_user(&path).unwrap(), Vec::new());
+
+        let presets = Preset::builtin();
+        Preset::save_user(&presets, &path).unwrap();
+        let loaded = Preset::load_user(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, presets);
+    }
+}
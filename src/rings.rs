@@ -0,0 +1,338 @@
+use crate::gpu::{Texture, VertexArray};
+use crate::material::Material;
+use crate::mesh::Mesh;
+
+// Texels across the ring in the profile texture
+pub const PROFILE_RESOLUTION: usize = 512;
+
+// Quads around the ring
+const SEGMENTS: usize = 256;
+
+// Narrowest a ring gets, in planet radii, so its edges never meet or cross
+pub const MIN_WIDTH: f32 = 0.01;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RingStop {
+    // Across the ring, from 0 at the inner edge to 1 at the outer
+    pub position: f32,
+    pub color: glm::Vec3,
+    // Share of the light blocked, 0 for a gap
+    pub density: f32,
+}
+
+// A flat disc of ice and rock in the planet's equatorial plane, drawn see
+// through and shaded by the planet
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rings {
+    // Edges of the ring, in planet radii from the centre
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    // Colour and density across the ring, blended like biome stops
+    pub stops: Vec<RingStop>,
+    // An image to take the profile from instead of `stops`, read left to
+    // right from the inner edge with alpha as density
+    pub image: Option<String>,
+}
+
+impl Rings {
+    // Saturn's main rings: a faint C ring, the bright B ring, the Cassini
+    // division and the A ring
+    pub fn new() -> Rings {
+        let stop = |position, color: [f32; 3], density| RingStop {
+            position,
+            color: glm::Vec3::from(color),
+            density,
+        };
+        Rings {
+            inner_radius: 1.25,
+            outer_radius: 2.3,
+            stops: vec![
+                stop(0.0, [0.45, 0.42, 0.38], 0.05),
+                stop(0.2, [0.55, 0.5, 0.45], 0.25),
+                stop(0.25, [0.85, 0.78, 0.66], 0.8),
+                stop(0.55, [0.9, 0.83, 0.7], 0.95),
+                stop(0.58, [0.4, 0.37, 0.33], 0.05),
+                stop(0.64, [0.4, 0.37, 0.33], 0.05),
+                stop(0.67, [0.78, 0.72, 0.62], 0.6),
+                stop(1.0, [0.7, 0.65, 0.58], 0.35),
+            ],
+            image: None,
+        }
+    }
+
+    // Inner and outer edge, the outer kept at least MIN_WIDTH beyond the inner
+    pub fn edges(&self) -> (f32, f32) {
+        (
+            self.inner_radius,
+            self.outer_radius.max(self.inner_radius + MIN_WIDTH),
+        )
+    }
+
+    // The stops from the inner edge out, whatever order they were edited in
+    fn sorted_stops(&self) -> Vec<RingStop> {
+        let mut stops = self.stops.clone();
+        stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+        stops
+    }
+
+    // Colour and density at `position` across the ring from the stops: flat
+    // beyond the first and last, linear in between
+    pub fn gradient_at(&self, position: f32) -> glm::Vec4 {
+        gradient(&self.sorted_stops(), position)
+    }
+
+    // The profile as PROFILE_RESOLUTION texels from the inner edge out, colour
+    // in rgb and density in alpha
+    pub fn profile(&self) -> Result<Vec<glm::Vec4>, String> {
+        match &self.image {
+            Some(path) => image_profile(path),
+            None => {
+                let stops = self.sorted_stops();
+                Ok((0..PROFILE_RESOLUTION)
+                    .map(|i| gradient(&stops, (i as f32 + 0.5) / PROFILE_RESOLUTION as f32))
+                    .collect())
+            }
+        }
+    }
+
+    // A flat annulus between the edges, facing up the spin axis
+    pub fn mesh(&self) -> Mesh {
+        let material = Material {
+            ambient: glm::vec3(1.0, 1.0, 1.0),
+            diffuse: glm::vec3(1.0, 1.0, 1.0),
+            specular: glm::vec3(0.0, 0.0, 0.0),
+            shininess: 1.0,
+            roughness: 1.0,
+            ..Material::new()
+        };
+        let normal = glm::vec3(0.0, 1.0, 0.0);
+
+        let (inner_radius, outer_radius) = self.edges();
+        let mut mesh = Mesh::empty();
+        for i in 0..SEGMENTS {
+            let angle = i as f32 / SEGMENTS as f32 * std::f32::consts::TAU;
+            let direction = glm::vec3(angle.cos(), 0.0, angle.sin());
+            mesh.push_vertex(direction * inner_radius, Some(normal), &material);
+            mesh.push_vertex(direction * outer_radius, Some(normal), &material);
+
+            let (inner, outer) = (2 * i as u32, 2 * i as u32 + 1);
+            let (next_inner, next_outer) = (
+                2 * ((i + 1) % SEGMENTS) as u32,
+                2 * ((i + 1) % SEGMENTS) as u32 + 1,
+            );
+            mesh.indices
+                .extend([inner, next_inner, outer, outer, next_inner, next_outer]);
+        }
+        mesh.index_count = mesh.indices.len() as i32;
+        mesh
+    }
+}
+
+impl Default for Rings {
+    fn default() -> Rings {
+        Rings::new()
+    }
+}
+
+// Blends `stops`, sorted by position, at `position`
+fn gradient(stops: &[RingStop], position: f32) -> glm::Vec4 {
+    let mut blended = match stops.first() {
+        Some(stop) => stop.color.push(stop.density),
+        None => return glm::Vec4::zeros(),
+    };
+    for pair in stops.windows(2) {
+        let (low, high) = (pair[0], pair[1]);
+        if position >= low.position {
+            let span = (high.position - low.position).max(1e-6);
+            let t = ((position - low.position) / span).clamp(0.0, 1.0);
+            blended = glm::lerp(
+                &low.color.push(low.density),
+                &high.color.push(high.density),
+                t,
+            );
+        }
+    }
+    blended
+}
+
+// Averages each column of the image down to one texel, resampled to
+// PROFILE_RESOLUTION
+fn image_profile(path: &str) -> Result<Vec<glm::Vec4>, String> {
+    let image = image::open(path).map_err(|e| e.to_string())?.into_rgba32f();
+    let (width, height) = image.dimensions();
+    Ok((0..PROFILE_RESOLUTION)
+        .map(|i| {
+            let x = (i * width as usize / PROFILE_RESOLUTION) as u32;
+            let sum = (0..height).fold(glm::Vec4::zeros(), |sum, y| {
+                sum + glm::Vec4::from(image.get_pixel(x, y).0)
+            });
+            sum / height as f32
+        })
+        .collect())
+}
+
+// Rings as drawn: the mesh and profile texture made from `rings`, remade
+// when those change
+pub struct RingLayer {
+    pub rings: Rings,
+    pub vao: VertexArray,
+    pub index_count: i32,
+    profile: Texture,
+}
+
+impl RingLayer {
+    pub unsafe fn new(rings: &Rings) -> RingLayer {
+        let mesh = rings.mesh();
+        let profile = Texture::new_1d(gl::RGBA32F, PROFILE_RESOLUTION as u32);
+        let layer = RingLayer {
+            rings: rings.clone(),
+            vao: mesh.create_vao(),
+            index_count: mesh.index_count,
+            profile,
+        };
+        layer.upload_profile();
+        layer
+    }
+
+    // Catches up with edits to `rings`, only redoing what changed
+    pub unsafe fn update(&mut self, rings: &Rings) {
+        if *rings == self.rings {
+            return;
+        }
+        let resized = rings.inner_radius != self.rings.inner_radius
+            || rings.outer_radius != self.rings.outer_radius;
+        let reprofiled = rings.stops != self.rings.stops || rings.image != self.rings.image;
+        self.rings = rings.clone();
+
+        if resized {
+            let mesh = rings.mesh();
+            self.vao = mesh.create_vao();
+            self.index_count = mesh.index_count;
+        }
+        if reprofiled {
+            self.upload_profile();
+        }
+    }
+
+    pub unsafe fn bind_profile(&self, unit: u32) {
+        self.profile.bind(unit);
+    }
+
+    // A profile that can not be read leaves the ring clear, rather than
+    // retrying every frame
+    unsafe fn upload_profile(&self) {
+        let texels = self.rings.profile().unwrap_or_else(|e| {
+            println!("Failed to load ring profile: {}", e);
+            vec![glm::Vec4::zeros(); PROFILE_RESOLUTION]
+        });
+        gl::TextureSubImage1D(
+            self.profile.id(),
+            0,
+            0,
+            PROFILE_RESOLUTION as i32,
+            gl::RGBA,
+            gl::FLOAT,
+            texels.as_ptr() as *const _,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gradient_blends_colour_and_density() {
+        let rings = Rings {
+            stops: vec![
+                RingStop {
+                    position: 0.2,
+                    color: glm::vec3(1.0, 0.0, 0.0),
+                    density: 1.0,
+                },
+                RingStop {
+                    position: 0.6,
+                    color: glm::vec3(0.0, 0.0, 1.0),
+                    density: 0.0,
+                },
+            ],
+            ..Rings::new()
+        };
+        assert_eq!(rings.gradient_at(0.0), glm::vec4(1.0, 0.0, 0.0, 1.0));
+        assert!((rings.gradient_at(0.4) - glm::vec4(0.5, 0.0, 0.5, 0.5)).norm() < 1e-6);
+        assert_eq!(rings.gradient_at(1.0), glm::vec4(0.0, 0.0, 1.0, 0.0));
+
+        let profile = rings.profile().unwrap();
+        assert_eq!(profile.len(), PROFILE_RESOLUTION);
+        assert_eq!(profile[0], glm::vec4(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(profile[PROFILE_RESOLUTION - 1].w, 0.0);
+    }
+
+    #[test]
+    fn stops_blend_in_order_of_position() {
+        let sorted = Rings::new();
+        let mut shuffled = sorted.clone();
+        shuffled.stops.reverse();
+        shuffled.stops.swap(1, 4);
+        assert_eq!(shuffled.profile().unwrap(), sorted.profile().unwrap());
+    }
+
+    #[test]
+    fn edges_stay_apart() {
+        let rings = Rings {
+            inner_radius: 2.0,
+            outer_radius: 1.5,
+            ..Rings::new()
+        };
+        assert_eq!(rings.edges(), (2.0, 2.0 + MIN_WIDTH));
+        assert_eq!(Rings::new().edges(), (1.25, 2.3));
+    }
+
+    #[test]
+    fn profile_from_an_image() {
+        let path =
+            std::env::temp_dir().join(format!("studious-system-rings-{}.png", std::process::id()));
+        // Opaque red on the inside, a clear gap on the outside, over two rows
+        let image = image::RgbaImage::from_fn(4, 2, |x, _| {
+            if x < 2 {
+                image::Rgba([255, 0, 0, 255])
+            } else {
+                image::Rgba([0, 0, 255, 0])
+            }
+        });
+        image.save(&path).unwrap();
+
+        let rings = Rings {
+            image: Some(path.to_str().unwrap().to_string()),
+            ..Rings::new()
+        };
+        let profile = rings.profile().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(profile[0], glm::vec4(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(
+            profile[PROFILE_RESOLUTION - 1],
+            glm::vec4(0.0, 0.0, 1.0, 0.0)
+        );
+
+        let missing = Rings {
+            image: Some(String::from("no-such-ring.png")),
+            ..Rings::new()
+        };
+        assert!(missing.profile().is_err());
+    }
+
+    #[test]
+    fn annulus_spans_the_edges() {
+        let rings = Rings::new();
+        let mesh = rings.mesh();
+        assert_eq!(mesh.index_count as usize, SEGMENTS * 6);
+        for vertex in mesh.vertices.chunks(3) {
+            let radius = glm::length(&glm::vec3(vertex[0], vertex[1], vertex[2]));
+            assert!(
+                (radius - rings.inner_radius).abs() < 1e-5
+                    || (radius - rings.outer_radius).abs() < 1e-5
+            );
+            assert_eq!(vertex[1], 0.0);
+        }
+    }
+}
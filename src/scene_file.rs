@@ -0,0 +1,1150 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::atmosphere::Atmosphere;
+use crate::background::{Background, Starfield};
+use crate::biome::{Biome, BiomeStop};
+use crate::camera::{Camera, CameraMode, DepthMode};
+use crate::camera_path::{CameraPath, Keyframe};
+use crate::clouds::Clouds;
+use crate::light::{Light, LightKind};
+use crate::material::{Material, Shading};
+use crate::noise::Noise;
+use crate::planet::Planet;
+use crate::rings::{RingStop, Rings};
+use crate::star::Star;
+
+// Written into every scene and raised whenever the layout changes in a way
+// older files have to be upgraded from. Files without one are version 0.
+pub const SCENE_VERSION: u32 = 1;
+
+// On-disk description of a scene. Kept apart from the runtime types so the
+// file layout stays stable while those change, and vectors are plain arrays
+// so every format writes them the same way.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SceneFile {
+    #[serde(default)]
+    pub version: u32,
+    pub planets: Vec<PlanetDesc>,
+    pub lights: Vec<LightDesc>,
+    // Terrain shared by every planet in version 0 scenes, moved onto each of
+    // them when read and never written
+    #[serde(default, skip_serializing, deserialize_with = "present")]
+    pub noise: Option<NoiseDesc>,
+    // Scenes from before the starfield get the default sky
+    #[serde(default)]
+    pub background: BackgroundDesc,
+    pub camera: CameraDesc,
+    #[serde(default)]
+    pub camera_path: Vec<KeyframeDesc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlanetDesc {
+    pub name: String,
+    pub mass: f64,
+    pub position: [f64; 3],
+    pub velocity: [f64; 3],
+    pub radius: f64,
+    pub detail: u32,
+    #[serde(default)]
+    pub axial_tilt: f32,
+    #[serde(default)]
+    pub rotation_period: f32,
+    #[serde(default)]
+    pub precession_period: f32,
+    #[serde(default)]
+    pub parent: Option<usize>,
+    #[serde(default)]
+    pub orbital_period: f32,
+    // Stars are emissive. Older scenes only have the flag and light their
+    // suns with a separate light.
+    #[serde(default)]
+    pub emissive: bool,
+    #[serde(default)]
+    pub star: Option<StarDesc>,
+    pub material: MaterialDesc,
+    #[serde(default)]
+    pub noise: NoiseDesc,
+    #[serde(default)]
+    pub biome: Vec<BiomeStopDesc>,
+    #[serde(default)]
+    pub atmosphere: Option<AtmosphereDesc>,
+    #[serde(default)]
+    pub clouds: Option<CloudsDesc>,
+    #[serde(default)]
+    pub rings: Option<RingsDesc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct MaterialDesc {
+    pub ambient: [f32; 3],
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub shininess: f32,
+    // Scenes from before physically based shading are all Phong
+    #[serde(default = "default_shading")]
+    pub shading: Shading,
+    #[serde(default = "default_roughness")]
+    pub roughness: f32,
+    #[serde(default)]
+    pub metallic: f32,
+}
+
+// Reads a field only older scenes have, written without the Some that RON
+// would otherwise expect
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+fn default_shading() -> Shading {
+    Shading::Phong
+}
+
+// Halfway between mirror and matte, also for biome stops without one
+fn default_roughness() -> f32 {
+    0.5
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct StarDesc {
+    // Kelvin
+    pub temperature: f32,
+    pub intensity: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct AtmosphereDesc {
+    // In planet radii
+    pub height: f32,
+    pub rayleigh: [f32; 3],
+    pub rayleigh_scale_height: f32,
+    pub mie: f32,
+    pub mie_scale_height: f32,
+    pub mie_anisotropy: f32,
+    pub density: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CloudsDesc {
+    // In planet radii
+    pub altitude: f32,
+    pub coverage: f32,
+    pub density: f32,
+    pub color: [f32; 3],
+    // Radians per second
+    pub wind_speed: f32,
+    pub noise: NoiseDesc,
+    pub shadows: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RingsDesc {
+    // In planet radii
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    pub stops: Vec<RingStopDesc>,
+    // Used in place of the stops when given
+    #[serde(default)]
+    pub image: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RingStopDesc {
+    pub position: f32,
+    pub color: [f32; 3],
+    pub density: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BackgroundDesc {
+    pub starfield: StarfieldDesc,
+    // Right, left, top, bottom, front and back
+    #[serde(default)]
+    pub skybox: Option<[String; 6]>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct StarfieldDesc {
+    pub seed: u64,
+    pub count: u32,
+    pub brightness: f32,
+    pub sky_color: [f32; 3],
+    pub milky_way: bool,
+    pub milky_way_brightness: f32,
+    pub galactic_pole: [f32; 3],
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct LightDesc {
+    #[serde(default = "default_light_kind")]
+    pub kind: LightKind,
+    pub position: [f64; 3],
+    #[serde(default = "default_light_direction")]
+    pub direction: [f32; 3],
+    pub ambient: [f32; 3],
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    // Constant, linear and quadratic falloff
+    #[serde(default = "default_attenuation")]
+    pub attenuation: [f32; 3],
+    // Spot cone in radians
+    #[serde(default = "default_inner_angle")]
+    pub inner_angle: f32,
+    #[serde(default = "default_outer_angle")]
+    pub outer_angle: f32,
+}
+
+// Scenes from before light types existed only had point lights
+fn default_light() -> Light {
+    Light::point(glm::DVec3::new(0.0, 0.0, 0.0))
+}
+
+fn default_light_kind() -> LightKind {
+    default_light().kind
+}
+
+fn default_light_direction() -> [f32; 3] {
+    default_light().direction.into()
+}
+
+fn default_attenuation() -> [f32; 3] {
+    default_light().attenuation.into()
+}
+
+fn default_inner_angle() -> f32 {
+    default_light().inner_angle
+}
+
+fn default_outer_angle() -> f32 {
+    default_light().outer_angle
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct NoiseDesc {
+    pub strength: f32,
+    pub base_roughness: f32,
+    pub roughness: f32,
+    pub persistence: f32,
+    pub center: [f32; 3],
+    pub num_layers: u32,
+    pub min_value: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct BiomeStopDesc {
+    pub height: f32,
+    pub color: [f32; 3],
+    #[serde(default = "default_roughness")]
+    pub roughness: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CameraDesc {
+    pub position: [f64; 3],
+    // Quaternion as [x, y, z, w]. Scenes from before the orbit camera have
+    // `yaw` and `pitch` instead, and the defaults for the rest.
+    #[serde(default = "default_orientation")]
+    pub orientation: [f32; 4],
+    // Vertical field of view in radians
+    #[serde(default = "default_fov")]
+    pub fov: f32,
+    #[serde(default = "default_mode")]
+    pub mode: CameraMode,
+    #[serde(default)]
+    pub target: Option<usize>,
+    #[serde(default = "default_distance")]
+    pub distance: f32,
+    // Degrees, turned into `orientation` when read and never written
+    #[serde(default, skip_serializing, deserialize_with = "present")]
+    pub yaw: Option<f32>,
+    #[serde(default, skip_serializing, deserialize_with = "present")]
+    pub pitch: Option<f32>,
+    // Scenes from before these existed get the camera's defaults
+    #[serde(default = "default_near")]
+    pub near: f32,
+    #[serde(default = "default_far")]
+    pub far: f32,
+    #[serde(default = "default_depth_mode")]
+    pub depth_mode: DepthMode,
+}
+
+fn default_camera() -> Camera {
+    Camera::new(glm::DVec3::new(0.0, 0.0, 0.0))
+}
+
+fn default_orientation() -> [f32; 4] {
+    default_camera().orientation.coords.into()
+}
+
+fn default_fov() -> f32 {
+    default_camera().fov
+}
+
+fn default_mode() -> CameraMode {
+    default_camera().mode
+}
+
+fn default_distance() -> f32 {
+    default_camera().distance
+}
+
+fn default_near() -> f32 {
+    default_camera().near
+}
+
+fn default_far() -> f32 {
+    default_camera().far
+}
+
+fn default_depth_mode() -> DepthMode {
+    default_camera().depth_mode
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct KeyframeDesc {
+    pub time: f32,
+    pub position: [f64; 3],
+    // Quaternion as [x, y, z, w]
+    pub orientation: [f32; 4],
+    pub fov: f32,
+}
+
+pub enum SceneFormat {
+    Ron,
+    Json,
+    Toml,
+}
+
+impl SceneFormat {
+    pub fn from_path(path: &str) -> Result<SceneFormat, String> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        match extension {
+            "ron" => Ok(SceneFormat::Ron),
+            "json" => Ok(SceneFormat::Json),
+            "toml" => Ok(SceneFormat::Toml),
+            e => Err(format!("Unsupported scene format: {}", e)),
+        }
+    }
+}
+
+impl SceneFile {
+    // Reads a scene, picking the format from the file extension
+    pub fn load(path: &str) -> Result<SceneFile, String> {
+        let format = SceneFormat::from_path(path)?;
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        SceneFile::parse(&text, format)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = self.serialize(SceneFormat::from_path(path)?)?;
+        std::fs::write(path, text).map_err(|e| e.to_string())
+    }
+
+    pub fn parse(text: &str, format: SceneFormat) -> Result<SceneFile, String> {
+        let scene: SceneFile = match format {
+            SceneFormat::Ron => ron::from_str(text).map_err(|e| e.to_string())?,
+            SceneFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string())?,
+            SceneFormat::Toml => toml::from_str(text).map_err(|e| e.to_string())?,
+        };
+        scene.validate()?;
+        Ok(scene.upgrade())
+    }
+
+    // Brings a scene written by an older build up to SCENE_VERSION
+    fn upgrade(mut self) -> SceneFile {
+        if let Some(noise) = self.noise.take() {
+            for planet in &mut self.planets {
+                planet.noise = noise;
+            }
+        }
+        let camera = &mut self.camera;
+        if let (Some(yaw), Some(pitch)) = (camera.yaw.take(), camera.pitch.take()) {
+            camera.orientation = yaw_pitch_orientation(yaw, pitch).coords.into();
+        }
+        self.version = SCENE_VERSION;
+        self
+    }
+
+    pub fn serialize(&self, format: SceneFormat) -> Result<String, String> {
+        match format {
+            SceneFormat::Ron => ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+                .map_err(|e| e.to_string()),
+            SceneFormat::Json => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
+            SceneFormat::Toml => toml::to_string_pretty(self).map_err(|e| e.to_string()),
+        }
+    }
+
+    // Planets are built in order, so a parent has to come before its moons.
+    // Scenes from a newer build may mean something this one can not read.
+    fn validate(&self) -> Result<(), String> {
+        if self.version > SCENE_VERSION {
+            return Err(format!(
+                "Scene version {} is newer than the supported version {}",
+                self.version, SCENE_VERSION
+            ));
+        }
+        for (i, planet) in self.planets.iter().enumerate() {
+            if let Some(parent) = planet.parent {
+                if parent >= i {
+                    return Err(format!(
+                        "Planet {} ({}) must come after its parent {}",
+                        i, planet.name, parent
+                    ));
+                }
+            }
+        }
+        if let Some(target) = self.camera.target {
+            if target >= self.planets.len() {
+                return Err(format!(
+                    "Camera target {} is not one of the {} planets",
+                    target,
+                    self.planets.len()
+                ));
+            }
+        }
+        // Directions are normalized on the way to the shaders, which a zero
+        // one would fill with NaN
+        for (i, light) in self.lights.iter().enumerate() {
+            if light.direction == [0.0; 3] {
+                return Err(format!("Light {} has no direction", i));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn capture(
+        planets: &[Planet],
+        lights: &[Light],
+        background: &Background,
+        camera: &Camera,
+        camera_path: &CameraPath,
+    ) -> SceneFile {
+        SceneFile {
+            version: SCENE_VERSION,
+            planets: planets.iter().map(PlanetDesc::from).collect(),
+            lights: lights.iter().map(LightDesc::from).collect(),
+            noise: None,
+            background: BackgroundDesc::from(background),
+            camera: CameraDesc::from(camera),
+            camera_path: camera_path
+                .keyframes()
+                .iter()
+                .map(KeyframeDesc::from)
+                .collect(),
+        }
+    }
+
+    pub fn planets(&self) -> Vec<Planet> {
+        self.planets.iter().map(PlanetDesc::to_planet).collect()
+    }
+
+    pub fn lights(&self) -> Vec<Light> {
+        self.lights.iter().map(LightDesc::to_light).collect()
+    }
+
+    pub fn background(&self) -> Background {
+        self.background.to_background()
+    }
+
+    pub fn camera_path(&self) -> CameraPath {
+        let mut path = CameraPath::new();
+        for keyframe in &self.camera_path {
+            path.insert(keyframe.to_keyframe());
+        }
+        path
+    }
+}
+
+// The old camera looked along (cos yaw cos pitch, sin pitch, sin yaw cos pitch)
+// with the angles in degrees, so a yaw of -90 faced down -z
+fn yaw_pitch_orientation(yaw: f32, pitch: f32) -> glm::Quat {
+    let turn = glm::quat_angle_axis(-(yaw + 90.0).to_radians(), &glm::vec3(0.0, 1.0, 0.0));
+    let tilt = glm::quat_angle_axis(pitch.to_radians(), &glm::vec3(1.0, 0.0, 0.0));
+    turn * tilt
+}
+
+impl PlanetDesc {
+    pub fn to_planet(&self) -> Planet {
+        let mut planet = Planet::new(
+            self.mass,
+            glm::DVec3::from(self.position),
+            glm::DVec3::from(self.velocity),
+            self.radius,
+            self.material.to_material(),
+            self.detail,
+        );
+        planet.name = self.name.clone();
+        planet.axial_tilt = self.axial_tilt;
+        planet.rotation_period = self.rotation_period;
+        planet.precession_period = self.precession_period;
+        planet.parent = self.parent;
+        planet.orbital_period = self.orbital_period;
+        planet.star = match (&self.star, self.emissive) {
+            (Some(star), _) => Some(star.to_star()),
+            // Keep the separate light of older scenes the only one
+            (None, true) => Some(Star {
+                intensity: 0.0,
+                ..Star::new()
+            }),
+            (None, false) => None,
+        };
+        planet.noise = self.noise.to_noise();
+        planet.biome = BiomeStopDesc::to_biome(&self.biome);
+        planet.atmosphere = self.atmosphere.as_ref().map(AtmosphereDesc::to_atmosphere);
+        planet.clouds = self.clouds.as_ref().map(CloudsDesc::to_clouds);
+        planet.rings = self.rings.as_ref().map(RingsDesc::to_rings);
+        planet
+    }
+}
+
+impl From<&Planet> for PlanetDesc {
+    fn from(planet: &Planet) -> PlanetDesc {
+        PlanetDesc {
+            name: planet.name.clone(),
+            mass: planet.mass,
+            position: planet.position.into(),
+            velocity: planet.velocity.into(),
+            radius: planet.radius,
+            detail: planet.detail(),
+            axial_tilt: planet.axial_tilt,
+            rotation_period: planet.rotation_period,
+            precession_period: planet.precession_period,
+            parent: planet.parent,
+            orbital_period: planet.orbital_period,
+            emissive: planet.star.is_some(),
+            star: planet.star.as_ref().map(StarDesc::from),
+            material: MaterialDesc::from(&planet.material()),
+            noise: NoiseDesc::from(&planet.noise),
+            biome: BiomeStopDesc::from_biome(&planet.biome),
+            atmosphere: planet.atmosphere.as_ref().map(AtmosphereDesc::from),
+            clouds: planet.clouds.as_ref().map(CloudsDesc::from),
+            rings: planet.rings.as_ref().map(RingsDesc::from),
+        }
+    }
+}
+
+impl MaterialDesc {
+    pub fn to_material(&self) -> Material {
+        Material {
+            ambient: glm::Vec3::from(self.ambient),
+            diffuse: glm::Vec3::from(self.diffuse),
+            specular: glm::Vec3::from(self.specular),
+            shininess: self.shininess,
+            shading: self.shading,
+            roughness: self.roughness,
+            metallic: self.metallic,
+        }
+    }
+}
+
+impl From<&Material> for MaterialDesc {
+    fn from(material: &Material) -> MaterialDesc {
+        MaterialDesc {
+            ambient: material.ambient.into(),
+            diffuse: material.diffuse.into(),
+            specular: material.specular.into(),
+            shininess: material.shininess,
+            shading: material.shading,
+            roughness: material.roughness,
+            metallic: material.metallic,
+        }
+    }
+}
+
+impl StarDesc {
+    pub fn to_star(&self) -> Star {
+        Star {
+            temperature: self.temperature,
+            intensity: self.intensity,
+        }
+    }
+}
+
+impl From<&Star> for StarDesc {
+    fn from(star: &Star) -> StarDesc {
+        StarDesc {
+            temperature: star.temperature,
+            intensity: star.intensity,
+        }
+    }
+}
+
+impl AtmosphereDesc {
+    pub fn to_atmosphere(&self) -> Atmosphere {
+        Atmosphere {
+            height: self.height,
+            rayleigh: glm::Vec3::from(self.rayleigh),
+            rayleigh_scale_height: self.rayleigh_scale_height,
+            mie: self.mie,
+            mie_scale_height: self.mie_scale_height,
+            mie_anisotropy: self.mie_anisotropy,
+            density: self.density,
+        }
+    }
+}
+
+impl From<&Atmosphere> for AtmosphereDesc {
+    fn from(atmosphere: &Atmosphere) -> AtmosphereDesc {
+        AtmosphereDesc {
+            height: atmosphere.height,
+            rayleigh: atmosphere.rayleigh.into(),
+            rayleigh_scale_height: atmosphere.rayleigh_scale_height,
+            mie: atmosphere.mie,
+            mie_scale_height: atmosphere.mie_scale_height,
+            mie_anisotropy: atmosphere.mie_anisotropy,
+            density: atmosphere.density,
+        }
+    }
+}
+
+impl CloudsDesc {
+    pub fn to_clouds(&self) -> Clouds {
+        Clouds {
+            altitude: self.altitude,
+            coverage: self.coverage,
+            density: self.density,
+            color: glm::Vec3::from(self.color),
+            wind_speed: self.wind_speed,
+            noise: self.noise.to_noise(),
+            shadows: self.shadows,
+        }
+    }
+}
+
+impl From<&Clouds> for CloudsDesc {
+    fn from(clouds: &Clouds) -> CloudsDesc {
+        CloudsDesc {
+            altitude: clouds.altitude,
+            coverage: clouds.coverage,
+            density: clouds.density,
+            color: clouds.color.into(),
+            wind_speed: clouds.wind_speed,
+            noise: NoiseDesc::from(&clouds.noise),
+            shadows: clouds.shadows,
+        }
+    }
+}
+
+impl RingsDesc {
+    pub fn to_rings(&self) -> Rings {
+        Rings {
+            inner_radius: self.inner_radius,
+            outer_radius: self.outer_radius,
+            stops: self
+                .stops
+                .iter()
+                .map(|stop| RingStop {
+                    position: stop.position,
+                    color: glm::Vec3::from(stop.color),
+                    density: stop.density,
+                })
+                .collect(),
+            image: self.image.clone(),
+        }
+    }
+}
+
+impl From<&Rings> for RingsDesc {
+    fn from(rings: &Rings) -> RingsDesc {
+        RingsDesc {
+            inner_radius: rings.inner_radius,
+            outer_radius: rings.outer_radius,
+            stops: rings
+                .stops
+                .iter()
+                .map(|stop| RingStopDesc {
+                    position: stop.position,
+                    color: stop.color.into(),
+                    density: stop.density,
+                })
+                .collect(),
+            image: rings.image.clone(),
+        }
+    }
+}
+
+impl BackgroundDesc {
+    pub fn to_background(&self) -> Background {
+        let starfield = &self.starfield;
+        Background {
+            starfield: Starfield {
+                seed: starfield.seed,
+                count: starfield.count,
+                brightness: starfield.brightness,
+                sky_color: glm::Vec3::from(starfield.sky_color),
+                milky_way: starfield.milky_way,
+                milky_way_brightness: starfield.milky_way_brightness,
+                galactic_pole: glm::Vec3::from(starfield.galactic_pole),
+            },
+            skybox: self.skybox.clone(),
+        }
+    }
+}
+
+impl From<&Background> for BackgroundDesc {
+    fn from(background: &Background) -> BackgroundDesc {
+        let starfield = &background.starfield;
+        BackgroundDesc {
+            starfield: StarfieldDesc {
+                seed: starfield.seed,
+                count: starfield.count,
+                brightness: starfield.brightness,
+                sky_color: starfield.sky_color.into(),
+                milky_way: starfield.milky_way,
+                milky_way_brightness: starfield.milky_way_brightness,
+                galactic_pole: starfield.galactic_pole.into(),
+            },
+            skybox: background.skybox.clone(),
+        }
+    }
+}
+
+impl Default for BackgroundDesc {
+    fn default() -> BackgroundDesc {
+        BackgroundDesc::from(&Background::default())
+    }
+}
+
+impl LightDesc {
+    pub fn to_light(&self) -> Light {
+        Light {
+            kind: self.kind,
+            position: glm::DVec3::from(self.position),
+            direction: glm::Vec3::from(self.direction),
+            ambient: glm::Vec3::from(self.ambient),
+            diffuse: glm::Vec3::from(self.diffuse),
+            specular: glm::Vec3::from(self.specular),
+            attenuation: glm::Vec3::from(self.attenuation),
+            inner_angle: self.inner_angle,
+            outer_angle: self.outer_angle,
+        }
+    }
+}
+
+impl From<&Light> for LightDesc {
+    fn from(light: &Light) -> LightDesc {
+        LightDesc {
+            kind: light.kind,
+            position: light.position.into(),
+            direction: light.direction.into(),
+            ambient: light.ambient.into(),
+            diffuse: light.diffuse.into(),
+            specular: light.specular.into(),
+            attenuation: light.attenuation.into(),
+            inner_angle: light.inner_angle,
+            outer_angle: light.outer_angle,
+        }
+    }
+}
+
+impl NoiseDesc {
+    pub fn to_noise(&self) -> Noise {
+        Noise {
+            strength: self.strength,
+            base_roughness: self.base_roughness,
+            roughness: self.roughness,
+            persistence: self.persistence,
+            center: glm::Vec3::from(self.center),
+            num_layers: self.num_layers,
+            min_value: self.min_value,
+        }
+    }
+}
+
+impl CameraDesc {
+    pub fn to_camera(&self) -> Camera {
+        let [x, y, z, w] = self.orientation;
+        let mut camera = Camera::new(glm::DVec3::from(self.position));
+        camera.orientation = glm::quat(x, y, z, w);
+        camera.fov = self.fov;
+        camera.distance = self.distance;
+        camera.near = self.near;
+        camera.far = self.far;
+        camera.depth_mode = self.depth_mode;
+        camera.set_mode(self.mode);
+        if self.target.is_some() {
+            camera.follow(self.target, self.distance);
+        }
+        camera
+    }
+}
+
+impl KeyframeDesc {
+    pub fn to_keyframe(&self) -> Keyframe {
+        let [x, y, z, w] = self.orientation;
+        Keyframe {
+            time: self.time,
+            position: glm::DVec3::from(self.position),
+            orientation: glm::quat(x, y, z, w),
+            fov: self.fov,
+        }
+    }
+}
+
+impl From<&Keyframe> for KeyframeDesc {
+    fn from(keyframe: &Keyframe) -> KeyframeDesc {
+        KeyframeDesc {
+            time: keyframe.time,
+            position: keyframe.position.into(),
+            orientation: keyframe.orientation.coords.into(),
+            fov: keyframe.fov,
+        }
+    }
+}
+
+impl From<&Camera> for CameraDesc {
+    fn from(camera: &Camera) -> CameraDesc {
+        CameraDesc {
+            position: camera.position.into(),
+            orientation: camera.orientation.coords.into(),
+            fov: camera.fov,
+            mode: camera.mode,
+            target: camera.target,
+            distance: camera.distance,
+            yaw: None,
+            pitch: None,
+            near: camera.near,
+            far: camera.far,
+            depth_mode: camera.depth_mode,
+        }
+    }
+}
+
+impl Default for NoiseDesc {
+    fn default() -> NoiseDesc {
+        NoiseDesc::from(&Noise::new())
+    }
+}
+
+impl From<&Noise> for NoiseDesc {
+    fn from(noise: &Noise) -> NoiseDesc {
+        NoiseDesc {
+            strength: noise.strength,
+            base_roughness: noise.base_roughness,
+            roughness: noise.roughness,
+            persistence: noise.persistence,
+            center: noise.center.into(),
+            num_layers: noise.num_layers,
+            min_value: noise.min_value,
+        }
+    }
+}
+
+impl BiomeStopDesc {
+    pub fn to_biome(stops: &[BiomeStopDesc]) -> Biome {
+        Biome {
+            stops: stops
+                .iter()
+                .map(|stop| BiomeStop {
+                    height: stop.height,
+                    color: glm::Vec3::from(stop.color),
+                    roughness: stop.roughness,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn from_biome(biome: &Biome) -> Vec<BiomeStopDesc> {
+        biome
+            .stops
+            .iter()
+            .map(|stop| BiomeStopDesc {
+                height: stop.height,
+                color: stop.color.into(),
+                roughness: stop.roughness,
+            })
+            .collect()
+    }
+}
+
+// The demo scene: a sun, a tilted planet and a moon orbiting it
+impl Default for SceneFile {
+    fn default() -> SceneFile {
+        let material = MaterialDesc::from(&Material::new());
+
+        SceneFile {
+            version: SCENE_VERSION,
+            planets: vec![
+                PlanetDesc {
+                    name: String::from("Sun"),
+                    mass: 100.0,
+                    position: [0.0, 10.0, 0.0],
+                    velocity: [0.0, 0.0, 0.0],
+                    radius: 1.0,
+                    detail: 6,
+                    axial_tilt: 0.0,
+                    rotation_period: 0.0,
+                    precession_period: 0.0,
+                    parent: None,
+                    orbital_period: 0.0,
+                    emissive: true,
+                    star: Some(StarDesc::from(&Star::new())),
+                    material: MaterialDesc {
+                        ambient: [1.0, 1.0, 1.0],
+                        diffuse: [0.5, 0.5, 0.5],
+                        specular: [1.0, 1.0, 1.0],
+                        shininess: 32.0,
+                        shading: Shading::Phong,
+                        roughness: default_roughness(),
+                        metallic: 0.0,
+                    },
+                    noise: NoiseDesc::default(),
+                    biome: Vec::new(),
+                    atmosphere: None,
+                    clouds: None,
+                    rings: None,
+                },
+                PlanetDesc {
+                    name: String::from("Planet"),
+                    mass: 100.0,
+                    position: [0.0, 0.0, 0.0],
+                    velocity: [0.0, 0.0, 0.0],
+                    radius: 1.0,
+                    detail: 6,
+                    axial_tilt: 23.44_f32.to_radians(),
+                    rotation_period: 20.0,
+                    precession_period: 300.0,
+                    parent: None,
+                    orbital_period: 0.0,
+                    emissive: false,
+                    star: None,
+                    material,
+                    noise: NoiseDesc::default(),
+                    biome: Vec::new(),
+                    atmosphere: Some(AtmosphereDesc::from(&Atmosphere::new())),
+                    clouds: Some(CloudsDesc::from(&Clouds::new())),
+                    rings: None,
+                },
+                PlanetDesc {
+                    name: String::from("Moon"),
+                    mass: 1.0,
+                    position: [3.0, 0.0, 0.0],
+                    velocity: [0.0, 0.0, 0.0],
+                    radius: 0.27,
+                    detail: 4,
+                    axial_tilt: 0.0,
+                    rotation_period: 0.0,
+                    precession_period: 0.0,
+                    parent: Some(1),
+                    orbital_period: 10.0,
+                    emissive: false,
+                    star: None,
+                    material,
+                    noise: NoiseDesc::default(),
+                    biome: Vec::new(),
+                    atmosphere: None,
+                    clouds: None,
+                    rings: None,
+                },
+            ],
+            lights: Vec::new(),
+            noise: None,
+            background: BackgroundDesc::default(),
+            camera: CameraDesc {
+                position: [0.0, 0.0, 5.0],
+                orientation: [0.0, 0.0, 0.0, 1.0],
+                fov: std::f32::consts::FRAC_PI_2,
+                mode: CameraMode::Orbit,
+                target: Some(1),
+                distance: 5.0,
+                yaw: None,
+                pitch: None,
+                near: default_near(),
+                far: default_far(),
+                depth_mode: default_depth_mode(),
+            },
+            camera_path: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene() -> SceneFile {
+        let mut scene = SceneFile::default();
+        scene.planets[1].noise.num_layers = 4;
+        scene.planets[1].noise.center = [0.25, -0.5, 0.125];
+        scene.camera.fov = 1.2;
+        scene.camera.depth_mode = DepthMode::Logarithmic;
+        scene.camera.orientation = [0.0, 0.38268343, 0.0, 0.9238795];
+        scene.camera_path = vec![
+            KeyframeDesc {
+                time: 0.0,
+                position: [0.0, 2.0, 8.0],
+                orientation: [0.0, 0.0, 0.0, 1.0],
+                fov: 1.2,
+            },
+            KeyframeDesc {
+                time: 4.5,
+                position: [3.0, 0.5, 1.0],
+                orientation: [0.0, 0.38268343, 0.0, 0.9238795],
+                fov: 0.8,
+            },
+        ];
+        let mut spot = Light::point(glm::DVec3::new(1.0, 2.0, 3.0));
+        spot.kind = LightKind::Spot;
+        spot.direction = glm::vec3(0.0, 0.0, -1.0);
+        spot.attenuation = glm::vec3(1.0, 0.09, 0.032);
+        scene.lights.push(LightDesc::from(&spot));
+        scene.planets[1].biome = vec![
+            BiomeStopDesc {
+                height: 0.0,
+                color: [0.1, 0.2, 0.6],
+                roughness: 0.1,
+            },
+            BiomeStopDesc {
+                height: 0.8,
+                color: [1.0, 1.0, 1.0],
+                roughness: 0.6,
+            },
+        ];
+        scene.planets[1].rings = Some(RingsDesc::from(&Rings::new()));
+        scene.background.starfield.seed = 42;
+        scene.background.skybox =
+            Some(["px.png", "nx.png", "py.png", "ny.png", "pz.png", "nz.png"].map(String::from));
+        scene.planets[2].material.shading = Shading::Pbr;
+        scene.planets[2].material.metallic = 1.0;
+        scene
+    }
+
+    fn assert_round_trip(format: fn() -> SceneFormat) {
+        let scene = scene();
+        let text = scene.serialize(format()).unwrap();
+        assert_eq!(SceneFile::parse(&text, format()).unwrap(), scene);
+    }
+
+    #[test]
+    fn ron_round_trip() {
+        assert_round_trip(|| SceneFormat::Ron);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        assert_round_trip(|| SceneFormat::Json);
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        assert_round_trip(|| SceneFormat::Toml);
+    }
+
+    #[test]
+    fn planets_survive_conversion() {
+        let scene = scene();
+        let planets = scene.planets();
+        let camera = scene.camera.to_camera();
+        let captured = SceneFile::capture(
+            &planets,
+            &scene.lights(),
+            &scene.background(),
+            &camera,
+            &scene.camera_path(),
+        );
+        assert_eq!(captured.planets, scene.planets);
+        assert_eq!(captured.background, scene.background);
+        assert_eq!(captured.lights, scene.lights);
+        assert_eq!(captured.camera, scene.camera);
+        assert_eq!(captured.camera_path, scene.camera_path);
+    }
+
+    #[test]
+    fn older_suns_keep_their_separate_light() {
+        let mut scene = scene();
+        assert_eq!(scene.planets()[0].star, Some(Star::new()));
+
+        scene.planets[0].star = None;
+        let sun = &scene.planets()[0];
+        assert_eq!(sun.star.map(|star| star.intensity), Some(0.0));
+        assert_eq!(scene.planets()[1].star, None);
+    }
+
+    #[test]
+    fn shared_noise_moves_onto_every_planet() {
+        // Written before each planet had its own terrain
+        let text = r#"(
+            planets: [
+                (name: "A", mass: 1.0, position: (0.0, 0.0, 0.0), velocity: (0.0, 0.0, 0.0),
+                 radius: 1.0, detail: 2,
+                 material: (ambient: (1.0, 1.0, 1.0), diffuse: (1.0, 1.0, 1.0),
+                            specular: (1.0, 1.0, 1.0), shininess: 32.0)),
+            ],
+            lights: [],
+            noise: (strength: 0.3, base_roughness: 1.0, roughness: 2.0, persistence: 0.5,
+                    center: (0.0, 0.0, 0.0), num_layers: 3, min_value: 0.1),
+            camera: (position: (0.0, 0.0, 5.0), yaw: -90.0, pitch: 0.0),
+        )"#;
+        let scene = SceneFile::parse(text, SceneFormat::Ron).unwrap();
+        assert_eq!(scene.version, SCENE_VERSION);
+        assert_eq!(scene.noise, None);
+        assert_eq!(scene.planets[0].noise.strength, 0.3);
+        assert_eq!(scene.planets[0].noise.num_layers, 3);
+    }
+
+    #[test]
+    fn yaw_and_pitch_become_an_orientation() {
+        // Written before the orbit camera, with only a free camera to save
+        let text = r#"{
+            "planets": [],
+            "lights": [],
+            "camera": { "position": [1.0, 2.0, 3.0], "yaw": 0.0, "pitch": 30.0 }
+        }"#;
+        let scene = SceneFile::parse(text, SceneFormat::Json).unwrap();
+        assert_eq!(scene.camera.yaw, None);
+        let camera = scene.camera.to_camera();
+        assert_eq!(camera.mode, CameraMode::Free);
+        assert_eq!(camera.fov, default_fov());
+
+        let pitch = 30.0_f32.to_radians();
+        let expected = glm::vec3(pitch.cos(), pitch.sin(), 0.0);
+        assert!((camera.forward() - expected).norm() < 1e-5);
+        let ahead = yaw_pitch_orientation(-90.0, 0.0);
+        let forward = glm::quat_rotate_vec3(&ahead, &glm::vec3(0.0, 0.0, -1.0));
+        assert!((forward - glm::vec3(0.0, 0.0, -1.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn newer_scenes_are_refused() {
+        let mut scene = scene();
+        assert_eq!(scene.version, SCENE_VERSION);
+        scene.version = SCENE_VERSION + 1;
+        let text = scene.serialize(SceneFormat::Ron).unwrap();
+        assert!(SceneFile::parse(&text, SceneFormat::Ron).is_err());
+    }
+
+    #[test]
+    fn moons_must_follow_their_parent() {
+        let mut scene = scene();
+        scene.planets.swap(1, 2);
+        scene.planets[1].parent = Some(2);
+        let text = scene.serialize(SceneFormat::Json).unwrap();
+        assert!(SceneFile::parse(&text, SceneFormat::Json).is_err());
+        assert!(SceneFormat::from_path("scene.yaml").is_err());
+    }
+
+    #[test]
+    fn camera_target_must_be_a_planet() {
+        let mut scene = scene();
+        scene.camera.target = Some(scene.planets.len());
+        let text = scene.serialize(SceneFormat::Ron).unwrap();
+        assert!(SceneFile::parse(&text, SceneFormat::Ron).is_err());
+    }
+
+    #[test]
+    fn lights_must_have_a_direction() {
+        let mut scene = scene();
+        scene.lights[0].direction = [0.0; 3];
+        let text = scene.serialize(SceneFormat::Json).unwrap();
+        assert!(SceneFile::parse(&text, SceneFormat::Json).is_err());
+    }
+}
@@ -1,12 +1,349 @@
 extern crate nalgebra_glm as glm;
 
+use std::ops::{Index, IndexMut};
+
+use crate::atmosphere::Atmosphere;
+use crate::biome::Biome;
+use crate::clouds::Clouds;
+use crate::gpu::VertexArray;
+use crate::mesh::{Mesh, VertexUpdates};
+use crate::noise::Noise;
+use crate::rings::RingLayer;
+use crate::vertex_format::MESH_VERTEX_FORMAT;
+
+pub type NodeId = usize;
+
+// Displacement and colouring for nodes drawn with shape.vert
+#[derive(Clone)]
+pub struct Terrain {
+    pub noise: Noise,
+    pub biome: Biome,
+}
+
 pub struct SceneNode {
-    pub vao_id: u32,
+    // Nodes without a vertex array only group and transform their children
+    pub vao: Option<VertexArray>,
     pub index_count: i32,
     pub shader_program: u32,
+    pub terrain: Option<Terrain>,
+    // Colour of nodes drawn unlit with light.frag
+    pub emission: Option<glm::Vec3>,
+    // Air around the node, drawn as a shell after everything solid
+    pub atmosphere: Option<Atmosphere>,
+    // Cloud layer drawn around the node the same way
+    pub clouds: Option<Clouds>,
+    // Rings in the node's equatorial plane, drawn see through after the clouds
+    pub rings: Option<RingLayer>,
+
+    position: glm::Vec3,
+    orientation: glm::Quat,
+    scale: glm::Vec3,
+    reference_point: glm::Vec3,
+
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+
+    // Cached model matrix, recomputed when this node or an ancestor changes
+    world_matrix: glm::Mat4,
+    dirty: bool,
+}
+
+impl SceneNode {
+    pub fn from_mesh(
+        mesh: &Mesh,
+        shader_program: u32,
+        position: glm::Vec3,
+        updates: VertexUpdates,
+    ) -> SceneNode {
+        SceneNode {
+            vao: Some(unsafe { mesh.create_vao_with_format(&MESH_VERTEX_FORMAT, updates) }),
+            index_count: mesh.index_count,
+            shader_program,
+            ..SceneNode::empty(position)
+        }
+    }
+
+    pub fn empty(position: glm::Vec3) -> SceneNode {
+        SceneNode {
+            vao: None,
+            index_count: 0,
+            shader_program: 0,
+            terrain: None,
+            emission: None,
+            atmosphere: None,
+            clouds: None,
+            rings: None,
+            position,
+            reference_point: glm::vec3(0.0, 0.0, 0.0),
+            orientation: glm::quat_identity(),
+            scale: glm::vec3(1.0, 1.0, 1.0),
+            parent: None,
+            children: Vec::new(),
+            world_matrix: glm::Mat4::identity(),
+            dirty: true,
+        }
+    }
+
+    pub fn position(&self) -> glm::Vec3 {
+        self.position
+    }
+
+    pub fn orientation(&self) -> glm::Quat {
+        self.orientation
+    }
+
+    pub fn scale(&self) -> glm::Vec3 {
+        self.scale
+    }
+
+    pub fn reference_point(&self) -> glm::Vec3 {
+        self.reference_point
+    }
+
+    pub fn set_position(&mut self, position: glm::Vec3) {
+        self.position = position;
+        self.dirty = true;
+    }
+
+    pub fn set_orientation(&mut self, orientation: glm::Quat) {
+        self.orientation = glm::quat_normalize(&orientation);
+        self.dirty = true;
+    }
+
+    // Turns the node by `angle` radians around `axis`, given in parent space
+    pub fn rotate(&mut self, angle: f32, axis: &glm::Vec3) {
+        self.set_orientation(glm::quat_angle_axis(angle, axis) * self.orientation);
+    }
+
+    pub fn set_scale(&mut self, scale: glm::Vec3) {
+        self.scale = scale;
+        self.dirty = true;
+    }
+
+    pub fn set_reference_point(&mut self, reference_point: glm::Vec3) {
+        self.reference_point = reference_point;
+        self.dirty = true;
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    // Model matrix as of the last `SceneGraph::update_world_matrices`
+    pub fn world_matrix(&self) -> &glm::Mat4 {
+        &self.world_matrix
+    }
+
+    pub fn world_position(&self) -> glm::Vec3 {
+        (self.world_matrix * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz()
+    }
+
+    // Transform relative to the parent node
+    pub fn local_matrix(&self) -> glm::Mat4 {
+        let mut model_matrix = glm::Mat4::identity();
+        model_matrix = glm::translation(&-self.reference_point) * model_matrix;
+
+        model_matrix = glm::quat_to_mat4(&self.orientation) * model_matrix;
+
+        model_matrix = glm::scale(&model_matrix, &self.scale);
+
+        model_matrix = glm::translation(&self.reference_point) * model_matrix;
+        glm::translation(&self.position) * model_matrix
+    }
+}
+
+// Nodes live in slots indexed by `NodeId`, and refer to each other by id
+#[derive(Default)]
+pub struct SceneGraph {
+    nodes: Vec<Option<SceneNode>>,
+}
+
+impl SceneGraph {
+    pub fn new() -> SceneGraph {
+        SceneGraph { nodes: Vec::new() }
+    }
+
+    pub fn add(&mut self, mut node: SceneNode, parent: Option<NodeId>) -> NodeId {
+        let id = match self.nodes.iter().position(|slot| slot.is_none()) {
+            Some(id) => id,
+            None => {
+                self.nodes.push(None);
+                self.nodes.len() - 1
+            }
+        };
+        node.parent = parent;
+        node.dirty = true;
+        if let Some(parent) = parent {
+            self[parent].children.push(id);
+        }
+        self.nodes[id] = Some(node);
+        id
+    }
+
+    // Removes the node along with everything attached to it
+    pub fn remove(&mut self, id: NodeId) {
+        if let Some(parent) = self[id].parent {
+            self[parent].children.retain(|&child| child != id);
+        }
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            if let Some(node) = self.nodes[id].take() {
+                pending.extend(node.children);
+            }
+        }
+    }
+
+    // Swaps in the geometry of `node`, keeping the transform and place in the tree
+    pub fn replace_geometry(&mut self, id: NodeId, node: SceneNode) {
+        let target = &mut self[id];
+        target.vao = node.vao;
+        target.index_count = node.index_count;
+        target.shader_program = node.shader_program;
+        target.terrain = node.terrain;
+        target.emission = node.emission;
+        target.atmosphere = node.atmosphere;
+        target.clouds = node.clouds;
+        target.rings = node.rings;
+    }
+
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) {
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            assert_ne!(a, id, "A scene node can not be its own ancestor");
+            ancestor = self[a].parent;
+        }
+
+        if let Some(old_parent) = self[id].parent {
+            self[old_parent].children.retain(|&child| child != id);
+        }
+        if let Some(parent) = parent {
+            self[parent].children.push(id);
+        }
+        self[id].parent = parent;
+        self[id].dirty = true;
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        matches!(self.nodes.get(id), Some(Some(_)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &SceneNode)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(id, slot)| slot.as_ref().map(|node| (id, node)))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (NodeId, &mut SceneNode)> {
+        self.nodes
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(id, slot)| slot.as_mut().map(|node| (id, node)))
+    }
+
+    // Composes world matrices down the tree, skipping subtrees that did not change
+    pub fn update_world_matrices(&mut self) {
+        let roots: Vec<NodeId> = self
+            .iter()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(id, _)| id)
+            .collect();
+
+        let mut pending: Vec<(NodeId, glm::Mat4, bool)> = roots
+            .into_iter()
+            .map(|id| (id, glm::Mat4::identity(), false))
+            .collect();
+
+        while let Some((id, parent_matrix, parent_changed)) = pending.pop() {
+            let node = &mut self[id];
+            let changed = node.dirty || parent_changed;
+            if changed {
+                node.world_matrix = parent_matrix * node.local_matrix();
+                node.dirty = false;
+            }
+            let world_matrix = node.world_matrix;
+            for &child in &node.children {
+                pending.push((child, world_matrix, changed));
+            }
+        }
+    }
+}
+
+impl Index<NodeId> for SceneGraph {
+    type Output = SceneNode;
+
+    fn index(&self, id: NodeId) -> &SceneNode {
+        self.nodes[id].as_ref().expect("Scene node was removed")
+    }
+}
+
+impl IndexMut<NodeId> for SceneGraph {
+    fn index_mut(&mut self, id: NodeId) -> &mut SceneNode {
+        self.nodes[id].as_mut().expect("Scene node was removed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: glm::Vec3, b: glm::Vec3) {
+        assert!(glm::distance(&a, &b) < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn children_follow_their_parents() {
+        let mut graph = SceneGraph::new();
+        let star = graph.add(SceneNode::empty(glm::vec3(0.0, 0.0, 0.0)), None);
+        let planet = graph.add(SceneNode::empty(glm::vec3(10.0, 0.0, 0.0)), Some(star));
+        let moon = graph.add(SceneNode::empty(glm::vec3(2.0, 0.0, 0.0)), Some(planet));
+
+        graph.update_world_matrices();
+        assert_close(graph[moon].world_position(), glm::vec3(12.0, 0.0, 0.0));
+
+        // A quarter turn of the star carries the whole system around it
+        graph[star].rotate(glm::half_pi(), &glm::vec3(0.0, 1.0, 0.0));
+        graph.update_world_matrices();
+        assert_close(graph[planet].world_position(), glm::vec3(0.0, 0.0, -10.0));
+        assert_close(graph[moon].world_position(), glm::vec3(0.0, 0.0, -12.0));
+    }
+
+    #[test]
+    fn removing_a_node_removes_its_subtree() {
+        let mut graph = SceneGraph::new();
+        let star = graph.add(SceneNode::empty(glm::vec3(0.0, 0.0, 0.0)), None);
+        let planet = graph.add(SceneNode::empty(glm::vec3(10.0, 0.0, 0.0)), Some(star));
+        let moon = graph.add(SceneNode::empty(glm::vec3(2.0, 0.0, 0.0)), Some(planet));
+
+        graph.remove(planet);
+        assert!(graph.contains(star));
+        assert!(!graph.contains(planet));
+        assert!(!graph.contains(moon));
+        assert!(graph[star].children().is_empty());
+
+        // Freed slots are reused
+        assert_eq!(
+            graph.add(SceneNode::empty(glm::vec3(0.0, 0.0, 0.0)), None),
+            moon.min(planet)
+        );
+    }
+
+    #[test]
+    fn reparenting_keeps_world_matrices_in_sync() {
+        let mut graph = SceneGraph::new();
+        let a = graph.add(SceneNode::empty(glm::vec3(1.0, 0.0, 0.0)), None);
+        let b = graph.add(SceneNode::empty(glm::vec3(0.0, 5.0, 0.0)), None);
+        let child = graph.add(SceneNode::empty(glm::vec3(0.0, 0.0, 1.0)), Some(a));
+
+        graph.update_world_matrices();
+        graph.set_parent(child, Some(b));
+        graph.update_world_matrices();
 
-    pub position: glm::Vec3,
-    pub rotation: glm::Vec3,
-    pub scale: glm::Vec3,
-    pub reference_point: glm::Vec3,
+        assert_close(graph[child].world_position(), glm::vec3(0.0, 5.0, 1.0));
+        assert!(graph[a].children().is_empty());
+    }
 }
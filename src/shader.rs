@@ -1,16 +1,23 @@
 use gl;
 use std::{ffi::CString, path::Path, ptr, str};
 
+use crate::gpu::Program;
+
 pub struct Shader {
-    pub program_id: u32,
+    pub program: Program,
+    // Kept to build the program again with other defines
+    sources: Vec<(String, ShaderType)>,
 }
 
 pub struct ShaderBuilder {
-    program_id: u32,
-    shaders: Vec<u32>,
+    program: Program,
+    sources: Vec<(String, ShaderType)>,
+    defines: Vec<String>,
+    includes: Vec<String>,
 }
 
 #[allow(dead_code)]
+#[derive(Clone, Copy)]
 pub enum ShaderType {
     Vertex,
     Fragment,
@@ -20,20 +27,44 @@ pub enum ShaderType {
 }
 
 impl Shader {
+    pub fn program_id(&self) -> u32 {
+        self.program.id()
+    }
+
     // Make sure the shader is active before calling this
     pub unsafe fn get_uniform_location(&self, name: &str) -> i32 {
         let name_cstr = CString::new(name).expect("CString::new failed");
-        gl::GetUniformLocation(self.program_id, name_cstr.as_ptr())
+        gl::GetUniformLocation(self.program.id(), name_cstr.as_ptr())
     }
 
     pub unsafe fn activate(&self) {
-        gl::UseProgram(self.program_id);
+        self.program.activate();
+    }
+
+    // Compiles the same sources again with `defines` and links them into this
+    // program, so whatever holds its id picks up the change
+    pub unsafe fn rebuild(&mut self, defines: &[&str]) {
+        let mut attached = [0u32; 8];
+        let mut count = 0;
+        gl::GetAttachedShaders(
+            self.program.id(),
+            attached.len() as i32,
+            &mut count,
+            attached.as_mut_ptr(),
+        );
+        // They were flagged for deletion when first linked, so this frees them
+        for &shader in &attached[..count as usize] {
+            gl::DetachShader(self.program.id(), shader);
+        }
+
+        let defines: Vec<String> = defines.iter().map(|define| define.to_string()).collect();
+        link_program(self.program.id(), &self.sources, &defines);
     }
 }
 
-impl Into<gl::types::GLenum> for ShaderType {
-    fn into(self) -> gl::types::GLenum {
-        match self {
+impl From<ShaderType> for gl::types::GLenum {
+    fn from(shader_type: ShaderType) -> gl::types::GLenum {
+        match shader_type {
             ShaderType::Vertex => gl::VERTEX_SHADER,
             ShaderType::Fragment => gl::FRAGMENT_SHADER,
             ShaderType::TessellationControl => gl::TESS_CONTROL_SHADER,
@@ -59,19 +90,19 @@ impl ShaderType {
 impl ShaderBuilder {
     pub unsafe fn new() -> ShaderBuilder {
         ShaderBuilder {
-            program_id: gl::CreateProgram(),
-            shaders: vec![],
+            program: Program::new(),
+            sources: vec![],
+            defines: vec![],
+            includes: vec![],
         }
     }
 
-    pub unsafe fn attach_file(self, shader_path: &str) -> ShaderBuilder {
+    pub fn attach_file(self, shader_path: &str) -> ShaderBuilder {
         let path = Path::new(shader_path);
         if let Some(extension) = path.extension() {
             let shader_type =
                 ShaderType::from_ext(extension).expect("Failed to parse file extension.");
-            let shader_src = std::fs::read_to_string(path)
-                .expect(&format!("Failed to read shader source. {}", shader_path));
-            self.compile_shader(&shader_src, shader_type)
+            self.attach_file_as(shader_path, shader_type)
         } else {
             panic!(
                 "Failed to read extension of file with path: {}",
@@ -80,82 +111,184 @@ impl ShaderBuilder {
         }
     }
 
-    pub unsafe fn compile_shader(
-        mut self,
-        shader_src: &str,
-        shader_type: ShaderType,
-    ) -> ShaderBuilder {
-        let shader = gl::CreateShader(shader_type.into());
-        let c_str_shader = CString::new(shader_src.as_bytes()).unwrap();
-        gl::ShaderSource(shader, 1, &c_str_shader.as_ptr(), ptr::null());
-        gl::CompileShader(shader);
+    // For sources whose extension does not say the stage, like functions shared
+    // by several stages that are compiled once for each
+    pub fn attach_file_as(self, shader_path: &str, shader_type: ShaderType) -> ShaderBuilder {
+        let shader_src = std::fs::read_to_string(shader_path)
+            .unwrap_or_else(|_| panic!("Failed to read shader source. {}", shader_path));
+        self.attach_source(&shader_src, shader_type)
+    }
 
-        if !self.check_shader_errors(shader) {
-            panic!("Shader failed to compile.");
-        }
-        self.shaders.push(shader);
+    // Stages are compiled when the program is linked, with every define given
+    // by then
+    pub fn attach_source(mut self, shader_src: &str, shader_type: ShaderType) -> ShaderBuilder {
+        self.sources.push((shader_src.to_string(), shader_type));
+        self
+    }
 
+    // Defines `name` in every stage, for sources that #ifdef on it
+    pub fn define(mut self, name: &str) -> ShaderBuilder {
+        self.defines.push(name.to_string());
         self
     }
 
-    unsafe fn check_shader_errors(&self, shader_id: u32) -> bool {
-        let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
-        gl::GetShaderiv(shader_id, gl::COMPILE_STATUS, &mut success);
-        if success != i32::from(gl::TRUE) {
-            gl::GetShaderInfoLog(
-                shader_id,
-                512,
-                ptr::null_mut(),
-                info_log.as_mut_ptr() as *mut gl::types::GLchar,
-            );
-            println!(
-                "ERROR::Shader Compilation Failed!\n{}",
-                String::from_utf8_lossy(&info_log)
-            );
-            return false;
-        }
-        true
-    }
-
-    unsafe fn check_linker_errors(&self) -> bool {
-        let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
-        gl::GetProgramiv(self.program_id, gl::LINK_STATUS, &mut success);
-        if success != i32::from(gl::TRUE) {
-            gl::GetProgramInfoLog(
-                self.program_id,
-                512,
-                ptr::null_mut(),
-                info_log.as_mut_ptr() as *mut gl::types::GLchar,
-            );
-            println!(
-                "ERROR::SHADER::PROGRAM::COMPILATION_FAILED\n{}",
-                String::from_utf8_lossy(&info_log)
-            );
-            return false;
-        }
-        true
+    // Puts the declarations in the file at `path` in front of every stage, for
+    // structs and blocks that several shaders have to agree on
+    pub fn include(mut self, path: &str) -> ShaderBuilder {
+        let source = std::fs::read_to_string(path)
+            .unwrap_or_else(|_| panic!("Failed to read shader source. {}", path));
+        self.includes.push(source);
+        self
     }
 
     #[must_use = "The shader program is useless if not stored in a variable."]
     pub unsafe fn link(self) -> Shader {
-        for &shader in &self.shaders {
-            gl::AttachShader(self.program_id, shader);
+        // Included once and for all, so a rebuild only adds its defines on top
+        let sources: Vec<(String, ShaderType)> = self
+            .sources
+            .iter()
+            .map(|(source, shader_type)| {
+                (after_version(source, &self.includes.concat()), *shader_type)
+            })
+            .collect();
+        link_program(self.program.id(), &sources, &self.defines);
+        Shader {
+            program: self.program,
+            sources,
         }
-        gl::LinkProgram(self.program_id);
+    }
+}
 
-        // todo:: use this to make safer abstraction
-        self.check_linker_errors();
+// Puts the defines right after the #version line, ahead of any includes so
+// those can #ifdef on them too
+fn with_defines(source: &str, defines: &[String]) -> String {
+    let lines: String = defines
+        .iter()
+        .map(|define| format!("#define {}\n", define))
+        .collect();
+    after_version(source, &lines)
+}
 
-        for &shader in &self.shaders {
-            gl::DeleteShader(shader);
-        }
+// Inserts `text` right after the #version line, which has to come first
+fn after_version(source: &str, text: &str) -> String {
+    if text.is_empty() {
+        return source.to_string();
+    }
+    let (version, rest) = match source.strip_prefix("#version") {
+        Some(_) => source.split_once('\n').unwrap_or((source, "")),
+        None => ("", source),
+    };
+    let mut out = String::from(version);
+    if !version.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(text);
+    out.push_str(rest);
+    out
+}
 
-        Shader {
-            program_id: self.program_id,
-        }
+unsafe fn compile(source: &str, shader_type: ShaderType) -> u32 {
+    let shader = gl::CreateShader(shader_type.into());
+    let c_str_shader = CString::new(source.as_bytes()).unwrap();
+    gl::ShaderSource(shader, 1, &c_str_shader.as_ptr(), ptr::null());
+    gl::CompileShader(shader);
+
+    if !check_shader_errors(shader) {
+        panic!("Shader failed to compile.");
+    }
+    shader
+}
+
+unsafe fn link_program(program: u32, sources: &[(String, ShaderType)], defines: &[String]) {
+    let shaders: Vec<u32> = sources
+        .iter()
+        .map(|(source, shader_type)| compile(&with_defines(source, defines), *shader_type))
+        .collect();
+    for &shader in &shaders {
+        gl::AttachShader(program, shader);
+    }
+    gl::LinkProgram(program);
+
+    // todo:: use this to make safer abstraction
+    check_linker_errors(program);
+
+    for &shader in &shaders {
+        gl::DeleteShader(shader);
+    }
+}
+
+unsafe fn check_shader_errors(shader_id: u32) -> bool {
+    let mut success = i32::from(gl::FALSE);
+    let mut info_log = vec![0u8; 512 - 1];
+    gl::GetShaderiv(shader_id, gl::COMPILE_STATUS, &mut success);
+    if success != i32::from(gl::TRUE) {
+        gl::GetShaderInfoLog(
+            shader_id,
+            512,
+            ptr::null_mut(),
+            info_log.as_mut_ptr() as *mut gl::types::GLchar,
+        );
+        println!(
+            "ERROR::Shader Compilation Failed!\n{}",
+            String::from_utf8_lossy(&info_log)
+        );
+        return false;
+    }
+    true
+}
+
+unsafe fn check_linker_errors(program: u32) -> bool {
+    let mut success = i32::from(gl::FALSE);
+    let mut info_log = vec![0u8; 512 - 1];
+    gl::GetProgramiv(program, gl::LINK_STATUS, &mut success);
+    if success != i32::from(gl::TRUE) {
+        gl::GetProgramInfoLog(
+            program,
+            512,
+            ptr::null_mut(),
+            info_log.as_mut_ptr() as *mut gl::types::GLchar,
+        );
+        println!(
+            "ERROR::SHADER::PROGRAM::COMPILATION_FAILED\n{}",
+            String::from_utf8_lossy(&info_log)
+        );
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defines_go_after_the_version() {
+        let defines = vec![String::from("LOG_DEPTH")];
+        assert_eq!(
+            with_defines("#version 450 core\nvoid main() {}\n", &defines),
+            "#version 450 core\n#define LOG_DEPTH\nvoid main() {}\n"
+        );
+        assert_eq!(
+            with_defines("void main() {}", &defines),
+            "#define LOG_DEPTH\nvoid main() {}"
+        );
+        assert_eq!(with_defines("void main() {}", &[]), "void main() {}");
+    }
+
+    #[test]
+    fn includes_go_between_the_version_and_the_source() {
+        let included = after_version(
+            "#version 450 core\nvoid main() {}\n",
+            "struct Light { vec3 position; };\n",
+        );
+        assert_eq!(
+            included,
+            "#version 450 core\nstruct Light { vec3 position; };\nvoid main() {}\n"
+        );
+        // Defines added when rebuilding still come first
+        assert_eq!(
+            with_defines(&included, &[String::from("LOG_DEPTH")]),
+            "#version 450 core\n#define LOG_DEPTH\nstruct Light { vec3 position; };\nvoid main() {}\n"
+        );
     }
 }
@@ -0,0 +1,154 @@
+use crate::gpu::{Framebuffer, Texture};
+use crate::planet::Planet;
+
+// Must match MAX_SHADOW_LIGHTS in shape.frag
+pub const MAX_SHADOW_LIGHTS: usize = 4;
+
+// Tunables for the shadows, edited from the UI
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowSettings {
+    pub enabled: bool,
+    // Distance from a light beyond which nothing is shadowed
+    pub range: f32,
+    // Depth offset, as a fraction of `range`, that keeps surfaces from
+    // shadowing themselves
+    pub bias: f32,
+    // Angle in radians the filter spreads its samples over, for soft edges
+    pub softness: f32,
+}
+
+impl ShadowSettings {
+    pub fn new() -> ShadowSettings {
+        ShadowSettings {
+            enabled: true,
+            range: 100.0,
+            bias: 0.0005,
+            softness: 0.004,
+        }
+    }
+
+    // Stretches the range across every body in `planets` wherever they orbit,
+    // so a light anywhere among them shadows all of them
+    pub fn fit_to(&mut self, planets: &[Planet]) {
+        // Furthest each body gets from the origin, moons adding their orbit
+        // to their parent's
+        let mut reach: Vec<f64> = Vec::with_capacity(planets.len());
+        for planet in planets {
+            let parent = planet.parent.and_then(|parent| reach.get(parent));
+            reach.push(parent.copied().unwrap_or(0.0) + glm::length(&planet.position));
+        }
+        let extent = planets
+            .iter()
+            .zip(&reach)
+            .map(|(planet, reach)| reach + planet.radius)
+            .fold(0.0, f64::max);
+        self.range = (2.0 * extent).max(1.0) as f32;
+    }
+}
+
+impl Default for ShadowSettings {
+    fn default() -> ShadowSettings {
+        ShadowSettings::new()
+    }
+}
+
+// Distance to the nearest occluder around each shadow casting light, one cube
+// per light in a cube map array, stored as a fraction of the shadow range
+pub struct ShadowMaps {
+    framebuffer: Framebuffer,
+}
+
+impl ShadowMaps {
+    pub unsafe fn new(resolution: u32) -> ShadowMaps {
+        let depth =
+            Texture::new_cube_array(gl::DEPTH_COMPONENT32F, resolution, MAX_SHADOW_LIGHTS as u32);
+        ShadowMaps {
+            framebuffer: Framebuffer::layered_depth(depth, resolution),
+        }
+    }
+
+    // Draws into `face` of the cube for light `index` from now on, cleared
+    pub unsafe fn bind_face(&self, index: usize, face: usize) {
+        self.framebuffer
+            .attach_depth_layer((index * 6 + face) as u32);
+        self.framebuffer.bind();
+        gl::Clear(gl::DEPTH_BUFFER_BIT);
+    }
+
+    pub unsafe fn bind_texture(&self, unit: u32) {
+        if let Some(depth) = self.framebuffer.depth() {
+            depth.bind(unit);
+        }
+    }
+
+    // View projections of the six cube faces around `light_position`, in the
+    // order and orientation GL expects cube map layers in
+    pub fn face_matrices(light_position: &glm::Vec3, range: f32) -> [glm::Mat4; 6] {
+        let faces = [
+            (glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, -1.0, 0.0)),
+            (glm::vec3(-1.0, 0.0, 0.0), glm::vec3(0.0, -1.0, 0.0)),
+            (glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, 0.0, 1.0)),
+            (glm::vec3(0.0, -1.0, 0.0), glm::vec3(0.0, 0.0, -1.0)),
+            (glm::vec3(0.0, 0.0, 1.0), glm::vec3(0.0, -1.0, 0.0)),
+            (glm::vec3(0.0, 0.0, -1.0), glm::vec3(0.0, -1.0, 0.0)),
+        ];
+        // Close enough to the light to catch anything outside a star's surface
+        let projection = glm::perspective(1.0, glm::half_pi(), range * 1e-4, range);
+        faces.map(|(direction, up)| {
+            projection * glm::look_at(light_position, &(light_position + direction), &up)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::material::Material;
+
+    use super::*;
+
+    #[test]
+    fn range_reaches_across_the_scene() {
+        let material = Material::new();
+        let zero = glm::DVec3::zeros();
+        let sun = Planet::new(1.0, zero, zero, 10.0, material, 1);
+        let mut planet = Planet::new(
+            1.0,
+            glm::DVec3::new(1000.0, 0.0, 0.0),
+            zero,
+            5.0,
+            material,
+            1,
+        );
+        planet.parent = Some(0);
+        let mut moon = Planet::new(1.0, glm::DVec3::new(0.0, 0.0, 20.0), zero, 1.0, material, 1);
+        moon.parent = Some(1);
+
+        let mut settings = ShadowSettings::new();
+        settings.fit_to(&[sun, planet, moon]);
+        assert_eq!(settings.range, 2.0 * 1021.0);
+    }
+
+    #[test]
+    fn each_face_looks_along_its_axis() {
+        let light = glm::vec3(1.0, 2.0, 3.0);
+        let matrices = ShadowMaps::face_matrices(&light, 50.0);
+        let axes = [
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(-1.0, 0.0, 0.0),
+            glm::vec3(0.0, 1.0, 0.0),
+            glm::vec3(0.0, -1.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec3(0.0, 0.0, -1.0),
+        ];
+        for (face, matrix) in matrices.iter().enumerate() {
+            // A point straight out along the face's axis lands in the middle
+            // of that face, and of no other
+            for (i, axis) in axes.iter().enumerate() {
+                let clip = matrix * (light + axis * 10.0).push(1.0);
+                let ndc = clip.xyz() / clip.w;
+                let centred = clip.w > 0.0 && ndc.x.abs() < 1e-4 && ndc.y.abs() < 1e-4;
+                assert_eq!(centred, i == face, "face {} axis {}", face, i);
+            }
+        }
+    }
+}
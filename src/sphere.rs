@@ -1,68 +1,72 @@
+use crate::biome::Biome;
 use crate::material::Material;
 use crate::mesh::{Mesh, MeshMaterial};
+use crate::noise::Noise;
 use crate::vertex::{Triangle, Vertex};
 use lerp::Lerp;
 
+#[derive(Clone)]
 pub struct Sphere {
     vertices: Vec<Vertex>,
     displaced_vertices: Vec<Vertex>,
     triangles: Vec<Triangle>,
     pub mesh: Mesh,
     pub mesh_material: Material,
+    detail: u32,
 }
 
 impl Sphere {
     pub fn new(detail: u32, material: Material) -> Sphere {
-        let phi = (1.0 + (5.0 as f32).sqrt()) / 2.0;
+        let phi = (1.0 + 5.0_f32.sqrt()) / 2.0;
         let mut regular_isocahedron = Sphere {
             vertices: vec![
                 Vertex {
                     position: glm::vec3(-1.0, phi, 0.0),
-                    material: material,
+                    material,
                 },
                 Vertex {
                     position: glm::vec3(1.0, phi, 0.0),
-                    material: material,
+                    material,
                 },
                 Vertex {
                     position: glm::vec3(-1.0, -phi, 0.0),
-                    material: material,
+                    material,
                 },
                 Vertex {
                     position: glm::vec3(1.0, -phi, 0.0),
-                    material: material,
+                    material,
                 },
                 Vertex {
                     position: glm::vec3(0.0, -1.0, phi),
-                    material: material,
+                    material,
                 },
                 Vertex {
                     position: glm::vec3(0.0, 1.0, phi),
-                    material: material,
+                    material,
                 },
                 Vertex {
                     position: glm::vec3(0.0, -1.0, -phi),
-                    material: material,
+                    material,
                 },
                 Vertex {
                     position: glm::vec3(0.0, 1.0, -phi),
-                    material: material,
+                    material,
                 },
                 Vertex {
                     position: glm::vec3(phi, 0.0, -1.0),
-                    material: material,
+                    material,
                 },
                 Vertex {
                     position: glm::vec3(phi, 0.0, 1.0),
-                    material: material,
+                    material,
                 },
                 Vertex {
                     position: glm::vec3(-phi, 0.0, -1.0),
-                    material: material,
+                    material,
                 },
                 Vertex {
                     position: glm::vec3(-phi, 0.0, 1.0),
-                    material: material,
+                    material,
                 },
             ],
             triangles: vec![
@@ -96,11 +100,16 @@ impl Sphere {
                     diffuse: Vec::new(),
                     specular: Vec::new(),
                     shininess: Vec::new(),
+                    roughness: Vec::new(),
+                    metallic: Vec::new(),
+                    shading: Vec::new(),
                 },
 
                 index_count: 0,
+                dirty: None,
             },
             mesh_material: material,
+            detail,
             displaced_vertices: Vec::new(),
         };
 
@@ -113,6 +122,21 @@ impl Sphere {
         regular_isocahedron
     }
 
+    pub fn detail(&self) -> u32 {
+        self.detail
+    }
+
+    // Recolours every vertex in place, keeping the shape (and any craters)
+    pub fn set_material(&mut self, material: Material) {
+        self.mesh_material = material;
+        for vertex in self.vertices.iter_mut().chain(&mut self.displaced_vertices) {
+            vertex.material = material;
+        }
+        self.mesh.material = self.get_mesh_material();
+        let vertex_count = self.vertices.len();
+        self.mesh.mark_dirty(0..vertex_count);
+    }
+
     fn add_vertex(&mut self, vertex: Vertex) -> usize {
         self.vertices.push(vertex);
         self.vertices.len() - 1
@@ -120,60 +144,124 @@ impl Sphere {
 
     fn generate_mesh(&mut self) {
         self.mesh = Mesh {
-            vertices: self.flatten_vertices(),
+            vertices: Sphere::flatten_vertices(&self.displaced_vertices),
             indices: self.flatten_cells(),
-            normals: self.get_vertex_normals(),
+            normals: self.get_vertex_normals(&self.displaced_vertices),
             material: self.get_mesh_material(),
 
             index_count: (self.triangles.len() * 3) as i32,
+            dirty: None,
         };
     }
 
-    fn get_mesh_material(&self) -> MeshMaterial {
-        MeshMaterial {
-            ambient: self.flatten_ambient(),
-            diffuse: self.flatten_diffuse(),
-            specular: self.flatten_specular(),
-            shininess: self.flatten_shininess(),
+    // Pushes the surface in around `center` (a direction from the middle of the
+    // sphere) and marks the changed vertices dirty on the mesh. `radius` is the
+    // angular radius of the crater in radians.
+    pub fn add_crater(&mut self, center: glm::Vec3, radius: f32, depth: f32) {
+        let center = glm::normalize(&center);
+        let mut displaced = vec![false; self.vertices.len()];
+
+        for (i, vertex) in self.vertices.iter().enumerate() {
+            let distance = glm::angle(&vertex.position, &center);
+            if distance >= radius {
+                continue;
+            }
+            let falloff = 1.0 - (distance / radius).powi(2);
+            self.displaced_vertices[i].position *= 1.0 - depth * falloff;
+            displaced[i] = true;
         }
-    }
 
-    fn flatten_vertices(&self) -> Vec<f32> {
-        let mut vec = Vec::new();
-        for vertex in &self.displaced_vertices {
-            vec.extend(&vertex.position);
+        // Normals change on every vertex sharing a triangle with a displaced
+        // one, which reaches past the rim of the crater
+        let mut touched: Option<std::ops::Range<usize>> = None;
+        for cell in &self.triangles {
+            if !(displaced[cell.a] || displaced[cell.b] || displaced[cell.c]) {
+                continue;
+            }
+            let low = cell.a.min(cell.b).min(cell.c);
+            let high = cell.a.max(cell.b).max(cell.c) + 1;
+            touched = Some(match touched {
+                Some(range) => range.start.min(low)..range.end.max(high),
+                None => low..high,
+            });
         }
-        vec
-    }
 
-    fn flatten_ambient(&self) -> Vec<f32> {
-        let mut vec = Vec::new();
-        for vertex in &self.vertices {
-            vec.extend(&vertex.material.ambient);
+        if let Some(range) = touched {
+            let normals = self.get_vertex_normals(&self.displaced_vertices);
+            let floats = range.start * 3..range.end * 3;
+            self.mesh.vertices[floats.clone()].copy_from_slice(
+                &Sphere::flatten_vertices(&self.displaced_vertices)[floats.clone()],
+            );
+            self.mesh.normals[floats.clone()].copy_from_slice(&normals[floats]);
+            self.mesh.mark_dirty(range);
         }
-        vec
     }
 
-    fn flatten_diffuse(&self) -> Vec<f32> {
-        let mut vec = Vec::new();
-        for vertex in &self.vertices {
-            vec.extend(&vertex.material.diffuse);
+    // Bakes the noise displacement and biome colours and roughness done in shape.vert into a
+    // standalone mesh, used when the terrain has to leave the GPU (e.g. for exporting).
+    // Starts from the vertices as uploaded, craters and all.
+    pub fn displaced_mesh(&self, noise: &Noise, biome: &Biome) -> Mesh {
+        let displaced: Vec<Vertex> = self
+            .displaced_vertices
+            .iter()
+            .map(|vertex| {
+                let mut material = vertex.material;
+                let elevation = noise.elevation(&vertex.position);
+                if let Some(color) = biome.color_at(elevation) {
+                    material.ambient = color;
+                    material.diffuse = color;
+                }
+                if let Some(roughness) = biome.roughness_at(elevation) {
+                    material.roughness = roughness;
+                }
+                Vertex {
+                    position: vertex.position * (noise.evaluate(&vertex.position) + 1.0),
+                    material,
+                }
+            })
+            .collect();
+
+        Mesh {
+            vertices: Sphere::flatten_vertices(&displaced),
+            indices: self.flatten_cells(),
+            normals: self.get_vertex_normals(&displaced),
+            material: Sphere::flatten_materials(&displaced),
+
+            index_count: (self.triangles.len() * 3) as i32,
+            dirty: None,
         }
-        vec
     }
 
-    fn flatten_specular(&self) -> Vec<f32> {
-        let mut vec = Vec::new();
-        for vertex in &self.vertices {
-            vec.extend(&vertex.material.specular);
+    fn get_mesh_material(&self) -> MeshMaterial {
+        Sphere::flatten_materials(&self.vertices)
+    }
+
+    fn flatten_materials(vertices: &[Vertex]) -> MeshMaterial {
+        let mut material = MeshMaterial {
+            ambient: Vec::new(),
+            diffuse: Vec::new(),
+            specular: Vec::new(),
+            shininess: Vec::new(),
+            roughness: Vec::new(),
+            metallic: Vec::new(),
+            shading: Vec::new(),
+        };
+        for vertex in vertices {
+            material.ambient.extend(&vertex.material.ambient);
+            material.diffuse.extend(&vertex.material.diffuse);
+            material.specular.extend(&vertex.material.specular);
+            material.shininess.push(vertex.material.shininess);
+            material.roughness.push(vertex.material.roughness);
+            material.metallic.push(vertex.material.metallic);
+            material.shading.push(vertex.material.shading.attribute());
         }
-        vec
+        material
     }
 
-    fn flatten_shininess(&self) -> Vec<f32> {
+    fn flatten_vertices(vertices: &[Vertex]) -> Vec<f32> {
         let mut vec = Vec::new();
-        for vertex in &self.vertices {
-            vec.push(vertex.material.shininess);
+        for vertex in vertices {
+            vec.extend(&vertex.position);
         }
         vec
     }
@@ -186,15 +274,14 @@ impl Sphere {
         vec
     }
 
-    fn get_vertex_normals(&mut self) -> Vec<f32> {
+    fn get_vertex_normals(&self, vertices: &[Vertex]) -> Vec<f32> {
         let mut vec: Vec<f32> = Vec::new();
-        let mut vertex_normals: Vec<glm::Vec3> =
-            vec![glm::vec3(0.0, 0.0, 0.0); self.vertices.len()];
+        let mut vertex_normals: Vec<glm::Vec3> = vec![glm::vec3(0.0, 0.0, 0.0); vertices.len()];
 
         for cell in &self.triangles {
-            let a = self.vertices[cell.a].position;
-            let b = self.vertices[cell.b].position;
-            let c = self.vertices[cell.c].position;
+            let a = vertices[cell.a].position;
+            let b = vertices[cell.b].position;
+            let c = vertices[cell.c].position;
 
             let ab = b - a;
             let ac = c - a;
@@ -230,15 +317,15 @@ impl Sphere {
 
         for i in 0..=cols {
             new_vertices.push(vec![]);
-            let aj = a.clone().lerp(c, i as f32 / cols as f32);
-            let bj = b.clone().lerp(c, i as f32 / cols as f32);
+            let aj = a.lerp(c, i as f32 / cols as f32);
+            let bj = b.lerp(c, i as f32 / cols as f32);
             let rows = cols - i;
 
             for j in 0..=rows {
                 if j == 0 && i == cols {
                     new_vertices[i].push(aj.normalize());
                 } else {
-                    new_vertices[i].push(aj.clone().lerp(bj, j as f32 / rows as f32).normalize());
+                    new_vertices[i].push(aj.lerp(bj, j as f32 / rows as f32).normalize());
                 }
             }
         }
@@ -291,7 +378,75 @@ impl Sphere {
         self.vertices = new_sphere.vertices;
         self.triangles = new_sphere.triangles;
         self.mesh_material = new_sphere.mesh_material;
+        self.detail = new_sphere.detail;
         self.displaced_vertices = new_sphere.displaced_vertices;
         self.mesh = new_sphere.mesh;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crater_only_moves_and_dirties_nearby_vertices() {
+        let mut sphere = Sphere::new(3, Material::new());
+        let before = sphere.mesh.vertices.clone();
+        let center = glm::vec3(0.0, 1.0, 0.0);
+
+        sphere.add_crater(center, 0.2, 0.1);
+
+        let dirty = sphere
+            .mesh
+            .dirty
+            .clone()
+            .expect("crater should mark vertices dirty");
+        for i in 0..before.len() / 3 {
+            let old = glm::make_vec3(&before[i * 3..i * 3 + 3]);
+            let new = glm::make_vec3(&sphere.mesh.vertices[i * 3..i * 3 + 3]);
+            if glm::angle(&old, &center) < 0.2 {
+                assert!(dirty.contains(&i));
+                assert!(new.norm() < old.norm());
+            } else {
+                assert_eq!(old, new);
+            }
+        }
+    }
+
+    #[test]
+    fn crater_updates_normals_around_its_rim() {
+        let mut sphere = Sphere::new(3, Material::new());
+        sphere.add_crater(glm::vec3(0.0, 1.0, 0.0), 0.2, 0.1);
+
+        // Compared as bits, as vertices in no triangle have NaN normals
+        let bits = |normals: &[f32]| normals.iter().map(|n| n.to_bits()).collect::<Vec<_>>();
+        let expected = sphere.get_vertex_normals(&sphere.displaced_vertices);
+        assert_eq!(bits(&sphere.mesh.normals), bits(&expected));
+    }
+
+    #[test]
+    fn craters_are_exported() {
+        let mut sphere = Sphere::new(3, Material::new());
+        let center = glm::vec3(0.0, 1.0, 0.0);
+        sphere.add_crater(center, 0.2, 0.1);
+
+        let flat = Noise {
+            strength: 0.0,
+            ..Noise::new()
+        };
+        let exported = sphere.displaced_mesh(&flat, &Biome::new());
+        let moved_in = exported.vertices.chunks(3).any(|position| {
+            let position = glm::make_vec3(position);
+            glm::angle(&position, &center) < 0.2 && position.norm() < 0.95
+        });
+        assert!(moved_in);
+    }
+
+    #[test]
+    fn dirty_ranges_merge() {
+        let mut sphere = Sphere::new(1, Material::new());
+        sphere.mesh.mark_dirty(10..20);
+        sphere.mesh.mark_dirty(4..12);
+        assert_eq!(sphere.mesh.dirty, Some(4..20));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::light::Light;
+
+// Share of a star's light that reaches everything regardless of facing
+const AMBIENT_FRACTION: f32 = 0.1;
+
+// How much brighter than white a star's surface is drawn, so it blooms
+const GLOW: f32 = 4.0;
+
+// What makes a planet a star: it glows in its blackbody colour and lights the
+// scene from wherever it is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Star {
+    // Surface temperature in kelvin
+    pub temperature: f32,
+    // Brightness of the emitted light, 1 for full strength
+    pub intensity: f32,
+}
+
+impl Star {
+    // A sun-like star
+    pub fn new() -> Star {
+        Star {
+            temperature: 5778.0,
+            intensity: 1.0,
+        }
+    }
+
+    // Blackbody colour at the star's temperature, brightest channel at most 1
+    pub fn color(&self) -> glm::Vec3 {
+        blackbody_color(self.temperature)
+    }
+
+    // Colour its surface is drawn in, well past white for the bloom to catch
+    pub fn emission(&self) -> glm::Vec3 {
+        self.color() * self.intensity * GLOW
+    }
+
+    // The light this star gives off from `position`
+    pub fn light(&self, position: glm::DVec3) -> Light {
+        let color = self.color() * self.intensity;
+        Light {
+            ambient: color * AMBIENT_FRACTION,
+            diffuse: color,
+            specular: color,
+            ..Light::point(position)
+        }
+    }
+}
+
+impl Default for Star {
+    fn default() -> Star {
+        Star::new()
+    }
+}
+
+// Colour of a blackbody at `temperature` kelvin, brightest channel at most 1.
+// Tanner Helland's fit, good from 1000 K to 40000 K.
+pub fn blackbody_color(temperature: f32) -> glm::Vec3 {
+    let t = temperature.clamp(1000.0, 40000.0) / 100.0;
+    let red = if t <= 66.0 {
+        255.0
+    } else {
+        329.69873 * (t - 60.0).powf(-0.13320476)
+    };
+    let green = if t <= 66.0 {
+        99.4708 * t.ln() - 161.11957
+    } else {
+        288.12216 * (t - 60.0).powf(-0.07551485)
+    };
+    let blue = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.51773 * (t - 10.0).ln() - 305.0448
+    };
+    glm::vec3(red, green, blue).map(|c| c.clamp(0.0, 255.0) / 255.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn star(temperature: f32) -> Star {
+        Star {
+            temperature,
+            ..Star::new()
+        }
+    }
+
+    #[test]
+    fn colour_runs_from_red_to_blue() {
+        let red_dwarf = star(3000.0).color();
+        let sun = star(5778.0).color();
+        let blue_giant = star(20000.0).color();
+
+        assert!(red_dwarf.x > red_dwarf.y && red_dwarf.y > red_dwarf.z);
+        assert!(sun.x > sun.z && sun.z > 0.85);
+        assert!(blue_giant.z > blue_giant.x);
+        let white = star(6600.0).color();
+        assert!(white.iter().all(|&c| c > 0.97));
+    }
+
+    #[test]
+    fn light_follows_the_star() {
+        let mut star = star(3000.0);
+        star.intensity = 2.0;
+        let light = star.light(glm::DVec3::new(1.0, 2.0, 3.0));
+        assert_eq!(light.position, glm::DVec3::new(1.0, 2.0, 3.0));
+        assert_eq!(light.diffuse, star.color() * 2.0);
+        assert!(light.ambient.x < light.diffuse.x);
+    }
+}
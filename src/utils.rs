@@ -3,7 +3,7 @@ use std::ffi::CString;
 use std::{mem, os::raw::c_void};
 
 pub fn byte_size_of_array<T>(val: &[T]) -> isize {
-    std::mem::size_of_val(&val[..]) as isize
+    std::mem::size_of_val(val) as isize
 }
 
 // Get the OpenGL-compatible pointer to an arbitrary array of numbers
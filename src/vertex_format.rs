@@ -0,0 +1,336 @@
+use crate::gpu::Buffer;
+use crate::mesh::Mesh;
+
+// Describes one shader input and where its data lives on the mesh. Source data
+// is always f32, and is converted to `gl_type` when the buffer is interleaved.
+pub struct VertexAttribute {
+    pub name: &'static str,
+    pub location: u32,
+    pub components: i32,
+    pub gl_type: gl::types::GLenum,
+    pub normalized: bool,
+    pub source: fn(&Mesh) -> &[f32],
+}
+
+pub struct VertexFormat {
+    pub attributes: &'static [VertexAttribute],
+}
+
+// Layout expected by shape.vert and light.vert
+pub const MESH_VERTEX_FORMAT: VertexFormat = VertexFormat {
+    attributes: &[
+        VertexAttribute {
+            name: "position",
+            location: 0,
+            components: 3,
+            gl_type: gl::FLOAT,
+            normalized: false,
+            source: |mesh| &mesh.vertices,
+        },
+        VertexAttribute {
+            name: "ambient_material",
+            location: 1,
+            components: 3,
+            gl_type: gl::FLOAT,
+            normalized: false,
+            source: |mesh| &mesh.material.ambient,
+        },
+        VertexAttribute {
+            name: "diffuse_material",
+            location: 2,
+            components: 3,
+            gl_type: gl::FLOAT,
+            normalized: false,
+            source: |mesh| &mesh.material.diffuse,
+        },
+        VertexAttribute {
+            name: "specular_material",
+            location: 3,
+            components: 3,
+            gl_type: gl::FLOAT,
+            normalized: false,
+            source: |mesh| &mesh.material.specular,
+        },
+        VertexAttribute {
+            name: "shininess",
+            location: 4,
+            components: 1,
+            gl_type: gl::FLOAT,
+            normalized: false,
+            source: |mesh| &mesh.material.shininess,
+        },
+        VertexAttribute {
+            name: "normalVector",
+            location: 5,
+            components: 3,
+            gl_type: gl::FLOAT,
+            normalized: false,
+            source: |mesh| &mesh.normals,
+        },
+        VertexAttribute {
+            name: "roughness",
+            location: 6,
+            components: 1,
+            gl_type: gl::FLOAT,
+            normalized: false,
+            source: |mesh| &mesh.material.roughness,
+        },
+        VertexAttribute {
+            name: "metallic",
+            location: 7,
+            components: 1,
+            gl_type: gl::FLOAT,
+            normalized: false,
+            source: |mesh| &mesh.material.metallic,
+        },
+        VertexAttribute {
+            name: "shading",
+            location: 8,
+            components: 1,
+            gl_type: gl::FLOAT,
+            normalized: false,
+            source: |mesh| &mesh.material.shading,
+        },
+    ],
+};
+
+// Layout expected by stars.vert: only where each star is and its colour
+pub const STAR_VERTEX_FORMAT: VertexFormat = VertexFormat {
+    attributes: &[
+        VertexAttribute {
+            name: "direction",
+            location: 0,
+            components: 3,
+            gl_type: gl::FLOAT,
+            normalized: false,
+            source: |mesh| &mesh.vertices,
+        },
+        VertexAttribute {
+            name: "color",
+            location: 2,
+            components: 3,
+            gl_type: gl::FLOAT,
+            normalized: false,
+            source: |mesh| &mesh.material.diffuse,
+        },
+    ],
+};
+
+impl VertexAttribute {
+    fn component_size(&self) -> usize {
+        match self.gl_type {
+            gl::FLOAT => 4,
+            gl::UNSIGNED_BYTE | gl::BYTE => 1,
+            gl::UNSIGNED_SHORT | gl::SHORT => 2,
+            t => panic!(
+                "Unsupported vertex attribute type {:#x} for {}",
+                t, self.name
+            ),
+        }
+    }
+
+    // Attributes are padded to 4 bytes to keep every attribute aligned
+    fn size(&self) -> usize {
+        (self.component_size() * self.components as usize + 3) & !3
+    }
+
+    fn write_component(&self, value: f32, out: &mut Vec<u8>) {
+        match self.gl_type {
+            gl::FLOAT => out.extend_from_slice(&value.to_le_bytes()),
+            gl::UNSIGNED_BYTE if self.normalized => {
+                out.push((value.clamp(0.0, 1.0) * 255.0).round() as u8)
+            }
+            gl::UNSIGNED_BYTE => out.push(value as u8),
+            gl::BYTE if self.normalized => {
+                out.push(((value.clamp(-1.0, 1.0) * 127.0).round() as i8) as u8)
+            }
+            gl::BYTE => out.push(value as i8 as u8),
+            gl::UNSIGNED_SHORT if self.normalized => out.extend_from_slice(
+                &((value.clamp(0.0, 1.0) * 65535.0).round() as u16).to_le_bytes(),
+            ),
+            gl::UNSIGNED_SHORT => out.extend_from_slice(&(value as u16).to_le_bytes()),
+            gl::SHORT if self.normalized => out.extend_from_slice(
+                &((value.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes(),
+            ),
+            gl::SHORT => out.extend_from_slice(&(value as i16).to_le_bytes()),
+            t => panic!(
+                "Unsupported vertex attribute type {:#x} for {}",
+                t, self.name
+            ),
+        }
+    }
+}
+
+impl VertexFormat {
+    pub fn stride(&self) -> usize {
+        self.attributes.iter().map(|a| a.size()).sum()
+    }
+
+    pub fn offset_of(&self, location: u32) -> usize {
+        self.attributes
+            .iter()
+            .take_while(|a| a.location != location)
+            .map(|a| a.size())
+            .sum()
+    }
+
+    // Every attribute holds one value per vertex, as `interleave_range` checks,
+    // so the count can be read off whichever attribute comes first
+    pub fn vertex_count(&self, mesh: &Mesh) -> usize {
+        self.attributes.first().map_or(0, |attribute| {
+            (attribute.source)(mesh).len() / attribute.components as usize
+        })
+    }
+
+    // Packs the attribute streams of the mesh into one buffer, vertex by vertex
+    pub fn interleave(&self, mesh: &Mesh) -> Vec<u8> {
+        self.interleave_range(mesh, 0..self.vertex_count(mesh))
+    }
+
+    pub fn interleave_range(&self, mesh: &Mesh, vertices: std::ops::Range<usize>) -> Vec<u8> {
+        let vertex_count = self.vertex_count(mesh);
+        for attribute in self.attributes {
+            let len = (attribute.source)(mesh).len();
+            assert_eq!(
+                len,
+                vertex_count * attribute.components as usize,
+                "Mesh has {} values for {}, expected {} components for {} vertices",
+                len,
+                attribute.name,
+                attribute.components,
+                vertex_count
+            );
+        }
+
+        let mut data = Vec::with_capacity(vertices.len() * self.stride());
+        for vertex in vertices {
+            for attribute in self.attributes {
+                let start = data.len();
+                let n = attribute.components as usize;
+                for &value in &(attribute.source)(mesh)[vertex * n..vertex * n + n] {
+                    attribute.write_component(value, &mut data);
+                }
+                data.resize(start + attribute.size(), 0);
+            }
+        }
+        data
+    }
+
+    // Attributes read from binding point 0, so the buffer or the offset into it
+    // can be swapped with `bind_vertex_buffer` without describing them again
+    pub unsafe fn enable_attributes(&self, vbo: &Buffer) {
+        for attribute in self.attributes {
+            gl::VertexAttribFormat(
+                attribute.location,
+                attribute.components,
+                attribute.gl_type,
+                if attribute.normalized {
+                    gl::TRUE
+                } else {
+                    gl::FALSE
+                },
+                self.offset_of(attribute.location) as u32,
+            );
+            gl::VertexAttribBinding(attribute.location, 0);
+            gl::EnableVertexAttribArray(attribute.location);
+        }
+        self.bind_vertex_buffer(vbo, 0);
+    }
+
+    pub unsafe fn bind_vertex_buffer(&self, vbo: &Buffer, offset: isize) {
+        gl::BindVertexBuffer(0, vbo.id(), offset, self.stride() as i32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mesh::MeshMaterial;
+
+    use super::*;
+
+    fn triangle() -> Mesh {
+        Mesh {
+            vertices: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            indices: vec![0, 1, 2],
+            normals: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            material: MeshMaterial {
+                ambient: vec![0.1; 9],
+                diffuse: vec![0.2; 9],
+                specular: vec![0.3; 9],
+                shininess: vec![32.0, 16.0, 8.0],
+                roughness: vec![0.5; 3],
+                metallic: vec![0.0, 0.5, 1.0],
+                shading: vec![0.0; 3],
+            },
+            index_count: 3,
+            dirty: None,
+        }
+    }
+
+    fn read_f32(data: &[u8], offset: usize) -> f32 {
+        f32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn mesh_format_is_tightly_packed() {
+        assert_eq!(MESH_VERTEX_FORMAT.stride(), 19 * 4);
+        assert_eq!(MESH_VERTEX_FORMAT.offset_of(4), 12 * 4);
+        assert_eq!(MESH_VERTEX_FORMAT.offset_of(5), 13 * 4);
+        assert_eq!(MESH_VERTEX_FORMAT.offset_of(7), 17 * 4);
+        assert_eq!(STAR_VERTEX_FORMAT.stride(), 6 * 4);
+        assert_eq!(STAR_VERTEX_FORMAT.offset_of(2), 3 * 4);
+    }
+
+    #[test]
+    fn interleaves_vertex_by_vertex() {
+        let mesh = triangle();
+        let data = MESH_VERTEX_FORMAT.interleave(&mesh);
+        let stride = MESH_VERTEX_FORMAT.stride();
+
+        assert_eq!(data.len(), 3 * stride);
+        assert_eq!(read_f32(&data, stride), 1.0);
+        assert_eq!(read_f32(&data, 2 * stride + 4), 1.0);
+        assert_eq!(
+            read_f32(&data, stride + MESH_VERTEX_FORMAT.offset_of(4)),
+            16.0
+        );
+        assert_eq!(
+            read_f32(&data, 2 * stride + MESH_VERTEX_FORMAT.offset_of(5) + 8),
+            1.0
+        );
+        assert_eq!(
+            read_f32(&data, stride + MESH_VERTEX_FORMAT.offset_of(7)),
+            0.5
+        );
+    }
+
+    #[test]
+    fn packs_and_pads_normalized_bytes() {
+        const FORMAT: VertexFormat = VertexFormat {
+            attributes: &[
+                VertexAttribute {
+                    name: "color",
+                    location: 0,
+                    components: 3,
+                    gl_type: gl::UNSIGNED_BYTE,
+                    normalized: true,
+                    source: |mesh| &mesh.material.diffuse,
+                },
+                VertexAttribute {
+                    name: "shininess",
+                    location: 1,
+                    components: 1,
+                    gl_type: gl::FLOAT,
+                    normalized: false,
+                    source: |mesh| &mesh.material.shininess,
+                },
+            ],
+        };
+        let data = FORMAT.interleave(&triangle());
+
+        assert_eq!(FORMAT.vertex_count(&triangle()), 3);
+        assert_eq!(FORMAT.stride(), 8);
+        assert_eq!(data[..4], [51, 51, 51, 0]);
+        assert_eq!(read_f32(&data, 12), 16.0);
+    }
+}